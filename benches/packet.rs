@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dns_demo::message::byte_packet_buffer::BytePacketBuffer;
+use dns_demo::message::header::{QRFlag, RDFlag};
+use dns_demo::message::records::{DNSAAAARecord, DNSARecord, DNSMXRecord, DNSNSRecord, DNSRecord};
+use dns_demo::message::{DNSPacket, DNSQuestion, ParseMode, QRClass, QRType};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// A referral-sized response: a few answers, an authority section and glue
+fn sample_response() -> DNSPacket {
+    let mut packet = DNSPacket::new();
+    packet.header.id = 4242;
+    packet.header.qr = QRFlag::Response;
+    packet.header.rd = RDFlag::Desired;
+    packet.question.questions.push(DNSQuestion::new("www.example.com".to_string(), QRType::A, QRClass::IN));
+    for i in 1..=4 {
+        let record = DNSARecord::new("www.example.com".to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, i));
+        packet.answer.answers.push(DNSRecord::A(record));
+    }
+    let mx = DNSMXRecord::new("example.com".to_string(), QRClass::IN, 3600, 10, "mail.example.com".to_string());
+    packet.answer.answers.push(DNSRecord::MX(mx));
+    for ns in ["a.iana-servers.net", "b.iana-servers.net"] {
+        let record = DNSNSRecord::new("example.com".to_string(), QRClass::IN, 86400, ns.to_string());
+        packet.authority.records.push(DNSRecord::NS(record));
+        let glue = DNSARecord::new(ns.to_string(), QRClass::IN, 86400, Ipv4Addr::new(199, 43, 135, 53));
+        packet.additional.records.push(DNSRecord::A(glue));
+        let glue = DNSAAAARecord::new(ns.to_string(), QRClass::IN, 86400, Ipv6Addr::new(0x2001, 0x500, 0x8f, 0, 0, 0, 0, 0x53));
+        packet.additional.records.push(DNSRecord::AAAA(glue));
+    }
+    packet
+}
+
+fn bench_packet(c: &mut Criterion) {
+    let mut packet = sample_response();
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    let wire = buffer.buf[..buffer.pos()].to_vec();
+
+    c.bench_function("parse response", |b| {
+        b.iter(|| DNSPacket::from_slice(black_box(&wire), ParseMode::Lenient).unwrap())
+    });
+    c.bench_function("serialize response", |b| {
+        b.iter(|| {
+            let mut buffer = BytePacketBuffer::new();
+            black_box(&mut packet).write(&mut buffer).unwrap();
+            buffer.pos()
+        })
+    });
+}
+
+criterion_group!(benches, bench_packet);
+criterion_main!(benches);
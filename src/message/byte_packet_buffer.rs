@@ -1,22 +1,76 @@
+// The most octets a domain name may take up on the wire, length bytes and
+// the terminating zero included (RFC 1035 section 2.3.4)
+const MAX_NAME_LEN: usize = 255;
+
 pub struct BytePacketBuffer {
-    pub buf: [u8; 512],
+    pub buf: Vec<u8>,
     pub pos: usize,
+    message_len: Option<usize>, // Length of the message received into `buf`, if known
+}
+
+impl Default for BytePacketBuffer {
+    fn default() -> Self {
+        BytePacketBuffer::new()
+    }
 }
 
 impl BytePacketBuffer {
     /// This gives us a fresh buffer for holding the packet contents, and a
-    /// field for keeping track of where we are.
+    /// field for keeping track of where we are. It holds the 512 bytes a
+    /// plain UDP message is limited to.
     pub fn new() -> BytePacketBuffer {
+        BytePacketBuffer::with_size(512)
+    }
+
+    /// A fresh buffer holding up to `size` bytes, for EDNS or TCP messages
+    /// larger than 512 bytes
+    pub fn with_size(size: usize) -> BytePacketBuffer {
         BytePacketBuffer {
-            buf: [0; 512],
+            buf: vec![0; size],
             pos: 0,
+            message_len: None,
         }
     }
 
+    /// Mark the first `len` bytes as the message, e.g. after receiving a
+    /// datagram of that length into `buf`. Reading then stops at the end of
+    /// the message rather than running on into the rest of the buffer, so
+    /// section counts are checked against what was actually received.
+    pub fn set_message_len(&mut self, len: usize) {
+        self.message_len = Some(len);
+    }
+
+    // Where reading stops: the end of the message if its length was set,
+    // otherwise the end of the buffer
+    fn end(&self) -> usize {
+        self.message_len.map_or(self.buf.len(), |len| len.min(self.buf.len()))
+    }
+
+    /// Rewind to the start and zero the contents, leaving the buffer as a
+    /// fresh one of the same size without allocating again. A loop handling
+    /// one message after another can keep a single buffer and reset it
+    /// before each, as `DNSResolver::run` does.
+    pub fn reset(&mut self) {
+        self.buf.fill(0);
+        self.pos = 0;
+        self.message_len = None;
+    }
+
     /// Get the current position within buffer
     pub fn pos(&self) -> usize {
         self.pos
     }
+
+    /// Number of bytes between the current position and the end of the
+    /// message, or of the buffer if the message length isn't known
+    pub fn remaining(&self) -> usize {
+        self.end().saturating_sub(self.pos)
+    }
+
+    /// Whether the position has reached the end of the buffer
+    pub fn is_eof(&self) -> bool {
+        self.remaining() == 0
+    }
     
     /// Step the buffer position forward a specific number of steps
     pub fn step(&mut self, steps: usize) -> Result<(),std::io::Error> {
@@ -32,20 +86,9 @@ impl BytePacketBuffer {
         Ok(())
     }
     
-    // Read the current position and step forward once
-    fn read(&mut self) -> Result<u8,std::io::Error>{
-        if self.pos >= 512 {
-            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "End of buffer"));
-        }
-        let res = self.buf[self.pos];
-        self.pos += 1;
-
-        Ok(res)
-    }
-    
     /// Read a single byte and move the position one step forward
     pub fn read_u8(&mut self) -> Result<u8,std::io::Error> {
-        if self.pos >= 512 {
+        if self.pos >= self.end() {
             return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "End of buffer"));
         }
         let res = self.buf[self.pos];
@@ -55,7 +98,7 @@ impl BytePacketBuffer {
     
     /// Get a single byte, without changing the buffer position
     pub fn get_byte(&mut self, pos: usize) -> Result<u8,std::io::Error> {
-        if pos >= 512 {
+        if pos >= self.end() {
             return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "End of buffer"));
         }
         Ok(self.buf[pos])
@@ -63,55 +106,51 @@ impl BytePacketBuffer {
 
     /// Get a range of bytes
     pub fn get_byte_range(&mut self, start: usize, len: usize) -> Result<&[u8],std::io::Error> {
-        if start + len >= 512 {
+        if start + len > self.end() {
             return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "End of buffer"));
         }
-        Ok(&self.buf[start..start + len as usize])
+        Ok(&self.buf[start..start + len])
     }
 
-    /// Read two bytes, stepping two steps forward
-    pub fn read_u16(&mut self) -> Result<u16,std::io::Error> {
-        let res = ((self.read()? as u16) << 8) | (self.read()? as u16);
+    // Read N bytes at once and step past them, with a single bounds check
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N],std::io::Error> {
+        let bytes = self.buf[0..self.end()].get(self.pos..self.pos + N)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "End of buffer"))?;
+        let mut res = [0; N];
+        res.copy_from_slice(bytes);
+        self.pos += N;
 
         Ok(res)
     }
 
+    /// Read two bytes, stepping two steps forward
+    pub fn read_u16(&mut self) -> Result<u16,std::io::Error> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
     /// Read four bytes, stepping four steps forward
     pub fn read_u32(&mut self) -> Result<u32,std::io::Error> {
-        let res = ((self.read()? as u32) << 24)
-            | ((self.read()? as u32) << 16)
-            | ((self.read()? as u32) << 8)
-            | ((self.read()? as u32) << 0);
-
-        Ok(res)
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+    /// Read six bytes, stepping six steps forward, such as TSIG's 48-bit
+    /// time signed
+    pub fn read_u48(&mut self) -> Result<u64,std::io::Error> {
+        let bytes: [u8; 6] = self.read_array()?;
+        let mut res = [0; 8];
+        res[2..].copy_from_slice(&bytes);
+
+        Ok(u64::from_be_bytes(res))
     }
     /// Read sixteen bytes, stepping sixteen steps forward
     pub fn read_u128(&mut self) -> Result<u128, std::io::Error> {
-        let res = ((self.read()? as u128) << 120)
-            | ((self.read()? as u128) << 112)
-            | ((self.read()? as u128) << 104)
-            | ((self.read()? as u128) << 96)
-            | ((self.read()? as u128) << 88)
-            | ((self.read()? as u128) << 80)
-            | ((self.read()? as u128) << 72)
-            | ((self.read()? as u128) << 64)
-            | ((self.read()? as u128) << 56)
-            | ((self.read()? as u128) << 48)
-            | ((self.read()? as u128) << 40)
-            | ((self.read()? as u128) << 32)
-            | ((self.read()? as u128) << 24)
-            | ((self.read()? as u128) << 16)
-            | ((self.read()? as u128) << 8)
-            | ((self.read()? as u128) << 0);
-    
-        Ok(res)
+        Ok(u128::from_be_bytes(self.read_array()?))
     }
 
     /// Read a qname
     ///
     /// The tricky part: Reading domain names, taking labels into consideration.
     /// Will take something like [3]www[6]google[3]com[0] and append
-    /// www.google.com to outstr.
+    /// www.google.com to outstr. The root name, a lone [0], appends nothing.
     pub fn read_qname(&mut self, outstr: &mut String) -> Result<(),std::io::Error> {
         // Since we might encounter jumps, we'll keep track of our position
         // locally as opposed to using the position within the struct. This
@@ -122,21 +161,18 @@ impl BytePacketBuffer {
 
         // track whether or not we've jumped
         let mut jumped = false;
-        let max_jumps = 5;
-        let mut jumps_performed = 0;
+
+        // Offsets we've already jumped to while reading this name
+        let mut visited: Vec<usize> = Vec::new();
+
+        // Octets of the name read so far, counting the terminating zero
+        let mut name_len = 1;
 
         // Our delimiter which we append for each label. Since we don't want a
         // dot at the beginning of the domain name we'll leave it empty for now
         // and set it to "." at the end of the first iteration.
         let mut delim = "";
         loop {
-            // Dns Packets are untrusted data, so we need to be paranoid. Someone
-            // can craft a packet with a cycle in the jump instructions. This guards
-            // against such packets.
-            if jumps_performed > max_jumps {
-                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Limit of {} jumps exceeded", max_jumps)));
-            }
-
             // At this point, we're always at the beginning of a label. Recall
             // that labels start with a length byte.
             let len = self.get_byte(pos)?;
@@ -153,12 +189,19 @@ impl BytePacketBuffer {
                 // Read another byte, calculate offset and perform the jump by
                 // updating our local position variable
                 let b2 = self.get_byte(pos + 1)? as u16;
-                let offset = (((len as u16) ^ 0xC0) << 8) | b2;
-                pos = offset as usize;
+                let offset = ((((len as u16) ^ 0xC0) << 8) | b2) as usize;
+
+                // Dns Packets are untrusted data, so we need to be paranoid. Someone
+                // can craft a packet with a cycle in the jump instructions. Landing on
+                // an offset we've already visited means we're in such a cycle.
+                if visited.contains(&offset) {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Compression pointer loop at offset {}", offset)));
+                }
+                visited.push(offset);
+                pos = offset;
 
                 // Indicate that a jump was performed.
                 jumped = true;
-                jumps_performed += 1;
 
                 continue;
             }
@@ -174,6 +217,13 @@ impl BytePacketBuffer {
                     break;
                 }
 
+                // However the labels are spread across compression pointers,
+                // the whole name can't be longer than a name may be.
+                name_len += len as usize + 1;
+                if name_len > MAX_NAME_LEN {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Name exceeds {} octets", MAX_NAME_LEN)));
+                }
+
                 // Append the delimiter to our output buffer first.
                 outstr.push_str(delim);
 
@@ -196,9 +246,19 @@ impl BytePacketBuffer {
         Ok(())
     }
 
+    /// Read a character-string: a length byte followed by up to 255 bytes
+    pub fn read_character_string(&mut self) -> Result<String,std::io::Error> {
+        let len = self.read_u8()? as usize;
+        let str_buffer = self.get_byte_range(self.pos, len)?;
+        let res = String::from_utf8_lossy(str_buffer).to_string();
+        self.step(len)?;
+
+        Ok(res)
+    }
+
     /// Write a single byte and move the position one step forward
     fn write(&mut self, val: u8) -> Result<(),std::io::Error> {
-        if self.pos >= 512 {
+        if self.pos >= self.buf.len() {
             return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "End of buffer"));
         }
         self.buf[self.pos] = val;
@@ -213,41 +273,50 @@ impl BytePacketBuffer {
         Ok(())
     }
 
-    /// Write two bytes and move the position two step forward
-    pub fn write_u16(&mut self, val: u16) -> Result<(),std::io::Error> {
-        self.write((val >> 8) as u8)?;
-        self.write((val & 0xFF) as u8)?;
+    /// Write a run of bytes and move the position past them, with a single
+    /// bounds check rather than one per byte
+    pub fn write_bytes(&mut self, val: &[u8]) -> Result<(),std::io::Error> {
+        let dest = self.buf.get_mut(self.pos..self.pos + val.len())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "End of buffer"))?;
+        dest.copy_from_slice(val);
+        self.pos += val.len();
 
         Ok(())
     }
 
     /// Write two bytes and move the position two step forward
+    pub fn write_u16(&mut self, val: u16) -> Result<(),std::io::Error> {
+        self.write_bytes(&val.to_be_bytes())
+    }
+
+    /// Write four bytes and move the position four steps forward
     pub fn write_u32(&mut self, val: u32) -> Result<(),std::io::Error> {
-        self.write(((val >> 24) & 0xFF) as u8)?;
-        self.write(((val >> 16) & 0xFF) as u8)?;
-        self.write(((val >> 8) & 0xFF) as u8)?;
-        self.write((val & 0xFF) as u8)?;
-        Ok(())
+        self.write_bytes(&val.to_be_bytes())
+    }
+
+    /// Write the low six bytes of `val` and move the position six steps
+    /// forward; values that don't fit in 48 bits are an error
+    pub fn write_u48(&mut self, val: u64) -> Result<(),std::io::Error> {
+        if val >> 48 != 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Value exceeds 48 bits"));
+        }
+        self.write_bytes(&val.to_be_bytes()[2..])
     }
 
     /// Write sixteen bytes and move the position sixteen steps forward
     pub fn write_u128(&mut self, val: u128) -> Result<(), std::io::Error> {
-        self.write(((val >> 120) & 0xFF) as u8)?;
-        self.write(((val >> 112) & 0xFF) as u8)?;
-        self.write(((val >> 104) & 0xFF) as u8)?;
-        self.write(((val >> 96) & 0xFF) as u8)?;
-        self.write(((val >> 88) & 0xFF) as u8)?;
-        self.write(((val >> 80) & 0xFF) as u8)?;
-        self.write(((val >> 72) & 0xFF) as u8)?;
-        self.write(((val >> 64) & 0xFF) as u8)?;
-        self.write(((val >> 56) & 0xFF) as u8)?;
-        self.write(((val >> 48) & 0xFF) as u8)?;
-        self.write(((val >> 40) & 0xFF) as u8)?;
-        self.write(((val >> 32) & 0xFF) as u8)?;
-        self.write(((val >> 24) & 0xFF) as u8)?;
-        self.write(((val >> 16) & 0xFF) as u8)?;
-        self.write(((val >> 8) & 0xFF) as u8)?;
-        self.write((val & 0xFF) as u8)?;
+        self.write_bytes(&val.to_be_bytes())
+    }
+
+    /// Write a character-string: a length byte followed by the bytes
+    pub fn write_character_string(&mut self, val: &str) -> Result<(),std::io::Error> {
+        let len = val.len();
+        if len > 0xFF {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Character-string exceeds 255 bytes of length"));
+        }
+
+        self.write_u8(len as u8)?;
+        self.write_bytes(val.as_bytes())?;
 
         Ok(())
     }
@@ -256,22 +325,177 @@ impl BytePacketBuffer {
     ///
     /// The tricky part: Reading domain names, taking labels into consideration.
     /// Will take something like www.google.com and append
-    /// [3]www[6]google[3]com[0] to outstr.
+    /// [3]www[6]google[3]com[0] to outstr. Empty labels are skipped, so a
+    /// trailing dot makes no difference and the root name, "" or ".", is
+    /// written as a lone [0].
     pub fn write_qname(&mut self, qname: &str) -> Result<(),std::io::Error> {
-        for label in qname.split('.') {
+        let labels = qname.split('.').filter(|label| !label.is_empty());
+        if labels.clone().map(|label| label.len() + 1).sum::<usize>() + 1 > MAX_NAME_LEN {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Name exceeds {} octets", MAX_NAME_LEN)));
+        }
+
+        for label in labels {
             let len = label.len();
             if len > 0x3f {
-                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Single label exceeds 63 characters of length"));
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Single label exceeds 63 characters of length"));
             }
 
             self.write_u8(len as u8)?;
-            for b in label.as_bytes() {
-                self.write_u8(*b)?;
-            }
+            self.write_bytes(label.as_bytes())?;
         }
 
         self.write_u8(0)?;
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Write RDATA preceded by its two-byte length.
+    ///
+    /// A placeholder length is written, then `write_rdata` runs, then the
+    /// placeholder is backfilled with the number of bytes it wrote, which is
+    /// an error if more than 65535. The position is left just after the RDATA, so a writer can never leave the
+    /// cursor mid-record or the length disagreeing with the data.
+    pub fn write_rdata_with_len<F>(&mut self, write_rdata: F) -> Result<(),std::io::Error>
+    where
+        F: FnOnce(&mut BytePacketBuffer) -> Result<(),std::io::Error>,
+    {
+        let len_pos = self.pos();
+        self.write_u16(0)?; // Placeholder for length
+
+        let start_pos = self.pos();
+        write_rdata(self)?;
+        let end_pos = self.pos();
+        let rdlength = checked_rdlength(&[end_pos - start_pos])?;
+        self.seek(len_pos)?;
+        self.write_u16(rdlength)?;
+        self.seek(end_pos)?;
+
+        Ok(())
+    }
+}
+
+/// The RDLENGTH of RDATA made up of parts of the given lengths, or an
+/// `InvalidData` error if the total does not fit in 16 bits. Writers of
+/// variable-length records check their parts with this before writing.
+pub fn checked_rdlength(parts: &[usize]) -> Result<u16,std::io::Error> {
+    parts
+        .iter()
+        .try_fold(0usize, |total, len| total.checked_add(*len))
+        .and_then(|total| u16::try_from(total).ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "RDATA exceeds 65535 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::records::{DNSARecord, DNSRecord};
+    use crate::message::{DNSPacket, DNSQuestion, ParseMode, QRClass, QRType};
+
+    fn sample_packet() -> DNSPacket {
+        let mut packet = DNSPacket::new();
+        packet.header.id = 1234;
+        packet.question.add_question(DNSQuestion::new("example.com".to_string(), QRType::A, QRClass::IN));
+        packet.answer.add_answer(DNSRecord::A(DNSARecord::new("example.com".to_string(), QRClass::IN, 300, "192.0.2.1".parse().unwrap())));
+        packet
+    }
+
+    #[test]
+    fn reset_buffer_round_trips_like_a_fresh_one() {
+        let mut fresh = BytePacketBuffer::new();
+        sample_packet().write(&mut fresh).unwrap();
+
+        // Leave a longer message behind first, so any leftover bytes or
+        // position would show up
+        let mut reused = BytePacketBuffer::new();
+        let mut other = sample_packet();
+        other.question.add_question(DNSQuestion::new("a-much-longer-name.example.org".to_string(), QRType::AAAA, QRClass::IN));
+        other.write(&mut reused).unwrap();
+        reused.reset();
+        assert_eq!(reused.pos(), 0);
+        sample_packet().write(&mut reused).unwrap();
+
+        assert_eq!(reused.pos, fresh.pos);
+        assert_eq!(reused.buf, fresh.buf);
+
+        reused.seek(0).unwrap();
+        let parsed = DNSPacket::from_buffer_with_mode(&mut reused, ParseMode::Strict).unwrap();
+        assert_eq!(parsed.header.id, 1234);
+        assert_eq!(parsed.question.questions[0].qname, "example.com");
+        assert_eq!(parsed.all_a(), vec!["192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap()]);
+    }
+
+    // A buffer holding `bytes` and nothing else
+    fn buffer_of(bytes: &[u8]) -> BytePacketBuffer {
+        let mut buffer = BytePacketBuffer::with_size(bytes.len());
+        buffer.buf.copy_from_slice(bytes);
+        buffer
+    }
+
+    #[test]
+    fn compression_pointer_loop_is_invalid() {
+        // A at offset 0 points to B at offset 2, which points back to A
+        let mut buffer = buffer_of(&[0xC0, 0x02, 0xC0, 0x00]);
+        let error = buffer.read_qname(&mut String::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("loop"));
+    }
+
+    #[test]
+    fn names_longer_than_255_octets_are_rejected() {
+        // Four 63 octet labels take 4 * 64 + 1 = 257 octets
+        let label = "a".repeat(63);
+        let long = [label.as_str(); 4].join(".");
+        let error = BytePacketBuffer::new().write_qname(&long).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        // Three of them and one of 61 octets make exactly 255, the most allowed
+        let longest = format!("{}.{}", [label.as_str(); 3].join("."), "b".repeat(61));
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_qname(&longest).unwrap();
+        assert_eq!(buffer.pos(), MAX_NAME_LEN);
+        buffer.seek(0).unwrap();
+        let mut name = String::new();
+        buffer.read_qname(&mut name).unwrap();
+        assert_eq!(name, longest);
+
+        // On the wire, a name spread across a pointer counts as a whole: the
+        // 63 octet label at offset 0 is followed by a pointer to the 255
+        // octet name written after it
+        let mut bytes = vec![63];
+        bytes.extend_from_slice(label.as_bytes());
+        bytes.extend_from_slice(&[0xC0, 66]);
+        bytes.extend_from_slice(&buffer.buf[0..MAX_NAME_LEN]);
+        let error = buffer_of(&bytes).read_qname(&mut String::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn root_name_is_a_lone_zero_byte() {
+        for root in ["", "."] {
+            let mut buffer = BytePacketBuffer::new();
+            buffer.write_qname(root).unwrap();
+            assert_eq!(&buffer.buf[0..buffer.pos()], &[0]);
+        }
+
+        let mut name = String::new();
+        let mut buffer = buffer_of(&[0]);
+        buffer.read_qname(&mut name).unwrap();
+        assert_eq!(name, "");
+        assert_eq!(buffer.pos(), 1);
+    }
+
+    #[test]
+    fn trailing_dot_encodes_like_the_bare_name() {
+        let mut bare = BytePacketBuffer::new();
+        bare.write_qname("example.com").unwrap();
+        let mut fqdn = BytePacketBuffer::new();
+        fqdn.write_qname("example.com.").unwrap();
+        assert_eq!(&fqdn.buf[0..fqdn.pos()], b"\x07example\x03com\x00");
+        assert_eq!(&fqdn.buf[0..fqdn.pos()], &bare.buf[0..bare.pos()]);
+
+        fqdn.seek(0).unwrap();
+        let mut name = String::new();
+        fqdn.read_qname(&mut name).unwrap();
+        assert_eq!(name, "example.com");
+    }
+}
@@ -0,0 +1,248 @@
+use ring::digest;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use crate::message::byte_packet_buffer::BytePacketBuffer;
+use crate::message::records::{DNSDNSKEYRecord, DNSDSRecord, DNSRRSIGRecord, DNSRecord};
+use crate::message::QRClass;
+
+/// RSA/SHA-256 (RFC 5702)
+pub const ALGORITHM_RSASHA256: u8 = 8;
+/// ECDSA Curve P-256 with SHA-256 (RFC 6605)
+pub const ALGORITHM_ECDSAP256SHA256: u8 = 13;
+
+// DNSKEY flag marking a key that may sign zone data
+const FLAG_ZONE_KEY: u16 = 0x0100;
+
+/// Check that `rrsig` is a valid signature over `rrset` made with `key`.
+///
+/// The RRset is put in canonical form (RFC 4034 section 6): owner and
+/// embedded names lowercased, the original TTL restored, wildcard owners
+/// reduced to the signed `*` name and records sorted by RDATA. The signed
+/// data is then rebuilt from the RRSIG's fields and the RRset and checked
+/// against the signature.
+///
+/// Returns `Ok(false)` if the key isn't the one that made the signature or
+/// the signature doesn't match, and an error for an unsupported algorithm or
+/// malformed key. The validity period isn't checked here.
+pub fn verify_rrsig(rrset: &[DNSRecord], rrsig: &DNSRRSIGRecord, key: &DNSDNSKEYRecord) -> Result<bool,std::io::Error> {
+    if key.protocol != 3
+        || key.flags & FLAG_ZONE_KEY == 0
+        || key.algorithm != rrsig.algorithm
+        || key.key_tag() != rrsig.key_tag
+        || canonical_name(&key.preamble.name) != canonical_name(&rrsig.signer_name)
+    {
+        return Ok(false);
+    }
+
+    let data = match signed_data(rrset, rrsig)? {
+        Some(data) => data,
+        None => return Ok(false),
+    };
+
+    match rrsig.algorithm {
+        ALGORITHM_RSASHA256 => {
+            let (exponent, modulus) = split_rsa_key(&key.public_key)?;
+            let public_key = RsaPublicKeyComponents { n: modulus, e: exponent };
+            Ok(public_key.verify(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, &data, &rrsig.signature).is_ok())
+        }
+        ALGORITHM_ECDSAP256SHA256 => {
+            if key.public_key.len() != 64 {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "ECDSA P-256 key must be 64 bytes"));
+            }
+            // ring takes the uncompressed point form, which DNSKEY omits the prefix of
+            let mut point = vec![0x04];
+            point.extend_from_slice(&key.public_key);
+            let public_key = UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point);
+            Ok(public_key.verify(&data, &rrsig.signature).is_ok())
+        }
+        other => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("Unsupported DNSSEC algorithm {}", other))),
+    }
+}
+
+/// Whether `ds` is the digest of `key` (RFC 4034 section 5.1.4), for the
+/// SHA-1, SHA-256 and SHA-384 digest types
+pub fn ds_matches_key(ds: &DNSDSRecord, key: &DNSDNSKEYRecord) -> Result<bool,std::io::Error> {
+    if ds.key_tag != key.key_tag() || ds.algorithm != key.algorithm {
+        return Ok(false);
+    }
+    let algorithm = match ds.digest_type {
+        1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        2 => &digest::SHA256,
+        4 => &digest::SHA384,
+        other => return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("Unsupported DS digest type {}", other))),
+    };
+
+    let key_record = DNSRecord::DNSKEY(key.clone());
+    let data = encode(|buffer| {
+        buffer.write_qname(&canonical_name(&key.preamble.name))?;
+        buffer.write_bytes(&canonical_rdata(&key_record)?)
+    })?;
+    Ok(digest::digest(algorithm, &data).as_ref() == ds.digest.as_slice())
+}
+
+/// Whether the current time, in seconds since the epoch, falls within the
+/// signature's validity period
+pub fn rrsig_is_current(rrsig: &DNSRRSIGRecord, now: u32) -> bool {
+    rrsig.inception <= now && now <= rrsig.expiration
+}
+
+/// The data an RRSIG signs: its own RDATA without the signature, followed
+/// by the RRset in canonical form. `None` if the records don't form the
+/// RRset the RRSIG covers.
+pub fn signed_data(rrset: &[DNSRecord], rrsig: &DNSRRSIGRecord) -> Result<Option<Vec<u8>>,std::io::Error> {
+    let first = match rrset.first() {
+        Some(first) => first.preamble(),
+        None => return Ok(None),
+    };
+    let owner = canonical_name(&first.name);
+    let class = first.class;
+    if rrset.iter().any(|record| {
+        let preamble = record.preamble();
+        preamble.rtype != rrsig.type_covered || preamble.class != class || canonical_name(&preamble.name) != owner
+    }) {
+        return Ok(None);
+    }
+
+    // A name with more labels than were signed was expanded from a wildcard
+    let labels: Vec<&str> = owner.split('.').filter(|label| !label.is_empty()).collect();
+    let signed_owner = match labels.len().cmp(&(rrsig.labels as usize)) {
+        std::cmp::Ordering::Less => return Ok(None),
+        std::cmp::Ordering::Equal => owner.clone(),
+        std::cmp::Ordering::Greater => {
+            let suffix = labels[labels.len() - rrsig.labels as usize..].join(".");
+            if suffix.is_empty() { "*".to_string() } else { format!("*.{}", suffix) }
+        }
+    };
+
+    let mut rdatas: Vec<Vec<u8>> = rrset.iter().map(canonical_rdata).collect::<Result<_,_>>()?;
+    rdatas.sort();
+    rdatas.dedup();
+
+    let mut signer = rrsig.clone();
+    signer.signer_name = canonical_name(&rrsig.signer_name);
+    let mut data = encode(|buffer| signer.write_signed_fields(buffer))?;
+    for rdata in rdatas {
+        data.extend(encode(|buffer| {
+            buffer.write_qname(&signed_owner)?;
+            buffer.write_u16(rrsig.type_covered.to_u16())?;
+            buffer.write_u16(QRClass::to_u16(&class))?;
+            buffer.write_u32(rrsig.original_ttl)?;
+            buffer.write_u16(rdata.len() as u16)
+        })?);
+        data.extend(rdata);
+    }
+
+    Ok(Some(data))
+}
+
+// Lowercase a name and drop any trailing dot
+fn canonical_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// The RDATA of a record with its embedded domain names lowercased, for the
+// types RFC 4034 section 6.2 (as updated by RFC 6840) lists
+fn canonical_rdata(record: &DNSRecord) -> Result<Vec<u8>,std::io::Error> {
+    let mut record = record.clone();
+    match &mut record {
+        DNSRecord::NS(ns) => ns.rdata = canonical_name(&ns.rdata),
+        DNSRecord::CNAME(cname) => cname.rdata = canonical_name(&cname.rdata),
+        DNSRecord::PTR(ptr) => ptr.ptrdname = canonical_name(&ptr.ptrdname),
+        DNSRecord::MX(mx) => mx.exchange = canonical_name(&mx.exchange),
+        DNSRecord::RT(rt) => rt.intermediate_host = canonical_name(&rt.intermediate_host),
+        DNSRecord::SRV(srv) => srv.target = canonical_name(&srv.target),
+        DNSRecord::SOA(soa) => {
+            soa.mname = canonical_name(&soa.mname);
+            soa.rname = canonical_name(&soa.rname);
+        }
+        DNSRecord::RRSIG(rrsig) => rrsig.signer_name = canonical_name(&rrsig.signer_name),
+        _ => {}
+    }
+
+    // Written under the root name, the RDATA starts after the one-byte
+    // owner and the type, class, TTL and RDLENGTH fields
+    record.preamble_mut().name = String::new();
+    let encoded = encode(|buffer| record.write(buffer))?;
+    Ok(encoded[11..].to_vec())
+}
+
+// Run a writer against a buffer large enough for any record and return the
+// bytes it wrote
+fn encode<F>(write: F) -> Result<Vec<u8>,std::io::Error>
+where
+    F: FnOnce(&mut BytePacketBuffer) -> Result<(),std::io::Error>,
+{
+    let mut buffer = BytePacketBuffer::with_size(u16::MAX as usize + 512);
+    write(&mut buffer)?;
+    buffer.buf.truncate(buffer.pos());
+    Ok(buffer.buf)
+}
+
+// Split an RSA DNSKEY into exponent and modulus (RFC 3110): a one-byte
+// exponent length, or zero followed by a two-byte length, then the exponent
+fn split_rsa_key(key: &[u8]) -> Result<(&[u8], &[u8]),std::io::Error> {
+    let malformed = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed RSA public key");
+    let (exponent_len, rest) = match key.first() {
+        Some(0) if key.len() >= 3 => (u16::from_be_bytes([key[1], key[2]]) as usize, &key[3..]),
+        Some(len) if *len != 0 => (*len as usize, &key[1..]),
+        _ => return Err(malformed()),
+    };
+    if rest.len() <= exponent_len {
+        return Err(malformed());
+    }
+    Ok(rest.split_at(exponent_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use crate::message::records::DNSARecord;
+    use crate::message::QRType;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // The ECDSA P-256 example of RFC 6605 section 6.1: the key of
+    // example.net, its DS and a signed A RRset of www.example.net
+    fn rfc_6605_example() -> (DNSDNSKEYRecord, Vec<DNSRecord>, DNSRRSIGRecord) {
+        let key = DNSDNSKEYRecord::new("example.net".to_string(), QRClass::IN, 3600, 257, 3, ALGORITHM_ECDSAP256SHA256, hex("1a88c88615d437fbb8bf9e1942a1929f28562706ae6c2bd399e7b1bfb6d1e9e75b92b4aa42917ae1c61b701ef035c3fe7be3009cbafe5a2f71316c902dcf0d00"));
+        let rrset = vec![DNSRecord::A(DNSARecord::new("www.example.net".to_string(), QRClass::IN, 3600, Ipv4Addr::new(192, 0, 2, 1)))];
+        // Valid from 20100812100439 to 20100909100439
+        let rrsig = DNSRRSIGRecord::new("www.example.net".to_string(), QRClass::IN, 3600, QRType::A, ALGORITHM_ECDSAP256SHA256, 3, 3600, 1284026679, 1281607479, 55648, "example.net".to_string(), hex("ab1eb02d8aa687e97da0229337aa8873e6f0eb26be289f28333d183f5d3b7a95c0c869adfb748daee3c5286eed6682c12e5533186baced9c26c167a9ebae950b"));
+        (key, rrset, rrsig)
+    }
+
+    #[test]
+    fn rfc_6605_signature_verifies() {
+        let (key, rrset, rrsig) = rfc_6605_example();
+        assert_eq!(key.key_tag(), 55648);
+        assert!(verify_rrsig(&rrset, &rrsig, &key).unwrap());
+
+        let ds = DNSDSRecord::new("example.net".to_string(), QRClass::IN, 3600, 55648, ALGORITHM_ECDSAP256SHA256, 2, hex("b4c8c1fe2e7477127b27115656ad6256f424625bf5c1e2770ce6d6e37df61d17"));
+        assert!(ds_matches_key(&ds, &key).unwrap());
+
+        assert!(rrsig_is_current(&rrsig, 1283000000));
+        assert!(!rrsig_is_current(&rrsig, 1284026680));
+    }
+
+    #[test]
+    fn rfc_6605_signature_fails_once_anything_signed_changes() {
+        // Names are compared in canonical form, so case doesn't matter
+        let (key, mut rrset, rrsig) = rfc_6605_example();
+        rrset[0].preamble_mut().name = "WWW.Example.NET.".to_string();
+        assert!(verify_rrsig(&rrset, &rrsig, &key).unwrap());
+
+        let (key, rrset, mut rrsig) = rfc_6605_example();
+        rrsig.signature[0] ^= 0x01;
+        assert!(!verify_rrsig(&rrset, &rrsig, &key).unwrap());
+
+        let (key, rrset, mut rrsig) = rfc_6605_example();
+        rrsig.original_ttl = 300;
+        assert!(!verify_rrsig(&rrset, &rrsig, &key).unwrap());
+
+        let (key, _, rrsig) = rfc_6605_example();
+        let rrset = vec![DNSRecord::A(DNSARecord::new("www.example.net".to_string(), QRClass::IN, 3600, Ipv4Addr::new(192, 0, 2, 2)))];
+        assert!(!verify_rrsig(&rrset, &rrsig, &key).unwrap());
+    }
+}
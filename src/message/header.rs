@@ -7,7 +7,7 @@ pub enum OpCode {
     Status,         // Server status request (STATUS)
     Notify,         // Notify (NOTIFY, RFC 1996)
     Update,         // Dynamic update (UPDATE, RFC 2136)
-    // Codes 6-15 are reserved for future use
+    Unassigned(u8), // Codes 3 and 6-15 are reserved for future use
 }
 
 impl OpCode {
@@ -18,6 +18,7 @@ impl OpCode {
             2 => Some(OpCode::Status),
             4 => Some(OpCode::Notify),
             5 => Some(OpCode::Update),
+            3 | 6..=15 => Some(OpCode::Unassigned(value)),
             _ => None,
         }
     }
@@ -28,6 +29,7 @@ impl OpCode {
             OpCode::Status => 2,
             OpCode::Notify => 4,
             OpCode::Update => 5,
+            OpCode::Unassigned(x) => *x,
         }
     }
 }
@@ -245,19 +247,23 @@ impl DNSHeaderSection {
         self.tc = TCFlag::from_u8(((a & (1 << 1)) > 0) as u8).unwrap();
         self.aa = AAFlag::from_u8(((a & (1 << 2)) > 0) as u8).unwrap();
 
-        // Directly extract the value for opcode, mask with 0x0F to get the correct value, then convert
+        // Directly extract the value for opcode, mask with 0x0F to get the correct value, then convert.
+        // Every 4-bit value maps to an opcode, so this can't fail
         self.opcode = OpCode::from_u8((a >> 3) & 0x0F).unwrap();
 
         // Convert boolean to u8, then use from_u8 for enum conversion
         self.qr = QRFlag::from_u8(((a & (1 << 7)) > 0) as u8).unwrap();
 
         // Directly extract the value for rcode, mask with 0x0F to get the correct value, then convert
-        self.rcode = RCode::from_u8(b & 0x0F).unwrap();
+        self.rcode = RCode::from_u8(b & 0x0F)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Reserved response code {}", b & 0x0F)))?;
 
         // Convert boolean to u8, then use from_u8 for enum conversion for remaining flags
         self.cd = CDFlag::from_u8(((b & (1 << 4)) > 0) as u8).unwrap();
         self.ad = ADFlag::from_u8(((b & (1 << 5)) > 0) as u8).unwrap();
-        self.z = ZFlag::from_u8(((b & (1 << 6)) > 0) as u8).unwrap();
+        // The z bit is reserved and must be ignored on receipt, so a peer
+        // setting it is not treated as an error
+        self.z = ZFlag::Unused;
         self.ra = RAFlag::from_u8(((b & (1 << 7)) > 0) as u8).unwrap();
 
         // Continue with buffer reading for counts
@@ -275,14 +281,14 @@ impl DNSHeaderSection {
                 | ((self.tc as u8) << 1)
                 | ((self.aa as u8) << 2)
                 | (OpCode::to_u8(&self.opcode) << 3)
-                | ((self.qr as u8) << 7) as u8,
+                | ((self.qr as u8) << 7),
         )?;
 
         buffer.write_u8(
             (self.rcode as u8)
                 | ((self.cd as u8) << 4)
                 | ((self.ad as u8) << 5)
-                | ((self.z as u8) << 6)
+                // Bit 6 is the reserved z bit, which is always written as zero
                 | ((self.ra as u8) << 7),
         )?;
 
@@ -293,4 +299,10 @@ impl DNSHeaderSection {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl Default for DNSHeaderSection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
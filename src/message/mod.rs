@@ -1,14 +1,20 @@
-pub(crate) mod header;
-mod records;
-pub(crate) mod byte_packet_buffer;
+pub mod header;
+pub mod records;
+pub mod byte_packet_buffer;
+#[cfg(feature = "dnssec")]
+pub mod dnssec;
+pub mod punycode;
+pub mod update;
 
 use byte_packet_buffer::BytePacketBuffer;
-use records::DNSRecord;
-use header::DNSHeaderSection;
-use std::net::Ipv4Addr;
+use records::{ClientSubnet, DNSRecord, DNSSOARecord};
+use header::{DNSHeaderSection, QRFlag, RAFlag, RCode};
+use crate::rng::Rng;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum QRType {
     UNKNOWN(u16),
     A,       // IPv4 address
@@ -21,6 +27,21 @@ pub enum QRType {
     AAAA,   // IPv6 address
     SRV,    // Service Record
     CAA,   // Certification Authority Authorization
+    CSYNC, // Child-to-Parent Synchronization
+    X25,   // X.25 PSDN address
+    ISDN,  // ISDN address
+    RT,    // Route Through
+    OPT,   // EDNS(0) options (pseudo-record)
+    SMIMEA, // S/MIME certificate association
+    DS,    // Delegation signer: digest of a child zone's key
+    RRSIG, // DNSSEC signature over an RRset
+    DNSKEY, // DNSSEC public key
+    TKEY,  // Transaction key establishment
+    IXFR,  // Incremental zone transfer (query only)
+    AXFR,  // Full zone transfer (query only)
+    MAILB, // Mailbox-related records (query only)
+    MAILA, // Mail agent records (query only)
+    ANY,   // All records (query only)
 }
 
 impl QRType {
@@ -36,6 +57,21 @@ impl QRType {
             QRType::AAAA => 28,   
             QRType::SRV => 33,    
             QRType::CAA => 257,
+            QRType::CSYNC => 62,
+            QRType::X25 => 19,
+            QRType::ISDN => 20,
+            QRType::RT => 21,
+            QRType::OPT => 41,
+            QRType::SMIMEA => 53,
+            QRType::DS => 43,
+            QRType::RRSIG => 46,
+            QRType::DNSKEY => 48,
+            QRType::TKEY => 249,
+            QRType::IXFR => 251,
+            QRType::AXFR => 252,
+            QRType::MAILB => 253,
+            QRType::MAILA => 254,
+            QRType::ANY => 255,
             QRType::UNKNOWN(x) => x
         }
     }
@@ -52,27 +88,93 @@ impl QRType {
             28 => QRType::AAAA,   
             33 => QRType::SRV,    
             257 => QRType::CAA,
+            62 => QRType::CSYNC,
+            19 => QRType::X25,
+            20 => QRType::ISDN,
+            21 => QRType::RT,
+            41 => QRType::OPT,
+            53 => QRType::SMIMEA,
+            43 => QRType::DS,
+            46 => QRType::RRSIG,
+            48 => QRType::DNSKEY,
+            249 => QRType::TKEY,
+            251 => QRType::IXFR,
+            252 => QRType::AXFR,
+            253 => QRType::MAILB,
+            254 => QRType::MAILA,
+            255 => QRType::ANY,
             _ => QRType::UNKNOWN(value)
         }
     }
+
+    /// Whether this is a pseudo-record type (OPT, TKEY, TSIG) carrying
+    /// transaction metadata rather than data to answer or cache
+    pub fn is_pseudo(&self) -> bool {
+        matches!(self.to_u16(), 41 | 249 | 250)
+    }
+
+    /// Whether this type may only appear in a question (IXFR, AXFR, MAILB,
+    /// MAILA, ANY) and never as the type of an answer record
+    pub fn is_query_only(&self) -> bool {
+        matches!(self.to_u16(), 251..=255)
+    }
+
+    /// Every type with its own record struct that can be stored and served,
+    /// i.e. neither pseudo nor query-only, in type code order
+    pub fn all_record_types() -> &'static [QRType] {
+        &[
+            QRType::A,
+            QRType::NS,
+            QRType::CNAME,
+            QRType::SOA,
+            QRType::PTR,
+            QRType::MX,
+            QRType::TXT,
+            QRType::X25,
+            QRType::ISDN,
+            QRType::RT,
+            QRType::AAAA,
+            QRType::SRV,
+            QRType::DS,
+            QRType::RRSIG,
+            QRType::DNSKEY,
+            QRType::SMIMEA,
+            QRType::CSYNC,
+            QRType::CAA,
+        ]
+    }
+}
+
+impl std::fmt::Display for QRType {
+    /// The mnemonic used in presentation format, or `TYPEnnn` (RFC 3597)
+    /// for types without one
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            QRType::UNKNOWN(x) => write!(f, "TYPE{}", x),
+            _ => write!(f, "{:?}", self),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq,Eq)]
 pub enum QRClass {
+    UNKNOWN(u16),
     IN,    // Internet
     CH,    // CHAOS
     HS,    // Hesiod
+    NONE,  // No class, used by dynamic update (RFC 2136)
     ANY, // Any class
 }
 
 impl QRClass {
-    pub fn from_u16(value: u16) -> Option<QRClass> {
+    pub fn from_u16(value: u16) -> QRClass {
         match value {
-            1 => Some(QRClass::IN),
-            3 => Some(QRClass::CH),
-            4 => Some(QRClass::HS),
-            255 => Some(QRClass::ANY),
-            _ => None,
+            1 => QRClass::IN,
+            3 => QRClass::CH,
+            4 => QRClass::HS,
+            254 => QRClass::NONE,
+            255 => QRClass::ANY,
+            _ => QRClass::UNKNOWN(value),
         }
     }
     pub fn to_u16(value: &QRClass) -> u16 {
@@ -80,7 +182,9 @@ impl QRClass {
             QRClass::IN => 1,
             QRClass::CH => 3,
             QRClass::HS => 4,
+            QRClass::NONE => 254,
             QRClass::ANY => 255,
+            QRClass::UNKNOWN(x) => *x,
         }
     }
 }
@@ -100,10 +204,24 @@ impl DNSQuestion {
             qtype, 
             qclass 
         }}
+    /// Whether a record answers this question: same name (ignoring case
+    /// and any trailing dot) and class, and either the asked-for type or a
+    /// CNAME standing in for it. An `ANY` type or class accepts every type
+    /// that can appear as an answer, or every class.
+    pub fn matches_record(&self, rec: &DNSRecord) -> bool {
+        let preamble = rec.preamble();
+        let canonical = |name: &str| name.trim_end_matches('.').to_lowercase();
+
+        let type_matches = !preamble.rtype.is_pseudo() && !preamble.rtype.is_query_only()
+            && (preamble.rtype == self.qtype || preamble.rtype == QRType::CNAME || self.qtype == QRType::ANY);
+        type_matches
+            && (preamble.class == self.qclass || self.qclass == QRClass::ANY)
+            && canonical(&preamble.name) == canonical(&self.qname)
+    }
     pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<(),std::io::Error> {
         buffer.read_qname(&mut self.qname)?;
         self.qtype = QRType::from_u16(buffer.read_u16()?); // qtype
-        let _ = buffer.read_u16()?; // class
+        self.qclass = QRClass::from_u16(buffer.read_u16()?); // class
 
         Ok(())
     }
@@ -112,26 +230,44 @@ impl DNSQuestion {
 
         let typenum = self.qtype.to_u16();
         buffer.write_u16(typenum)?;
-        buffer.write_u16(1)?;
+        buffer.write_u16(QRClass::to_u16(&self.qclass))?;
 
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub struct DNSQuestionSection {
     pub questions: Vec<DNSQuestion>,
+    pub raw: Option<Vec<u8>>, // Bytes written in place of `questions` when set, e.g. a request's section echoed verbatim
 }
 
 impl DNSQuestionSection {
     // Constructor for creating a new DNSQuestionSection
-    pub fn new() -> Self { DNSQuestionSection { questions: Vec::new() }}
+    pub fn new() -> Self { DNSQuestionSection { questions: Vec::new(), raw: None }}
+
+    // Method to add a question to the section, which no longer matches any
+    // raw bytes it was parsed from
+    pub fn add_question(&mut self, question:DNSQuestion) {
+        self.questions.push(question);
+        self.raw = None;
+    }
+}
 
-    // Method to add a question to the section
-    pub fn add_question(&mut self, question:DNSQuestion) { self.questions.push(question); }
+// Sections are equal when their questions are, however they are written
+impl PartialEq for DNSQuestionSection {
+    fn eq(&self, other: &Self) -> bool {
+        self.questions == other.questions
+    }
+}
+
+impl Default for DNSQuestionSection {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSAnswerSection {
     pub answers: Vec<DNSRecord>,
 }
@@ -144,7 +280,13 @@ impl DNSAnswerSection {
     pub fn add_answer(&mut self, answer:DNSRecord) { self.answers.push(answer); }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Default for DNSAnswerSection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSAuthoritySection {
     pub records: Vec<DNSRecord>,
 }
@@ -157,7 +299,13 @@ impl DNSAuthoritySection {
     pub fn add_record(&mut self, record: DNSRecord) { self.records.push(record); }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Default for DNSAuthoritySection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSAdditionalSection {
     pub records: Vec<DNSRecord>,
 }
@@ -170,13 +318,73 @@ impl DNSAdditionalSection {
     pub fn add_record(&mut self, record: DNSRecord) { self.records.push(record); }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Default for DNSAdditionalSection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How strictly `DNSPacket::from_buffer_with_mode` validates a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    Strict,  // Reject a set z bit or a record not filling its RDLENGTH
+    Lenient, // Tolerate them, recording a warning on the packet
+}
+
+/// Parses the records of a packet one at a time, as returned by
+/// `DNSPacket::record_iter`. Records are read leniently; after an error the
+/// iterator ends, since the position of the next record is unknown.
+pub struct RecordIter<'a> {
+    buffer: &'a mut BytePacketBuffer,
+    sections: [(&'static str, u16); 3], // Each section's name and records left to read
+    index: u16, // Position of the next record within its section
+    warnings: Vec<String>,
+}
+
+impl<'a> RecordIter<'a> {
+    // Constructor for reading the given number of answer, authority and
+    // additional records from the buffer's current position
+    pub fn new(buffer: &'a mut BytePacketBuffer, ancount: u16, nscount: u16, arcount: u16) -> Self {
+        let sections = [("answer", ancount), ("authority", nscount), ("additional", arcount)];
+        RecordIter { buffer, sections, index: 0, warnings: Vec::new() }
+    }
+    /// Problems tolerated in the records read so far
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+}
+
+impl Iterator for RecordIter<'_> {
+    type Item = Result<DNSRecord,std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let section = self.sections.iter_mut().find(|(_, left)| *left > 0)?;
+        let name = section.0;
+        section.1 -= 1;
+        let index = self.index;
+        self.index = if section.1 == 0 { 0 } else { index + 1 };
+
+        let record = DNSRecord::read_with_mode(self.buffer, ParseMode::Lenient, &mut self.warnings);
+        if record.is_err() {
+            self.sections = [("", 0); 3];
+        }
+        Some(record.map_err(|e| section_error(name, index, e)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.sections.iter().map(|(_, left)| *left as usize).sum();
+        (0, Some(left))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSPacket {
     pub header: DNSHeaderSection,
     pub question: DNSQuestionSection,
     pub answer: DNSAnswerSection,
     pub authority: DNSAuthoritySection,
-    pub additional: DNSAdditionalSection
+    pub additional: DNSAdditionalSection,
+    pub warnings: Vec<String>, // Problems tolerated while parsing leniently or receiving it
 }
 
 impl DNSPacket {
@@ -192,44 +400,142 @@ impl DNSPacket {
             question,
             answer,
             authority,
-            additional
+            additional,
+            warnings: Vec::new(),
         }
     }
     pub fn from_buffer(buffer: &mut BytePacketBuffer) -> Result<DNSPacket,std::io::Error> {
+        DNSPacket::from_buffer_with_mode(buffer, ParseMode::Lenient)
+    }
+    /// Parse a packet, either rejecting protocol violations or tolerating them
+    /// where the packet can still be understood. Section counts that cannot
+    /// be satisfied are an error in either mode.
+    pub fn from_buffer_with_mode(buffer: &mut BytePacketBuffer, mode: ParseMode) -> Result<DNSPacket,std::io::Error> {
         let mut result:DNSPacket = DNSPacket::new();
+        let mut warnings = Vec::new();
         result.header.read(buffer)?;
 
-        for _ in 0..result.header.qdcount {
-            let mut question = DNSQuestion::new("".to_string(), QRType::UNKNOWN(0),QRClass::ANY);
-            question.read(buffer)?;
-            result.question.add_question(question);
+        // The reserved z bit sits just above the AD bit in the second flags byte
+        if buffer.buf[3] & (1 << 6) != 0 {
+            let message = "Reserved z bit is set".to_string();
+            match mode {
+                ParseMode::Strict => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message)),
+                ParseMode::Lenient => warnings.push(message),
+            }
         }
 
-        for _ in 0..result.header.ancount {
-            let rec = DNSRecord::read(buffer)?;
+        result.question = read_questions(buffer, result.header.qdcount)?;
+
+        check_count(buffer, "answer", result.header.ancount, MIN_RECORD_LEN)?;
+        for i in 0..result.header.ancount {
+            let rec = DNSRecord::read_with_mode(buffer, mode, &mut warnings).map_err(|e| section_error("answer", i, e))?;
             result.answer.add_answer(rec);
         }
-        for _ in 0..result.header.nscount {
-            let rec = DNSRecord::read(buffer)?;
+        check_count(buffer, "authority", result.header.nscount, MIN_RECORD_LEN)?;
+        for i in 0..result.header.nscount {
+            let rec = DNSRecord::read_with_mode(buffer, mode, &mut warnings).map_err(|e| section_error("authority", i, e))?;
             result.authority.add_record(rec);
         }
-        for _ in 0..result.header.arcount {
-            let rec = DNSRecord::read(buffer).unwrap();
+        check_count(buffer, "additional", result.header.arcount, MIN_RECORD_LEN)?;
+        for i in 0..result.header.arcount {
+            let rec = DNSRecord::read_with_mode(buffer, mode, &mut warnings).map_err(|e| section_error("additional", i, e))?;
             result.additional.add_record(rec);
         }
 
+        result.warnings = warnings;
         Ok(result)
     }
+    /// Parse only the 12-byte header, e.g. to check the response code or
+    /// the TC flag, leaving the buffer positioned at the question section.
+    pub fn header_only(buffer: &mut BytePacketBuffer) -> Result<DNSHeaderSection,std::io::Error> {
+        let mut header = DNSHeaderSection::new();
+        header.read(buffer)?;
+        Ok(header)
+    }
+    /// Parse the header and questions, returning them with an iterator that
+    /// parses the answer, authority and additional records, in that order,
+    /// only as it is advanced. Callers after the first answer needn't pay
+    /// for the rest of the packet.
+    pub fn record_iter(buffer: &mut BytePacketBuffer) -> Result<(DNSHeaderSection, DNSQuestionSection, RecordIter<'_>),std::io::Error> {
+        let header = DNSPacket::header_only(buffer)?;
+        let questions = read_questions(buffer, header.qdcount)?;
+        let records = RecordIter::new(buffer, header.ancount, header.nscount, header.arcount);
+        Ok((header, questions, records))
+    }
+    /// Start the response to `request`: its ID, opcode, RD flag and
+    /// questions are copied, QR is set and recursion is marked available.
+    /// The sections are left for the caller to fill in.
+    pub fn response_to(request: &DNSPacket) -> DNSPacket {
+        let mut packet = DNSPacket::new();
+        packet.header.id = request.header.id;
+        packet.header.opcode = request.header.opcode;
+        packet.header.qr = QRFlag::Response;
+        packet.header.rd = request.header.rd;
+        packet.header.ra = RAFlag::Available;
+        packet.question.questions = request.question.questions.clone();
+        packet
+    }
+    /// Parse a packet from exactly the bytes received, e.g. a datagram.
+    ///
+    /// Unlike `from_buffer`, the length of the message is known, so bytes
+    /// left over after the last record can be detected. Strictly they are an
+    /// error; leniently they are recorded as a warning.
+    /// The question section of the message in `data` exactly as it was
+    /// encoded, preserving the casing of names that parsing lowercases
+    pub fn question_bytes(data: &[u8]) -> Result<Vec<u8>,std::io::Error> {
+        let mut buffer = BytePacketBuffer::with_size(data.len());
+        buffer.buf.copy_from_slice(data);
+        let mut header = DNSHeaderSection::new();
+        header.read(&mut buffer)?;
+        let start = buffer.pos();
+        read_questions(&mut buffer, header.qdcount)?;
+        Ok(buffer.buf[start..buffer.pos()].to_vec())
+    }
+    pub fn from_slice(data: &[u8], mode: ParseMode) -> Result<DNSPacket,std::io::Error> {
+        let mut buffer = BytePacketBuffer::with_size(data.len());
+        buffer.buf.copy_from_slice(data);
+
+        let mut packet = DNSPacket::from_buffer_with_mode(&mut buffer, mode)?;
+        if buffer.pos() < data.len() {
+            let message = format!("{} trailing bytes after the last record", data.len() - buffer.pos());
+            match mode {
+                ParseMode::Strict => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message)),
+                ParseMode::Lenient => packet.warnings.push(message),
+            }
+        }
+        Ok(packet)
+    }
+    /// Read a length-prefixed packet, as framed on TCP, from a byte stream.
+    ///
+    /// The two-byte big-endian length is read first, then exactly that many
+    /// bytes are read and parsed.
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<DNSPacket,std::io::Error> {
+        let mut len_bytes = [0u8; 2];
+        reader.read_exact(&mut len_bytes)?;
+        let len = u16::from_be_bytes(len_bytes) as usize;
+
+        let mut buffer = BytePacketBuffer::with_size(len);
+        reader.read_exact(&mut buffer.buf)?;
+
+        DNSPacket::from_buffer(&mut buffer)
+    }
     pub fn write(&mut self, buffer: &mut BytePacketBuffer) -> Result<(),std::io::Error> {
-        self.header.qdcount = self.question.questions.len() as u16;
-        self.header.ancount = self.answer.answers.len() as u16;
-        self.header.nscount = self.authority.records.len() as u16;
-        self.header.arcount = self.additional.records.len() as u16;
+        self.header.qdcount = section_count("question", self.question.questions.len())?;
+        self.header.ancount = section_count("answer", self.answer.answers.len())?;
+        self.header.nscount = section_count("authority", self.authority.records.len())?;
+        self.header.arcount = section_count("additional", self.additional.records.len())?;
 
         self.header.write(buffer)?;
 
-        for question in &self.question.questions {
-            question.write(buffer)?;
+        match &self.question.raw {
+            Some(raw) => {
+                buffer.write_bytes(raw)?;
+            }
+            None => {
+                for question in &self.question.questions {
+                    question.write(buffer)?;
+                }
+            }
         }
         for rec in &self.answer.answers {
             rec.write(buffer)?;
@@ -243,15 +549,76 @@ impl DNSPacket {
 
         Ok(())
     }
-    pub fn get_random_a(&self) -> Option<Ipv4Addr> {
+    /// Append the answer records of another packet that aren't already
+    /// present, ignoring TTL differences. Used to accumulate the answers of
+    /// several responses, such as the steps of a CNAME chain.
+    pub fn merge_answers(&mut self, other: &DNSPacket) {
+        for rec in &other.answer.answers {
+            if !self.answer.answers.iter().any(|existing| existing.same_rr(rec)) {
+                self.answer.add_answer(rec.clone());
+            }
+        }
+    }
+    /// Whether this is still the packet `DNSPacket::new` returns: a query
+    /// with ID 0, the default flags and nothing in any section. Sent as it
+    /// is, it is a malformed query, so a response that looks like this was
+    /// never filled in.
+    pub fn is_empty_query(&self) -> bool {
+        self.header == DNSHeaderSection::new()
+            && self.question.questions.is_empty()
+            && self.answer.answers.is_empty()
+            && self.authority.records.is_empty()
+            && self.additional.records.is_empty()
+    }
+    /// Whether this response delegates the question to other name servers:
+    /// no answers, no error, and NS records in the authority section.
+    pub fn is_referral(&self) -> bool {
+        self.answer.answers.is_empty()
+            && self.header.rcode == RCode::NoError
+            && self.authority.records.iter().any(|record| matches!(record, DNSRecord::NS(_)))
+    }
+    /// Every A and AAAA address in the answer section, in order
+    pub fn all_addresses(&self) -> Vec<IpAddr> {
+        self.answer.answers
+            .iter()
+            .filter_map(|record| match record {
+                DNSRecord::A(a_record) => Some(IpAddr::V4(a_record.rdata)),
+                DNSRecord::AAAA(aaaa_record) => Some(IpAddr::V6(aaaa_record.address)),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Every A address in the answer section, in order
+    pub fn all_a(&self) -> Vec<Ipv4Addr> {
         self.answer.answers
             .iter()
             .filter_map(|record| match record {
                 DNSRecord::A(a_record) => Some(a_record.rdata),
                 _ => None,
             })
-            .next()
+            .collect()
     }
+    /// Every AAAA address in the answer section, in order
+    pub fn all_aaaa(&self) -> Vec<Ipv6Addr> {
+        self.answer.answers
+            .iter()
+            .filter_map(|record| match record {
+                DNSRecord::AAAA(aaaa_record) => Some(aaaa_record.address),
+                _ => None,
+            })
+            .collect()
+    }
+    /// One of the A records in the answer section, picked using `rng`
+    pub fn get_random_a(&self, rng: &mut Rng) -> Option<Ipv4Addr> {
+        let addresses = self.all_a();
+        if addresses.is_empty() {
+            return None;
+        }
+        Some(addresses[rng.below(addresses.len())])
+    }
+    // The (zone, host) pairs of the authority section's NS records for zones
+    // containing `qname`. Any other records there, such as a stray CNAME or
+    // the SOA of a negative answer, are ignored.
     fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
         self.authority.records
             .iter()
@@ -259,24 +626,359 @@ impl DNSPacket {
                 DNSRecord::NS(ns_record) => Some((ns_record.preamble.name.as_str(), ns_record.rdata.as_str())),
                 _ => None,
             })
-            .filter(move |(domain, _)| qname.ends_with(*domain))
+            .filter(move |(domain, _)| is_within(qname, domain))
     }
-    pub fn get_resolved_ns(&self, qname: &str) -> Option<Ipv4Addr> {
+    /// Drop authority and additional records that repeat one in the answer
+    /// section, ignoring TTL, as some upstreams send them twice
+    pub fn collapse_duplicates(&mut self) {
+        let answers = &self.answer.answers;
+        self.authority.records.retain(|record| !answers.iter().any(|answer| answer.same_rr(record)));
+        self.additional.records.retain(|record| !answers.iter().any(|answer| answer.same_rr(record)));
+    }
+    /// The UDP payload size advertised by the packet's OPT record, or `None`
+    /// if it doesn't use EDNS
+    pub fn edns_udp_size(&self) -> Option<u16> {
+        self.additional.records
+            .iter()
+            .find_map(|record| match record {
+                DNSRecord::OPT(opt_record) => Some(opt_record.udp_payload_size()),
+                _ => None,
+            })
+    }
+    /// The zone a referral for `qname` delegates to: the owner name of its
+    /// NS records, or `None` if none of them lead towards `qname`
+    pub fn referral_zone<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
         self.get_ns(qname)
+            .map(|(domain, _)| domain)
+            .next()
+    }
+    /// The address of a name server for `qname` taken from the glue in the
+    /// additional section. IPv4 glue is preferred, as more hosts can reach
+    /// it, but AAAA glue is used when there is no A glue at all.
+    pub fn get_resolved_ns(&self, qname: &str) -> Option<IpAddr> {
+        let glue: Vec<IpAddr> = self.get_ns(qname)
             .flat_map(|(_, host)| {
                 self.additional.records
                     .iter()
-                    .filter_map(|record| match record {
-                        DNSRecord::A(a_record) if a_record.preamble.name == *host => Some(a_record.rdata),
+                    .filter_map(move |record| match record {
+                        DNSRecord::A(a_record) if same_name(&a_record.preamble.name, host) => Some(IpAddr::V4(a_record.rdata)),
+                        DNSRecord::AAAA(aaaa_record) if same_name(&aaaa_record.preamble.name, host) => Some(IpAddr::V6(aaaa_record.address)),
                         _ => None,
                     })
             })
-            .next()
+            .collect();
+
+        glue.iter().find(|addr| addr.is_ipv4()).or(glue.first()).copied()
+    }
+    /// Every name server host a referral for `qname` names, in order
+    pub fn get_ns_hosts<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = &'a str> {
+        self.get_ns(qname)
+            .map(|(_, host)| host)
     }
     pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
         self.get_ns(qname)
             .map(|(_, host)| host)
             .next()
     }
-                
+    /// The smallest TTL among the answer records, or `None` if there are no
+    /// answers. This is how long a positive answer may be cached.
+    pub fn min_ttl(&self) -> Option<u32> {
+        self.answer.answers
+            .iter()
+            .map(|record| record.preamble().ttl)
+            .min()
+    }
+    /// TTL to use when negatively caching this response, per RFC 2308.
+    ///
+    /// Only applies to NXDOMAIN and NODATA responses, i.e. when the answer
+    /// section is empty. The value is the SOA `minimum` from the authority
+    /// section, capped by the TTL of the SOA record itself. Positive answers
+    /// and responses without an SOA return `None`.
+    pub fn negative_ttl(&self) -> Option<u32> {
+        if !self.answer.answers.is_empty() {
+            return None;
+        }
+
+        self.get_soa()
+            .map(|soa_record| soa_record.minimum.min(soa_record.preamble.ttl))
+    }
+    /// The first SOA record in the answer section, as for a direct SOA
+    /// query, or else in the authority section, as in negative responses
+    pub fn get_soa(&self) -> Option<&DNSSOARecord> {
+        self.answer.answers
+            .iter()
+            .chain(self.authority.records.iter())
+            .find_map(|record| match record {
+                DNSRecord::SOA(soa_record) => Some(soa_record),
+                _ => None,
+            })
+    }
+    /// The EDNS Client Subnet option from the OPT record in the additional
+    /// section, if any. In a response its `scope_prefix` says how much of
+    /// the client's address the answer depends on.
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        self.additional.records
+            .iter()
+            .find_map(|record| match record {
+                DNSRecord::OPT(opt_record) => opt_record.client_subnet(),
+                _ => None,
+            })
+    }
+}
+
+impl Default for DNSPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Smallest possible encodings: a root name followed by type and class, plus
+// TTL and RDLENGTH for a resource record
+const MIN_QUESTION_LEN: usize = 5;
+const MIN_RECORD_LEN: usize = 11;
+
+// Whether two names are the same, ignoring case and a trailing dot
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+// Whether `name` is `zone` or below it, matching whole labels so that
+// `notexample.com` isn't taken to be within `example.com`
+fn is_within(name: &str, zone: &str) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+// Read `count` questions in order. Each is parsed into a fresh question,
+// so every one gets its own name, type and class from the wire.
+fn read_questions(buffer: &mut BytePacketBuffer, count: u16) -> Result<DNSQuestionSection,std::io::Error> {
+    check_count(buffer, "question", count, MIN_QUESTION_LEN)?;
+    let mut section = DNSQuestionSection::new();
+    for i in 0..count {
+        let mut question = DNSQuestion::new("".to_string(), QRType::UNKNOWN(0), QRClass::UNKNOWN(0));
+        question.read(buffer).map_err(|e| section_error("question", i, e))?;
+        section.add_question(question);
+    }
+    Ok(section)
+}
+
+// Reject a section count that the rest of the message couldn't hold even if
+// every entry were as short as possible. The bytes counted are those left of
+// the message received, not of the buffer it was received into, when the
+// buffer knows the message's length.
+fn check_count(buffer: &BytePacketBuffer, section: &str, count: u16, min_len: usize) -> Result<(),std::io::Error> {
+    let remaining = buffer.remaining();
+    if count as usize * min_len > remaining {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} count of {} exceeds the {} bytes remaining", section, count, remaining)));
+    }
+    Ok(())
+}
+
+// The count of entries in a section, which must fit the 16-bit header field
+fn section_count(section: &str, len: usize) -> Result<u16,std::io::Error> {
+    u16::try_from(len).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} section of {} entries exceeds 65535", section, len)))
+}
+
+// Name the section and entry a parse error occurred in
+fn section_error(section: &str, index: u16, e: std::io::Error) -> std::io::Error {
+    std::io::Error::new(e.kind(), format!("{} section, entry {}: {}", section, index, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::records::DNSARecord;
+
+    fn sample_response() -> DNSPacket {
+        let mut packet = DNSPacket::new();
+        packet.header.id = 1234;
+        packet.header.qr = QRFlag::Response;
+        packet.question.add_question(DNSQuestion::new("example.com".to_string(), QRType::A, QRClass::IN));
+        packet.answer.add_answer(DNSRecord::A(DNSARecord::new("example.com".to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, 1))));
+        packet
+    }
+
+    fn encode(packet: &mut DNSPacket) -> Vec<u8> {
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buf[0..buffer.pos].to_vec()
+    }
+
+    #[test]
+    fn huge_answer_count_on_a_short_packet_is_a_descriptive_error() {
+        let mut data = encode(&mut sample_response());
+        data[6..8].copy_from_slice(&65535u16.to_be_bytes());
+
+        let error = DNSPacket::from_slice(&data, ParseMode::Lenient).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("answer count of 65535"));
+    }
+
+    #[test]
+    fn counts_are_checked_against_the_message_received_not_the_buffer() {
+        // Three answers claimed but one sent; the rest of the 512 byte buffer
+        // is zeros, which would otherwise read as records
+        let mut data = encode(&mut sample_response());
+        data[6..8].copy_from_slice(&3u16.to_be_bytes());
+        let mut buffer = BytePacketBuffer::new();
+        buffer.buf[0..data.len()].copy_from_slice(&data);
+        buffer.set_message_len(data.len());
+
+        let error = DNSPacket::from_buffer(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("answer count of 3"));
+    }
+
+    #[test]
+    fn root_ns_query_serializes_with_an_empty_name() {
+        let mut query = DNSPacket::new();
+        query.header.id = 0x1234;
+        query.question.add_question(DNSQuestion::new(String::new(), QRType::NS, QRClass::IN));
+        let bytes = encode(&mut query);
+        // The question is the root's lone zero byte, then type NS and class IN
+        assert_eq!(bytes.len(), 12 + 5);
+        assert_eq!(&bytes[4..6], &[0, 1]);
+        assert_eq!(&bytes[12..], &[0, 0, 2, 0, 1]);
+
+        let parsed = DNSPacket::from_slice(&bytes, ParseMode::Strict).unwrap();
+        assert_eq!(parsed.question.questions[0].qname, "");
+        assert_eq!(parsed.question.questions[0].qtype, QRType::NS);
+    }
+
+    #[test]
+    fn each_of_several_questions_keeps_its_own_type_and_class() {
+        let bytes = [
+            // ID 1, three questions and no records
+            &[0, 1, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0][..],
+            b"\x01a\x04test\x00", &[0, 1, 0, 1], // a.test A IN
+            b"\x01b\x04test\x00", &[0, 15, 0, 3], // b.test MX CH
+            b"\x01c\x04test\x00", &[0, 16, 0, 255], // c.test TXT ANY
+        ].concat();
+        let packet = DNSPacket::from_slice(&bytes, ParseMode::Strict).unwrap();
+
+        let expected = [
+            ("a.test", QRType::A, QRClass::IN),
+            ("b.test", QRType::MX, QRClass::CH),
+            ("c.test", QRType::TXT, QRClass::ANY),
+        ];
+        assert_eq!(packet.question.questions.len(), expected.len());
+        for (question, (qname, qtype, qclass)) in packet.question.questions.iter().zip(expected) {
+            assert_eq!((question.qname.as_str(), question.qtype, question.qclass), (qname, qtype, qclass));
+        }
+    }
+
+    #[test]
+    fn parsed_questions_are_written_as_they_now_stand() {
+        let mut built = sample_response();
+        let data = encode(&mut built);
+        let mut parsed = DNSPacket::from_slice(&data, ParseMode::Strict).unwrap();
+        assert_eq!(parsed, built);
+
+        parsed.question.questions[0].qname = "example.org".to_string();
+        parsed.question.questions.push(DNSQuestion::new("example.net".to_string(), QRType::MX, QRClass::IN));
+        let reparsed = DNSPacket::from_slice(&encode(&mut parsed), ParseMode::Strict).unwrap();
+        assert_eq!(reparsed.header.qdcount, 2);
+        assert_eq!(reparsed.question, parsed.question);
+    }
+
+    #[test]
+    fn question_bytes_keep_the_casing_sent() {
+        let mut request = DNSPacket::new();
+        request.question.add_question(DNSQuestion::new("ExAmPlE.com".to_string(), QRType::A, QRClass::IN));
+        let data = encode(&mut request);
+        assert_eq!(DNSPacket::question_bytes(&data).unwrap(), &data[12..]);
+        assert!(DNSPacket::question_bytes(&data[..20]).is_err());
+    }
+
+    fn soa(ttl: u32, minimum: u32) -> DNSRecord {
+        DNSRecord::SOA(DNSSOARecord::new("example.com".to_string(), QRClass::IN, ttl, "ns.example.com".to_string(), "admin.example.com".to_string(), 1, 3600, 600, 86400, minimum))
+    }
+
+    #[test]
+    fn soa_is_found_in_the_answer_section() {
+        let mut packet = DNSPacket::new();
+        packet.answer.add_answer(soa(3600, 300));
+        assert_eq!(packet.get_soa().map(|soa_record| soa_record.preamble.ttl), Some(3600));
+    }
+
+    #[test]
+    fn soa_is_found_in_the_authority_section() {
+        let mut packet = DNSPacket::new();
+        assert!(packet.get_soa().is_none());
+        packet.authority.add_record(soa(900, 300));
+        assert_eq!(packet.get_soa().map(|soa_record| soa_record.minimum), Some(300));
+
+        // One in the answer section is preferred
+        packet.answer.add_answer(soa(3600, 60));
+        assert_eq!(packet.get_soa().map(|soa_record| soa_record.minimum), Some(60));
+    }
+
+    #[test]
+    fn negative_ttl_is_the_soa_minimum_when_smaller() {
+        // NODATA
+        let mut packet = DNSPacket::new();
+        packet.authority.add_record(soa(3600, 300));
+        assert_eq!(packet.negative_ttl(), Some(300));
+    }
+
+    #[test]
+    fn negative_ttl_is_capped_by_the_soa_ttl() {
+        let mut packet = DNSPacket::new();
+        packet.header.rcode = RCode::NXDomain;
+        packet.authority.add_record(soa(60, 300));
+        assert_eq!(packet.negative_ttl(), Some(60));
+    }
+
+    #[test]
+    fn positive_answer_has_no_negative_ttl() {
+        let mut packet = sample_response();
+        packet.authority.add_record(soa(3600, 300));
+        assert_eq!(packet.negative_ttl(), None);
+        // Nor does a negative answer without an SOA
+        assert_eq!(DNSPacket::new().negative_ttl(), None);
+    }
+
+    #[test]
+    fn header_only_reads_just_the_header() {
+        // Anything after the header, even garbage, is left unread
+        let mut data = encode(&mut sample_response())[0..12].to_vec();
+        data.extend_from_slice(&[0xff; 8]);
+        let mut buffer = BytePacketBuffer::with_size(data.len());
+        buffer.buf.copy_from_slice(&data);
+
+        let header = DNSPacket::header_only(&mut buffer).unwrap();
+        assert_eq!(header.id, 1234);
+        assert_eq!(header.qr, QRFlag::Response);
+        assert_eq!(header.ancount, 1);
+        assert_eq!(buffer.pos(), 12);
+    }
+
+    #[test]
+    fn record_iter_parses_records_only_as_they_are_asked_for() {
+        // Two answers, the second with an RDLENGTH running past the end
+        let mut packet = sample_response();
+        packet.answer.add_answer(DNSRecord::A(DNSARecord::new("example.com".to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, 2))));
+        let mut data = encode(&mut packet);
+        let len = data.len();
+        data[len - 6..len - 4].copy_from_slice(&[0, 40]);
+        let mut buffer = BytePacketBuffer::with_size(len);
+        buffer.buf.copy_from_slice(&data);
+        assert!(DNSPacket::from_buffer(&mut buffer).is_err());
+
+        buffer.seek(0).unwrap();
+        let (header, questions, mut records) = DNSPacket::record_iter(&mut buffer).unwrap();
+        assert_eq!(header.ancount, 2);
+        assert_eq!(questions.questions[0].qname, "example.com");
+        assert_eq!(records.size_hint(), (0, Some(2)));
+
+        // The first answer is there to be had despite the broken second one
+        let first = records.next().unwrap().unwrap();
+        assert!(matches!(first, DNSRecord::A(ref a) if a.rdata == Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(records.size_hint(), (0, Some(1)));
+
+        let error = records.next().unwrap().unwrap_err();
+        assert!(error.to_string().contains("answer"));
+        assert!(records.next().is_none());
+    }
 }
@@ -0,0 +1,151 @@
+// Punycode (RFC 3492) parameters for IDNA
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Decode a name's A-labels (`xn--...`) to their Unicode U-labels. Labels
+/// that aren't A-labels, or don't decode, are kept as they are.
+pub fn to_unicode(name: &str) -> String {
+    name.split('.')
+        .map(|label| {
+            let encoded = label.get(..4)
+                .filter(|prefix| prefix.eq_ignore_ascii_case("xn--"))
+                .map(|_| &label[4..]);
+            match encoded.and_then(decode) {
+                Some(decoded) => decoded,
+                None => label.to_string(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Decode a Punycode string, without the `xn--` prefix. `None` if it is
+/// malformed or empty.
+pub fn decode(input: &str) -> Option<String> {
+    // A bare `xn--` encodes nothing, which is no label at all
+    if input.is_empty() {
+        return None;
+    }
+    // Everything before the last delimiter is copied as is
+    let (basic, extended) = match input.rfind('-') {
+        Some(pos) => (&input[..pos], &input[pos + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.bytes();
+    while digits.len() > 0 {
+        // Each generalized variable-length integer gives the next insertion
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(digits.next()?)?;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = if k <= bias {
+                TMIN
+            } else if k >= bias + TMAX {
+                TMAX
+            } else {
+                k - bias
+            };
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+
+    Some(output.into_iter().collect())
+}
+
+fn decode_digit(byte: u8) -> Option<u32> {
+    match byte {
+        b'a'..=b'z' => Some((byte - b'a') as u32),
+        b'A'..=b'Z' => Some((byte - b'A') as u32),
+        b'0'..=b'9' => Some((byte - b'0') as u32 + 26),
+        _ => None,
+    }
+}
+
+fn adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use crate::message::records::{DNSARecord, DNSRecord};
+    use crate::message::QRClass;
+
+    #[test]
+    fn record_owner_is_displayed_in_unicode() {
+        let record = DNSRecord::A(DNSARecord::new("xn--mnchen-3ya.de".to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(record.display_unicode(), "münchen.de");
+        assert_eq!(record.preamble().name, "xn--mnchen-3ya.de");
+        assert_eq!(to_unicode("XN--mnchen-3ya.de"), "münchen.de");
+    }
+
+    #[test]
+    fn rfc_3492_samples_decode() {
+        // From section 7.1
+        let samples = [
+            ("egbpdaj6bu4bxfgehfvwxn", "ليهمابتكلموشعربي؟"),
+            ("ihqwcrb4cv8a8dqg056pqjye", "他们为什么不说中文"),
+            ("ihqwctvzc91f659drss3x8bo0yb", "他們爲什麽不說中文"),
+            ("Proprostnemluvesky-uyb24dma41a", "Pročprostěnemluvíčesky"),
+            ("4dbcagdahymbxekheh6e0a7fei0b", "למההםפשוטלאמדבריםעברית"),
+            ("3B-ww4c5e180e575a65lsy2b", "3年B組金八先生"),
+            ("-with-SUPER-MONKEYS-pc58ag80a8qai00g7n9n", "安室奈美恵-with-SUPER-MONKEYS"),
+            ("Hello-Another-Way--fc4qua05auwb3674vfr0b", "Hello-Another-Way-それぞれの場所"),
+            ("2-u9tlzr9756bt3uc0v", "ひとつ屋根の下2"),
+            ("d9juau41awczczp", "そのスピードで"),
+            ("-> $1.00 <--", "-> $1.00 <-"),
+        ];
+        for (encoded, decoded) in samples {
+            assert_eq!(decode(encoded).as_deref(), Some(decoded), "decoding {}", encoded);
+        }
+    }
+
+    #[test]
+    fn malformed_punycode_is_rejected() {
+        assert_eq!(decode(""), None);
+        assert_eq!(decode("mnchen-3y!"), None); // Not a base-36 digit
+        assert_eq!(decode("mnchen-3"), None); // Ends mid-integer
+        assert_eq!(decode("ü-3ya"), None); // Non-ASCII basic code points
+        assert_eq!(decode("99999a"), None); // Beyond the last code point
+        assert_eq!(decode("9999999999999a"), None); // Overflows a u32
+
+        // Labels that don't decode are displayed as they are
+        assert_eq!(to_unicode("xn--.de"), "xn--.de");
+        assert_eq!(to_unicode("xn--mnchen-3y!.de"), "xn--mnchen-3y!.de");
+        assert_eq!(to_unicode("www.example.com"), "www.example.com");
+    }
+}
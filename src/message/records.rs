@@ -1,10 +1,11 @@
-use crate::message::{QRType,QRClass,byte_packet_buffer::BytePacketBuffer};
+use crate::message::{QRType,QRClass,ParseMode,punycode,byte_packet_buffer::{checked_rdlength, BytePacketBuffer}};
 use std::net::{
+    IpAddr,
     Ipv4Addr,
     Ipv6Addr
 };
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DNSRecord {
     A(DNSARecord),
     CNAME(DNSCNAMERecord),
@@ -16,32 +17,193 @@ pub enum DNSRecord {
     CAA(DNSCAARecord),
     SRV(DNSSRVRecord),
     PTR(DNSPTRRecord),
+    CSYNC(DNSCSYNCRecord),
+    X25(DNSX25Record),
+    ISDN(DNSISDNRecord),
+    RT(DNSRTRecord),
+    TKEY(DNSTKEYRecord),
+    OPT(DNSOPTRecord),
+    SMIMEA(DNSSMIMEARecord),
+    DS(DNSDSRecord),
+    RRSIG(DNSRRSIGRecord),
+    DNSKEY(DNSDNSKEYRecord),
     UNKNOWN(DNSUNKNOWNRecord)
 }
 
 impl DNSRecord {
+    pub fn preamble(&self) -> &DNSRecordPreamble {
+        match self {
+            DNSRecord::A(record) => &record.preamble,
+            DNSRecord::CNAME(record) => &record.preamble,
+            DNSRecord::NS(record) => &record.preamble,
+            DNSRecord::MX(record) => &record.preamble,
+            DNSRecord::TXT(record) => &record.preamble,
+            DNSRecord::AAAA(record) => &record.preamble,
+            DNSRecord::SOA(record) => &record.preamble,
+            DNSRecord::CAA(record) => &record.preamble,
+            DNSRecord::SRV(record) => &record.preamble,
+            DNSRecord::PTR(record) => &record.preamble,
+            DNSRecord::CSYNC(record) => &record.preamble,
+            DNSRecord::X25(record) => &record.preamble,
+            DNSRecord::ISDN(record) => &record.preamble,
+            DNSRecord::RT(record) => &record.preamble,
+            DNSRecord::TKEY(record) => &record.preamble,
+            DNSRecord::OPT(record) => &record.preamble,
+            DNSRecord::SMIMEA(record) => &record.preamble,
+            DNSRecord::DS(record) => &record.preamble,
+            DNSRecord::RRSIG(record) => &record.preamble,
+            DNSRecord::DNSKEY(record) => &record.preamble,
+            DNSRecord::UNKNOWN(record) => &record.preamble,
+        }
+    }
+    pub fn preamble_mut(&mut self) -> &mut DNSRecordPreamble {
+        match self {
+            DNSRecord::A(record) => &mut record.preamble,
+            DNSRecord::CNAME(record) => &mut record.preamble,
+            DNSRecord::NS(record) => &mut record.preamble,
+            DNSRecord::MX(record) => &mut record.preamble,
+            DNSRecord::TXT(record) => &mut record.preamble,
+            DNSRecord::AAAA(record) => &mut record.preamble,
+            DNSRecord::SOA(record) => &mut record.preamble,
+            DNSRecord::CAA(record) => &mut record.preamble,
+            DNSRecord::SRV(record) => &mut record.preamble,
+            DNSRecord::PTR(record) => &mut record.preamble,
+            DNSRecord::CSYNC(record) => &mut record.preamble,
+            DNSRecord::X25(record) => &mut record.preamble,
+            DNSRecord::ISDN(record) => &mut record.preamble,
+            DNSRecord::RT(record) => &mut record.preamble,
+            DNSRecord::TKEY(record) => &mut record.preamble,
+            DNSRecord::OPT(record) => &mut record.preamble,
+            DNSRecord::SMIMEA(record) => &mut record.preamble,
+            DNSRecord::DS(record) => &mut record.preamble,
+            DNSRecord::RRSIG(record) => &mut record.preamble,
+            DNSRecord::DNSKEY(record) => &mut record.preamble,
+            DNSRecord::UNKNOWN(record) => &mut record.preamble,
+        }
+    }
+    /// The record's owner name for display, with any A-labels (`xn--...`)
+    /// decoded to Unicode. The record itself keeps the wire form.
+    pub fn display_unicode(&self) -> String {
+        punycode::to_unicode(&self.preamble().name)
+    }
+    /// Whether two records describe the same resource record, ignoring TTL.
+    ///
+    /// Used when merging answer sets, where the same record may arrive from
+    /// different sources with different remaining lifetimes.
+    pub fn same_rr(&self, other: &DNSRecord) -> bool {
+        let mut other = other.clone();
+        other.preamble_mut().ttl = self.preamble().ttl;
+        *self == other
+    }
+    /// Build a record from the presentation (zone file) form of its RDATA,
+    /// e.g. `192.0.2.1` for an A record or `10 mail.example.com.` for MX.
+    ///
+    /// Supports A, AAAA, CNAME, NS, PTR, MX, TXT and SRV. Domain names may be
+    /// written with or without the trailing dot; TXT data is one or more
+    /// character strings, quoted if they contain spaces.
+    pub fn from_presentation(name: &str, ttl: u32, class: QRClass, rtype: QRType, rdata: &str) -> Result<DNSRecord,std::io::Error> {
+        let name = presentation_name(name);
+        let tokens = presentation_tokens(rdata)?;
+        let field = |i: usize| -> Result<&str,std::io::Error> {
+            let token = tokens.get(i).ok_or_else(|| invalid_presentation(format!("{:?} RDATA is missing field {}", rtype, i + 1)))?;
+            std::str::from_utf8(token).map_err(|_| invalid_presentation(format!("{:?} RDATA field {} isn't valid text", rtype, i + 1)))
+        };
+        let expect_fields = |count: usize| -> Result<(),std::io::Error> {
+            if tokens.len() != count {
+                return Err(invalid_presentation(format!("{:?} RDATA has {} fields, expected {}", rtype, tokens.len(), count)));
+            }
+            Ok(())
+        };
+
+        match rtype {
+            QRType::A => {
+                expect_fields(1)?;
+                let addr = field(0)?.parse::<Ipv4Addr>().map_err(|e| invalid_presentation(e.to_string()))?;
+                Ok(DNSRecord::A(DNSARecord::new(name, class, ttl, addr)))
+            }
+            QRType::AAAA => {
+                expect_fields(1)?;
+                let address = field(0)?.parse::<Ipv6Addr>().map_err(|e| invalid_presentation(e.to_string()))?;
+                Ok(DNSRecord::AAAA(DNSAAAARecord::new(name, class, ttl, address)))
+            }
+            QRType::CNAME => {
+                expect_fields(1)?;
+                Ok(DNSRecord::CNAME(DNSCNAMERecord::new(name, class, ttl, presentation_name(field(0)?))))
+            }
+            QRType::NS => {
+                expect_fields(1)?;
+                Ok(DNSRecord::NS(DNSNSRecord::new(name, class, ttl, presentation_name(field(0)?))))
+            }
+            QRType::PTR => {
+                expect_fields(1)?;
+                Ok(DNSRecord::PTR(DNSPTRRecord::new(name, class, ttl, presentation_name(field(0)?))))
+            }
+            QRType::MX => {
+                expect_fields(2)?;
+                let preference = presentation_u16(field(0)?)?;
+                Ok(DNSRecord::MX(DNSMXRecord::new(name, class, ttl, preference, presentation_name(field(1)?))))
+            }
+            QRType::SRV => {
+                expect_fields(4)?;
+                let priority = presentation_u16(field(0)?)?;
+                let weight = presentation_u16(field(1)?)?;
+                let port = presentation_u16(field(2)?)?;
+                Ok(DNSRecord::SRV(DNSSRVRecord::new(name, class, ttl, priority, weight, port, presentation_name(field(3)?))))
+            }
+            QRType::TXT => {
+                if tokens.is_empty() {
+                    return Err(invalid_presentation("TXT RDATA needs at least one string".to_string()));
+                }
+                // The text holds the RDATA as it appears on the wire: each
+                // string preceded by its length in bytes
+                let mut rdata: Vec<u8> = Vec::new();
+                for token in &tokens {
+                    if token.len() > 255 {
+                        return Err(invalid_presentation(format!("TXT string of {} bytes exceeds 255", token.len())));
+                    }
+                    rdata.push(token.len() as u8);
+                    rdata.extend_from_slice(token);
+                }
+                let text = rdata.iter().map(|byte| *byte as char).collect();
+                Ok(DNSRecord::TXT(DNSTXTRecord::new(name, class, ttl, text)))
+            }
+            _ => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("No presentation format support for {:?} records", rtype))),
+        }
+    }
     pub fn read(buffer: &mut BytePacketBuffer) -> Result<DNSRecord,std::io::Error> {
+        DNSRecord::read_with_mode(buffer, ParseMode::Lenient, &mut Vec::new())
+    }
+    /// Read a record, checking that its RDATA is exactly RDLENGTH bytes.
+    ///
+    /// In strict mode a mismatch is an error. In lenient mode the buffer is
+    /// moved to the end given by RDLENGTH and a warning is recorded.
+    pub fn read_with_mode(buffer: &mut BytePacketBuffer, mode: ParseMode, warnings: &mut Vec<String>) -> Result<DNSRecord,std::io::Error> {
         let mut domain = String::new();
         buffer.read_qname(&mut domain)?;
 
         let qtype_num:u16 = buffer.read_u16()?;
         let qtype: QRType = QRType::from_u16(qtype_num);
 
-        let qclass_num:u16 = 1;
-        let class:QRClass = QRClass::from_u16(qclass_num).unwrap();
-        
+        let qclass_num:u16 = buffer.read_u16()?;
+        let class:QRClass = QRClass::from_u16(qclass_num);
+
         let ttl: u32 = buffer.read_u32()?;
         let data_len:u16 = buffer.read_u16()?;
 
-        match qtype {
+        // Make sure the RDATA can hold the record's mandatory fields before
+        // reading them, so the readers below never underflow, and that it
+        // fits in what is left of the buffer.
+        if data_len as usize > buffer.remaining() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("RDATA of {} bytes runs past the end of the buffer", data_len)));
+        }
+        if data_len < min_rdlength(qtype) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("RDATA of {} bytes is too short for a {:?} record", data_len, qtype)));
+        }
+
+        let rdata_start = buffer.pos();
+        let record: Result<DNSRecord,std::io::Error> = match qtype {
             QRType::A => {
-                let raw_addr = buffer.read_u32()?;
-                let addr = Ipv4Addr::new(
-                    ((raw_addr >> 24) & 0xFF) as u8,
-                    ((raw_addr >> 16) & 0xFF) as u8,
-                    ((raw_addr >> 8) & 0xFF) as u8,
-                    ((raw_addr >> 0) & 0xFF) as u8,
-                );
+                let addr = Ipv4Addr::from(buffer.read_u32()?);
 
                 Ok(DNSRecord::A(DNSARecord::new(domain, class, ttl, addr)))
             }
@@ -66,26 +228,15 @@ impl DNSRecord {
                 Ok(DNSRecord::MX(DNSMXRecord::new(domain, class, ttl, preference, exchange)))
             }
             QRType::TXT => {
-                let mut i:u16 = 0;
+                let end = buffer.pos() + data_len as usize;
                 let mut text: String = String::new();
-                while i <= data_len {                    
+                while buffer.pos() < end {
                     text.push(buffer.read_u8()? as char);
-                    i += 1;
                 }
                 Ok(DNSRecord::TXT(DNSTXTRecord::new(domain, class, ttl, text)))
             }
             QRType::AAAA => {
-                let raw_addr = buffer.read_u128()?;
-                let address:Ipv6Addr = Ipv6Addr::new(
-                    ((raw_addr >> 112) & 0xFFFF) as u16,
-                    ((raw_addr >> 96) & 0xFFFF) as u16,
-                    ((raw_addr >> 80) & 0xFFFF) as u16,
-                    ((raw_addr >> 64) & 0xFFFF) as u16,
-                    ((raw_addr >> 48) & 0xFFFF) as u16,
-                    ((raw_addr >> 32) & 0xFFFF) as u16,
-                    ((raw_addr >> 16) & 0xFFFF) as u16,
-                    ((raw_addr >> 0) & 0xFFFF) as u16,
-                );
+                let address:Ipv6Addr = Ipv6Addr::from(buffer.read_u128()?);
                 Ok(DNSRecord::AAAA(DNSAAAARecord::new(domain,class, ttl, address)))
             }
             QRType::SOA => {
@@ -103,18 +254,15 @@ impl DNSRecord {
             QRType::CAA => {
                 let flags: u8 = buffer.read_u8()?;
                 let tag_len: u8 = buffer.read_u8()?;
-                let mut i:u16 = 0;
+                let value_len = (data_len - 2).checked_sub(tag_len as u16)
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "CAA tag exceeds record length"))?;
                 let mut tag: String = String::new();
-                while i as u8 <= tag_len {                    
+                for _ in 0..tag_len {
                     tag.push(buffer.read_u8()? as char);
-                    i += 1;
                 }
-                i = 0;
-                let value_len = data_len - tag_len as u16;
-                let value: String = String::new();
-                while i <= value_len {                    
-                    tag.push(buffer.read_u8()? as char);
-                    i += 1;
+                let mut value: String = String::new();
+                for _ in 0..value_len {
+                    value.push(buffer.read_u8()? as char);
                 }
                 Ok(DNSRecord::CAA(DNSCAARecord::new(domain, class, ttl, flags, tag, value)))
             }
@@ -131,163 +279,436 @@ impl DNSRecord {
                 buffer.read_qname(&mut ptrdname)?;
                 Ok(DNSRecord::PTR(DNSPTRRecord::new(domain,class, ttl, ptrdname)))
             }
-            QRType::UNKNOWN(_) => {
+            QRType::CSYNC => {
+                let bit_maps_len = data_len as usize - 6;
+                let soa_serial: u32 = buffer.read_u32()?;
+                let flags: u16 = buffer.read_u16()?;
+                let type_bit_maps = read_type_bit_maps(buffer, bit_maps_len)?;
+                Ok(DNSRecord::CSYNC(DNSCSYNCRecord::new(domain, class, ttl, soa_serial, flags, type_bit_maps)))
+            }
+            QRType::X25 => {
+                let psdn_address: String = buffer.read_character_string()?;
+                Ok(DNSRecord::X25(DNSX25Record::new(domain, class, ttl, psdn_address)))
+            }
+            QRType::ISDN => {
+                let start_pos = buffer.pos();
+                let address: String = buffer.read_character_string()?;
+                // The sub-address is optional and only present if RDATA continues
+                let subaddress: Option<String> = if buffer.pos() - start_pos < data_len as usize {
+                    Some(buffer.read_character_string()?)
+                } else {
+                    None
+                };
+                Ok(DNSRecord::ISDN(DNSISDNRecord::new(domain, class, ttl, address, subaddress)))
+            }
+            QRType::RT => {
+                let preference: u16 = buffer.read_u16()?;
+                let mut intermediate_host: String = String::new();
+                buffer.read_qname(&mut intermediate_host)?;
+                Ok(DNSRecord::RT(DNSRTRecord::new(domain, class, ttl, preference, intermediate_host)))
+            }
+            QRType::TKEY => {
+                let mut algorithm: String = String::new();
+                buffer.read_qname(&mut algorithm)?;
+                let inception: u32 = buffer.read_u32()?;
+                let expiration: u32 = buffer.read_u32()?;
+                let mode: u16 = buffer.read_u16()?;
+                let error: u16 = buffer.read_u16()?;
+                let key_len = buffer.read_u16()? as usize;
+                let key_data = buffer.get_byte_range(buffer.pos(), key_len)?.to_vec();
+                buffer.step(key_len)?;
+                let other_len = buffer.read_u16()? as usize;
+                let other_data = buffer.get_byte_range(buffer.pos(), other_len)?.to_vec();
+                buffer.step(other_len)?;
+                Ok(DNSRecord::TKEY(DNSTKEYRecord::new(domain, class, ttl, algorithm, inception, expiration, mode, error, key_data, other_data)))
+            }
+            QRType::OPT => {
+                // RDATA is a sequence of {code, length, data} options, none of
+                // which may run past its end
+                let end = rdata_start + data_len as usize;
+                let mut options: Vec<EDNSOption> = Vec::new();
+                while buffer.pos() < end {
+                    if buffer.pos() + 4 > end {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "EDNS option header runs past the end of the RDATA"));
+                    }
+                    let code: u16 = buffer.read_u16()?;
+                    let len = buffer.read_u16()? as usize;
+                    if buffer.pos() + len > end {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("EDNS option {} runs past the end of the RDATA", code)));
+                    }
+                    let data = buffer.get_byte_range(buffer.pos(), len)?.to_vec();
+                    buffer.step(len)?;
+                    options.push(EDNSOption { code, data });
+                }
+                let mut record = DNSOPTRecord::new(QRClass::to_u16(&class), options);
+                record.preamble.name = domain;
+                record.preamble.ttl = ttl;
+                Ok(DNSRecord::OPT(record))
+            }
+            QRType::SMIMEA => {
+                let usage: u8 = buffer.read_u8()?;
+                let selector: u8 = buffer.read_u8()?;
+                let matching_type: u8 = buffer.read_u8()?;
+                // The association data runs to the end of the RDATA
+                let data_len = data_len as usize - 3;
+                let cert_association_data = buffer.get_byte_range(buffer.pos(), data_len)?.to_vec();
+                buffer.step(data_len)?;
+                Ok(DNSRecord::SMIMEA(DNSSMIMEARecord::new(domain, class, ttl, usage, selector, matching_type, cert_association_data)))
+            }
+            QRType::DS => {
+                let key_tag: u16 = buffer.read_u16()?;
+                let algorithm: u8 = buffer.read_u8()?;
+                let digest_type: u8 = buffer.read_u8()?;
+                // The digest runs to the end of the RDATA
+                let digest_len = data_len as usize - 4;
+                let digest = buffer.get_byte_range(buffer.pos(), digest_len)?.to_vec();
+                buffer.step(digest_len)?;
+                Ok(DNSRecord::DS(DNSDSRecord::new(domain, class, ttl, key_tag, algorithm, digest_type, digest)))
+            }
+            QRType::RRSIG => {
+                let type_covered = QRType::from_u16(buffer.read_u16()?);
+                let algorithm: u8 = buffer.read_u8()?;
+                let labels: u8 = buffer.read_u8()?;
+                let original_ttl: u32 = buffer.read_u32()?;
+                let expiration: u32 = buffer.read_u32()?;
+                let inception: u32 = buffer.read_u32()?;
+                let key_tag: u16 = buffer.read_u16()?;
+                let mut signer_name: String = String::new();
+                buffer.read_qname(&mut signer_name)?;
+                // The signature runs to the end of the RDATA
+                let signature_len = (data_len as usize).checked_sub(buffer.pos() - rdata_start)
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "RRSIG signer name overruns RDATA"))?;
+                let signature = buffer.get_byte_range(buffer.pos(), signature_len)?.to_vec();
+                buffer.step(signature_len)?;
+                Ok(DNSRecord::RRSIG(DNSRRSIGRecord::new(domain, class, ttl, type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer_name, signature)))
+            }
+            QRType::DNSKEY => {
+                let flags: u16 = buffer.read_u16()?;
+                let protocol: u8 = buffer.read_u8()?;
+                let algorithm: u8 = buffer.read_u8()?;
+                // The public key runs to the end of the RDATA
+                let key_len = data_len as usize - 4;
+                let public_key = buffer.get_byte_range(buffer.pos(), key_len)?.to_vec();
+                buffer.step(key_len)?;
+                Ok(DNSRecord::DNSKEY(DNSDNSKEYRecord::new(domain, class, ttl, flags, protocol, algorithm, public_key)))
+            }
+            // Query-only types never carry data, so are kept opaque like unknown ones
+            QRType::UNKNOWN(_) | QRType::IXFR | QRType::AXFR | QRType::MAILB | QRType::MAILA | QRType::ANY => {
+                let rdata = buffer.get_byte_range(buffer.pos(), data_len as usize)?.to_vec();
                 buffer.step(data_len as usize)?;
-                Ok(DNSRecord::UNKNOWN(DNSUNKNOWNRecord::new(domain,class, ttl)))
+                Ok(DNSRecord::UNKNOWN(DNSUNKNOWNRecord::new(domain, qtype, class, ttl, rdata)))
+            }
+        };
+        let record = record?;
+
+        let consumed = buffer.pos() - rdata_start;
+        if consumed != data_len as usize {
+            let message = format!("{:?} record has RDLENGTH {} but {} bytes of RDATA", qtype, data_len, consumed);
+            match mode {
+                ParseMode::Strict => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message)),
+                ParseMode::Lenient => {
+                    buffer.seek(rdata_start + data_len as usize)?;
+                    warnings.push(message);
+                }
             }
         }
+
+        Ok(record)
     }
     pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<(), std::io::Error> {
         match self {
-            DNSRecord::A(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                buffer.write_u16(record.preamble.rdlength)?;
-                
+            DNSRecord::A(record) => write_record(buffer, &record.preamble, |buffer| {
                 // Write the IPv4 address
-                let octets = record.rdata.octets();
-                for octet in octets.iter() {
-                    buffer.write_u8(*octet)?;
-                }
-            },
-            DNSRecord::CNAME(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                // Placeholder position for length
-                let len_pos = buffer.pos();
-                buffer.write_u16(0)?; // Placeholder for length
-
-                let start_pos = buffer.pos();
-                buffer.write_qname(&record.rdata)?;
-                let end_pos = buffer.pos();
-                let rdlength = end_pos - start_pos;
-                buffer.seek(len_pos)?;
-                buffer.write_u16(rdlength as u16)?;
-                buffer.seek(end_pos)?;
-            },
-            DNSRecord::MX(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                let len_pos = buffer.pos();
-                buffer.write_u16(0)?; // Placeholder for length
-
-                let start_pos = buffer.pos();
+                buffer.write_bytes(&record.rdata.octets())?;
+                Ok(())
+            }),
+            DNSRecord::CNAME(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_qname(&record.rdata)
+            }),
+            DNSRecord::NS(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_qname(&record.rdata)
+            }),
+            DNSRecord::MX(record) => write_record(buffer, &record.preamble, |buffer| {
                 buffer.write_u16(record.preference)?;
-                buffer.write_qname(&record.exchange)?;
-                let end_pos = buffer.pos();
-                let rdlength = end_pos - start_pos;
-                buffer.seek(len_pos)?;
-                buffer.write_u16(rdlength as u16)?;
-                buffer.seek(end_pos)?;
-            },
-            DNSRecord::TXT(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                let text_bytes = record.text.as_bytes();
-                buffer.write_u16(text_bytes.len() as u16)?;
-                for byte in text_bytes {
-                    buffer.write_u8(*byte)?;
+                buffer.write_qname(&record.exchange)
+            }),
+            DNSRecord::TXT(record) => write_record(buffer, &record.preamble, |buffer| {
+                for c in record.text.chars() {
+                    let byte = u8::try_from(c).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("TXT RDATA character {:?} isn't a single byte", c)))?;
+                    buffer.write_u8(byte)?;
                 }
-            },
-            DNSRecord::AAAA(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                buffer.write_u16(16)?; // IPv6 address is always 16 bytes
-                buffer.write_u128(record.address.into())?;
-            },
-            DNSRecord::SOA(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                let len_pos = buffer.pos();
-                buffer.write_u16(0)?; // Placeholder for length
-
-                let start_pos = buffer.pos();
+                Ok(())
+            }),
+            DNSRecord::AAAA(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_u128(record.address.into())
+            }),
+            DNSRecord::SOA(record) => write_record(buffer, &record.preamble, |buffer| {
                 buffer.write_qname(&record.mname)?;
                 buffer.write_qname(&record.rname)?;
                 buffer.write_u32(record.serial)?;
                 buffer.write_u32(record.refresh)?;
                 buffer.write_u32(record.retry)?;
                 buffer.write_u32(record.expire)?;
-                buffer.write_u32(record.minimum)?;
-                let end_pos = buffer.pos();
-                let rdlength = end_pos - start_pos;
-                buffer.seek(len_pos)?;
-                buffer.write_u16(rdlength as u16)?;
-                buffer.seek(end_pos)?;
-            },
-            DNSRecord::SRV(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                let len_pos = buffer.pos();
-                buffer.write_u16(0)?; // Placeholder for length
-
-                let start_pos = buffer.pos();
+                buffer.write_u32(record.minimum)
+            }),
+            DNSRecord::SRV(record) => write_record(buffer, &record.preamble, |buffer| {
                 buffer.write_u16(record.priority)?;
                 buffer.write_u16(record.weight)?;
                 buffer.write_u16(record.port)?;
-                buffer.write_qname(&record.target)?;
-                let end_pos = buffer.pos();
-                let rdlength = end_pos - start_pos;
-                buffer.seek(len_pos)?;
-                buffer.write_u16(rdlength as u16)?;
-                buffer.seek(end_pos)?;
-            },
-            DNSRecord::CAA(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                // Calculate the length of the CAA record data.
+                buffer.write_qname(&record.target)
+            }),
+            DNSRecord::CAA(record) => write_record(buffer, &record.preamble, |buffer| {
                 // Flags (1 byte) + Tag length (1 byte) + Tag + Value
-                let data_len = 1 + 1 + record.tag.len() + record.value.len();
-                buffer.write_u16(data_len as u16)?;
-                        
+                let tag_len = u8::try_from(record.tag.len())
+                    .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "CAA tag exceeds 255 bytes"))?;
+                checked_rdlength(&[2, record.tag.len(), record.value.len()])?;
                 buffer.write_u8(record.flags)?;
-                buffer.write_u8(record.tag.len() as u8)?;
-                for byte in record.tag.as_bytes() {
-                    buffer.write_u8(*byte)?;
+                buffer.write_u8(tag_len)?;
+                buffer.write_bytes(record.tag.as_bytes())?;
+                buffer.write_bytes(record.value.as_bytes())?;
+                Ok(())
+            }),
+            DNSRecord::PTR(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_qname(&record.ptrdname)
+            }),
+            DNSRecord::CSYNC(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_u32(record.soa_serial)?;
+                buffer.write_u16(record.flags)?;
+                write_type_bit_maps(buffer, &record.type_bit_maps)
+            }),
+            DNSRecord::X25(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_character_string(&record.psdn_address)
+            }),
+            DNSRecord::ISDN(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_character_string(&record.address)?;
+                if let Some(subaddress) = &record.subaddress {
+                    buffer.write_character_string(subaddress)?;
                 }
-                for byte in record.value.as_bytes() {
-                    buffer.write_u8(*byte)?;
+                Ok(())
+            }),
+            DNSRecord::RT(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_u16(record.preference)?;
+                buffer.write_qname(&record.intermediate_host)
+            }),
+            DNSRecord::TKEY(record) => write_record(buffer, &record.preamble, |buffer| {
+                buffer.write_qname(&record.algorithm)?;
+                buffer.write_u32(record.inception)?;
+                buffer.write_u32(record.expiration)?;
+                buffer.write_u16(record.mode)?;
+                buffer.write_u16(record.error)?;
+                // An encoded name is at most two bytes longer than its text
+                checked_rdlength(&[record.algorithm.len() + 2, 16, record.key_data.len(), record.other_data.len()])?;
+                for data in [&record.key_data, &record.other_data] {
+                    buffer.write_u16(data.len() as u16)?;
+                    buffer.write_bytes(data)?;
                 }
-            },
-            DNSRecord::PTR(record) => {
-                buffer.write_qname(&record.preamble.name)?;
-                buffer.write_u16(record.preamble.rtype.to_u16())?;
-                buffer.write_u16(QRClass::to_u16(&record.preamble.class))?;
-                buffer.write_u32(record.preamble.ttl)?;
-                let len_pos = buffer.pos();
-                buffer.write_u16(0)?; // Placeholder for length
+                Ok(())
+            }),
+            DNSRecord::OPT(record) => write_record(buffer, &record.preamble, |buffer| {
+                let lengths: Vec<usize> = record.options.iter().map(|option| 4 + option.data.len()).collect();
+                checked_rdlength(&lengths)?;
+                for option in &record.options {
+                    buffer.write_u16(option.code)?;
+                    buffer.write_u16(option.data.len() as u16)?;
+                    buffer.write_bytes(&option.data)?;
+                }
+                Ok(())
+            }),
+            DNSRecord::SMIMEA(record) => write_record(buffer, &record.preamble, |buffer| {
+                checked_rdlength(&[3, record.cert_association_data.len()])?;
+                buffer.write_u8(record.usage)?;
+                buffer.write_u8(record.selector)?;
+                buffer.write_u8(record.matching_type)?;
+                buffer.write_bytes(&record.cert_association_data)
+            }),
+            DNSRecord::DS(record) => write_record(buffer, &record.preamble, |buffer| {
+                checked_rdlength(&[4, record.digest.len()])?;
+                buffer.write_u16(record.key_tag)?;
+                buffer.write_u8(record.algorithm)?;
+                buffer.write_u8(record.digest_type)?;
+                buffer.write_bytes(&record.digest)
+            }),
+            DNSRecord::RRSIG(record) => write_record(buffer, &record.preamble, |buffer| {
+                // An encoded name is at most two bytes longer than its text
+                checked_rdlength(&[18, record.signer_name.len() + 2, record.signature.len()])?;
+                record.write_signed_fields(buffer)?;
+                buffer.write_bytes(&record.signature)
+            }),
+            DNSRecord::DNSKEY(record) => write_record(buffer, &record.preamble, |buffer| {
+                checked_rdlength(&[4, record.public_key.len()])?;
+                buffer.write_u16(record.flags)?;
+                buffer.write_u8(record.protocol)?;
+                buffer.write_u8(record.algorithm)?;
+                buffer.write_bytes(&record.public_key)
+            }),
+            DNSRecord::UNKNOWN(record) => write_record(buffer, &record.preamble, |buffer| {
+                checked_rdlength(&[record.rdata.len()])?;
+                buffer.write_bytes(&record.rdata)
+            }),
+        }
+    }
+}
 
-                let start_pos = buffer.pos();
-                buffer.write_qname(&record.ptrdname)?;
-                let end_pos = buffer.pos();
-                let rdlength = end_pos - start_pos;
-                buffer.seek(len_pos)?;
-                buffer.write_u16(rdlength as u16)?;
-                buffer.seek(end_pos)?;
-            },
-            // Handle other record types similarly...
-            _ => return Err(std::io::Error::new(std::io::ErrorKind::Other, "Unsupported record type")),
+/// The smallest RDATA length that can hold a record type's mandatory fields.
+/// A domain name takes at least one byte, for the root.
+fn min_rdlength(qtype: QRType) -> u16 {
+    match qtype {
+        QRType::A => 4,
+        QRType::AAAA => 16,
+        QRType::NS | QRType::CNAME | QRType::PTR => 1,
+        QRType::MX | QRType::RT => 2 + 1,
+        QRType::TXT | QRType::X25 | QRType::ISDN => 1,
+        QRType::SOA => 1 + 1 + 5 * 4,
+        QRType::CAA => 2,
+        QRType::SRV => 3 * 2 + 1,
+        QRType::CSYNC => 4 + 2,
+        QRType::TKEY => 1 + 2 * 4 + 4 * 2,
+        QRType::SMIMEA => 3,
+        QRType::DS => 4,
+        QRType::RRSIG => 18 + 1,
+        QRType::DNSKEY => 4,
+        QRType::OPT | QRType::UNKNOWN(_) | QRType::IXFR | QRType::AXFR | QRType::MAILB | QRType::MAILA | QRType::ANY => 0,
+    }
+}
+
+/// Write a record: the preamble fields, then the RDATA produced by
+/// `write_rdata` with its RDLENGTH backfilled. All record writers go through
+/// this so the length can never disagree with the data.
+fn write_record<F>(buffer: &mut BytePacketBuffer, preamble: &DNSRecordPreamble, write_rdata: F) -> Result<(), std::io::Error>
+where
+    F: FnOnce(&mut BytePacketBuffer) -> Result<(), std::io::Error>,
+{
+    buffer.write_qname(&preamble.name)?;
+    buffer.write_u16(preamble.rtype.to_u16())?;
+    buffer.write_u16(QRClass::to_u16(&preamble.class))?;
+    buffer.write_u32(preamble.ttl)?;
+    buffer.write_rdata_with_len(write_rdata)
+}
+
+// Strip the trailing dot from a fully qualified name; the root becomes empty
+fn presentation_name(name: &str) -> String {
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+fn presentation_u16(field: &str) -> Result<u16,std::io::Error> {
+    field.parse::<u16>().map_err(|e| invalid_presentation(format!("{:?}: {}", field, e)))
+}
+
+fn invalid_presentation(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+// Split RDATA into whitespace-separated fields, as bytes. Double quotes group
+// a field containing spaces, and a backslash escapes the next character or,
+// followed by three digits, gives a byte by its decimal value.
+fn presentation_tokens(rdata: &str) -> Result<Vec<Vec<u8>>,std::io::Error> {
+    let mut tokens = Vec::new();
+    let mut current: Vec<u8> = Vec::new();
+    let mut in_token = false;
+    let mut quoted = false;
+    let mut chars = rdata.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_token = true;
+            }
+            '\\' => {
+                let next = chars.next().ok_or_else(|| invalid_presentation("RDATA ends with a backslash".to_string()))?;
+                if next.is_ascii_digit() {
+                    let digits: String = std::iter::once(next).chain(chars.by_ref().take(2)).collect();
+                    let value = digits.parse::<u8>().map_err(|_| invalid_presentation(format!("Invalid escape \\{}", digits)))?;
+                    current.push(value);
+                } else {
+                    current.extend_from_slice(next.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                in_token = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            c => {
+                current.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                in_token = true;
+            }
         }
-        Ok(())
     }
+
+    if quoted {
+        return Err(invalid_presentation("Unterminated quoted string in RDATA".to_string()));
+    }
+    if in_token {
+        tokens.push(current);
+    }
+    Ok(tokens)
 }
-#[derive(Debug, PartialEq, Eq)]
+
+/// Read the window-block type bitmaps shared by NSEC and CSYNC.
+///
+/// Each block is a window number, a bitmap length of 1-32 bytes, and the
+/// bitmap itself, where bit N (most significant first) marks type
+/// `window * 256 + N` as present.
+pub(crate) fn read_type_bit_maps(buffer: &mut BytePacketBuffer, len: usize) -> Result<Vec<QRType>,std::io::Error> {
+    let mut types: Vec<QRType> = Vec::new();
+    let end = buffer.pos() + len;
+
+    while buffer.pos() < end {
+        if buffer.pos() + 2 > end {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Type bitmap window header runs past the end of the RDATA"));
+        }
+        let window = buffer.read_u8()? as u16;
+        let bitmap_len = buffer.read_u8()?;
+        if bitmap_len == 0 || bitmap_len > 32 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid type bitmap length"));
+        }
+        if buffer.pos() + bitmap_len as usize > end {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Type bitmap runs past the end of the RDATA"));
+        }
+
+        for i in 0..bitmap_len as u16 {
+            let byte = buffer.read_u8()?;
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(QRType::from_u16((window << 8) | (i * 8 + bit)));
+                }
+            }
+        }
+    }
+
+    Ok(types)
+}
+
+/// Write the window-block type bitmaps shared by NSEC and CSYNC.
+fn write_type_bit_maps(buffer: &mut BytePacketBuffer, types: &[QRType]) -> Result<(),std::io::Error> {
+    let mut codes: Vec<u16> = types.iter().map(|qtype| qtype.to_u16()).collect();
+    codes.sort_unstable();
+    codes.dedup();
+
+    let mut i = 0;
+    while i < codes.len() {
+        let window = codes[i] >> 8;
+        let mut bitmap = [0u8; 32];
+        let mut bitmap_len = 0;
+
+        while i < codes.len() && codes[i] >> 8 == window {
+            let offset = (codes[i] & 0xFF) as usize;
+            bitmap[offset / 8] |= 0x80 >> (offset % 8);
+            bitmap_len = offset / 8 + 1;
+            i += 1;
+        }
+
+        buffer.write_u8(window as u8)?;
+        buffer.write_u8(bitmap_len as u8)?;
+        buffer.write_bytes(&bitmap[0..bitmap_len])?;
+    }
+
+    Ok(())
+}
+#[derive(Debug, Clone, Eq)]
 pub struct DNSRecordPreamble {
     pub name: String, // The domain name the record pertains to
     pub rtype: QRType, // The type of the resource record
@@ -301,7 +722,19 @@ impl DNSRecordPreamble {
     pub fn new(name: String, rtype: QRType, class: QRClass, ttl: u32, rdlength: u16) -> Self { DNSRecordPreamble { name, rtype, class, ttl, rdlength }}
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Owner names compare case-insensitively, as DNS names do, so a record
+/// built as `Example.COM` equals one read off the wire as `example.com`.
+impl PartialEq for DNSRecordPreamble {
+    fn eq(&self, other: &Self) -> bool {
+        self.name.eq_ignore_ascii_case(&other.name)
+            && self.rtype == other.rtype
+            && self.class == other.class
+            && self.ttl == other.ttl
+            && self.rdlength == other.rdlength
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSARecord {
     pub preamble: DNSRecordPreamble, // The common preamble for DNS records
     pub rdata: std::net::Ipv4Addr, // The IPv4 address
@@ -323,27 +756,24 @@ impl DNSARecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSUNKNOWNRecord {
     pub preamble: DNSRecordPreamble,
+    pub rdata: Vec<u8>, // The RDATA as received, so the record can be relayed unchanged
 }
 
 impl DNSUNKNOWNRecord {
-    // Constructor for creating a new DNSARecord
-    pub fn new(name: String, class:QRClass, ttl: u32) -> Self {
+    // Constructor for a record of a type we don't interpret, keeping its
+    // type code and opaque RDATA (RFC 3597)
+    pub fn new(name: String, rtype: QRType, class:QRClass, ttl: u32, rdata: Vec<u8>) -> Self {
         DNSUNKNOWNRecord {
-            preamble: DNSRecordPreamble {
-                name,
-                rtype: QRType::UNKNOWN(0), // The type code for an A record is 1
-                class, // The class for Internet is 1 (IN)
-                ttl,
-                rdlength: 4, // IPv4 addresses are 4 bytes in length
-            },
+            preamble: DNSRecordPreamble::new(name, rtype, class, ttl, 0), // rdlength will be set later
+            rdata,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSCNAMERecord {
     pub preamble: DNSRecordPreamble, // The common preamble for DNS records
     pub rdata: String, // The canonical domain name
@@ -366,7 +796,7 @@ impl DNSCNAMERecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSNSRecord {
     pub preamble: DNSRecordPreamble, // The common preamble for DNS records
     pub rdata: String, // The domain name of the authoritative name server
@@ -389,7 +819,7 @@ impl DNSNSRecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSMXRecord {
     pub preamble: DNSRecordPreamble,
     pub preference: u16, // Preference value
@@ -406,10 +836,10 @@ impl DNSMXRecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSTXTRecord {
     pub preamble: DNSRecordPreamble,
-    pub text: String, // Text data
+    pub text: String, // The RDATA, one char per byte: each string's length followed by its bytes
 }
 
 impl DNSTXTRecord {
@@ -421,7 +851,7 @@ impl DNSTXTRecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSAAAARecord {
     pub preamble: DNSRecordPreamble,
     pub address: std::net::Ipv6Addr, // IPv6 address
@@ -436,7 +866,7 @@ impl DNSAAAARecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSSOARecord {
     pub preamble: DNSRecordPreamble,
     pub mname: String, // Primary name server
@@ -449,6 +879,7 @@ pub struct DNSSOARecord {
 }
 
 impl DNSSOARecord {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(name: String, class:QRClass, ttl: u32, mname: String, rname: String, serial: u32, refresh: u32, retry: u32, expire: u32, minimum: u32) -> Self {
         DNSSOARecord {
             preamble: DNSRecordPreamble::new(name, QRType::SOA, class, ttl, 0), // rdlength will be set later
@@ -463,7 +894,7 @@ impl DNSSOARecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSCAARecord {
     pub preamble: DNSRecordPreamble,
     pub flags: u8,    // Flags
@@ -482,7 +913,7 @@ impl DNSCAARecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSSRVRecord {
     pub preamble: DNSRecordPreamble,
     pub priority: u16, // Priority
@@ -503,7 +934,7 @@ impl DNSSRVRecord {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DNSPTRRecord {
     pub preamble: DNSRecordPreamble,
     pub ptrdname: String, // The domain name which the PTR points to
@@ -517,3 +948,588 @@ impl DNSPTRRecord {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSCSYNCRecord {
+    pub preamble: DNSRecordPreamble,
+    pub soa_serial: u32, // SOA serial the child zone was synchronized at
+    pub flags: u16,      // Flags
+    pub type_bit_maps: Vec<QRType>, // Record types to synchronize
+}
+
+impl DNSCSYNCRecord {
+    pub fn new(name: String, class:QRClass, ttl: u32, soa_serial: u32, flags: u16, type_bit_maps: Vec<QRType>) -> Self {
+        DNSCSYNCRecord {
+            preamble: DNSRecordPreamble::new(name, QRType::CSYNC, class, ttl, 0), // rdlength will be set later
+            soa_serial,
+            flags,
+            type_bit_maps,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSX25Record {
+    pub preamble: DNSRecordPreamble,
+    pub psdn_address: String, // X.121 PSDN address
+}
+
+impl DNSX25Record {
+    pub fn new(name: String, class:QRClass, ttl: u32, psdn_address: String) -> Self {
+        DNSX25Record {
+            preamble: DNSRecordPreamble::new(name, QRType::X25, class, ttl, 0), // rdlength will be set later
+            psdn_address,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSISDNRecord {
+    pub preamble: DNSRecordPreamble,
+    pub address: String, // ISDN telephone number
+    pub subaddress: Option<String>, // Optional sub-address
+}
+
+impl DNSISDNRecord {
+    pub fn new(name: String, class:QRClass, ttl: u32, address: String, subaddress: Option<String>) -> Self {
+        DNSISDNRecord {
+            preamble: DNSRecordPreamble::new(name, QRType::ISDN, class, ttl, 0), // rdlength will be set later
+            address,
+            subaddress,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSRTRecord {
+    pub preamble: DNSRecordPreamble,
+    pub preference: u16, // Preference value
+    pub intermediate_host: String, // Host to route through
+}
+
+impl DNSRTRecord {
+    pub fn new(name: String, class:QRClass, ttl: u32, preference: u16, intermediate_host: String) -> Self {
+        DNSRTRecord {
+            preamble: DNSRecordPreamble::new(name, QRType::RT, class, ttl, 0), // rdlength will be set later
+            preference,
+            intermediate_host,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSTKEYRecord {
+    pub preamble: DNSRecordPreamble,
+    pub algorithm: String, // Name of the key agreement algorithm
+    pub inception: u32,    // Start of the key's validity, in seconds since the epoch
+    pub expiration: u32,   // End of the key's validity, in seconds since the epoch
+    pub mode: u16,         // Key agreement mode, e.g. 3 for GSS-API
+    pub error: u16,        // Extended RCODE covering TKEY processing
+    pub key_data: Vec<u8>, // Key exchange data
+    pub other_data: Vec<u8>, // Reserved for future use
+}
+
+impl DNSTKEYRecord {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(name: String, class:QRClass, ttl: u32, algorithm: String, inception: u32, expiration: u32, mode: u16, error: u16, key_data: Vec<u8>, other_data: Vec<u8>) -> Self {
+        DNSTKEYRecord {
+            preamble: DNSRecordPreamble::new(name, QRType::TKEY, class, ttl, 0), // rdlength will be set later
+            algorithm,
+            inception,
+            expiration,
+            mode,
+            error,
+            key_data,
+            other_data,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSSMIMEARecord {
+    pub preamble: DNSRecordPreamble,
+    pub usage: u8,         // How the certificate is to be verified, e.g. 3 for domain-issued
+    pub selector: u8,      // Which part of the certificate is matched: 0 full, 1 public key
+    pub matching_type: u8, // How the data is presented: 0 exact, 1 SHA-256, 2 SHA-512
+    pub cert_association_data: Vec<u8>, // The certificate data, or its hash
+}
+
+impl DNSSMIMEARecord {
+    pub fn new(name: String, class:QRClass, ttl: u32, usage: u8, selector: u8, matching_type: u8, cert_association_data: Vec<u8>) -> Self {
+        DNSSMIMEARecord {
+            preamble: DNSRecordPreamble::new(name, QRType::SMIMEA, class, ttl, 0), // rdlength will be set later
+            usage,
+            selector,
+            matching_type,
+            cert_association_data,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSDSRecord {
+    pub preamble: DNSRecordPreamble,
+    pub key_tag: u16,     // Tag of the child zone's DNSKEY
+    pub algorithm: u8,    // Algorithm of that key
+    pub digest_type: u8,  // Digest algorithm, e.g. 2 for SHA-256
+    pub digest: Vec<u8>,  // Digest of the key's owner name and RDATA
+}
+
+impl DNSDSRecord {
+    pub fn new(name: String, class:QRClass, ttl: u32, key_tag: u16, algorithm: u8, digest_type: u8, digest: Vec<u8>) -> Self {
+        DNSDSRecord {
+            preamble: DNSRecordPreamble::new(name, QRType::DS, class, ttl, 0), // rdlength will be set later
+            key_tag,
+            algorithm,
+            digest_type,
+            digest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSRRSIGRecord {
+    pub preamble: DNSRecordPreamble,
+    pub type_covered: QRType, // Type of the RRset the signature covers
+    pub algorithm: u8,        // Signing algorithm, e.g. 8 for RSA/SHA-256
+    pub labels: u8,           // Labels in the owner name, fewer if it was a wildcard
+    pub original_ttl: u32,    // TTL of the RRset as signed
+    pub expiration: u32,      // End of the validity period, in seconds since the epoch
+    pub inception: u32,       // Start of the validity period, in seconds since the epoch
+    pub key_tag: u16,         // Tag of the DNSKEY that made the signature
+    pub signer_name: String,  // Zone of the signing key
+    pub signature: Vec<u8>,   // The signature itself
+}
+
+impl DNSRRSIGRecord {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(name: String, class:QRClass, ttl: u32, type_covered: QRType, algorithm: u8, labels: u8, original_ttl: u32, expiration: u32, inception: u32, key_tag: u16, signer_name: String, signature: Vec<u8>) -> Self {
+        DNSRRSIGRecord {
+            preamble: DNSRecordPreamble::new(name, QRType::RRSIG, class, ttl, 0), // rdlength will be set later
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer_name,
+            signature,
+        }
+    }
+    /// Write the RDATA fields before the signature, which are themselves
+    /// covered by it
+    pub fn write_signed_fields(&self, buffer: &mut BytePacketBuffer) -> Result<(), std::io::Error> {
+        buffer.write_u16(self.type_covered.to_u16())?;
+        buffer.write_u8(self.algorithm)?;
+        buffer.write_u8(self.labels)?;
+        buffer.write_u32(self.original_ttl)?;
+        buffer.write_u32(self.expiration)?;
+        buffer.write_u32(self.inception)?;
+        buffer.write_u16(self.key_tag)?;
+        buffer.write_qname(&self.signer_name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSDNSKEYRecord {
+    pub preamble: DNSRecordPreamble,
+    pub flags: u16,      // 256 for a zone key, 257 if also a secure entry point
+    pub protocol: u8,    // Always 3
+    pub algorithm: u8,   // Algorithm of the key, e.g. 13 for ECDSA P-256/SHA-256
+    pub public_key: Vec<u8>, // The key material, in the algorithm's format
+}
+
+impl DNSDNSKEYRecord {
+    pub fn new(name: String, class:QRClass, ttl: u32, flags: u16, protocol: u8, algorithm: u8, public_key: Vec<u8>) -> Self {
+        DNSDNSKEYRecord {
+            preamble: DNSRecordPreamble::new(name, QRType::DNSKEY, class, ttl, 0), // rdlength will be set later
+            flags,
+            protocol,
+            algorithm,
+            public_key,
+        }
+    }
+    /// The key tag RRSIG and DS records identify this key by (RFC 4034
+    /// appendix B)
+    pub fn key_tag(&self) -> u16 {
+        let mut rdata = vec![(self.flags >> 8) as u8, self.flags as u8, self.protocol, self.algorithm];
+        rdata.extend_from_slice(&self.public_key);
+        let mut sum: u32 = 0;
+        for (i, byte) in rdata.iter().enumerate() {
+            sum += if i % 2 == 0 { (*byte as u32) << 8 } else { *byte as u32 };
+        }
+        sum += (sum >> 16) & 0xFFFF;
+        (sum & 0xFFFF) as u16
+    }
+}
+
+/// EDNS option code for Client Subnet (RFC 7871)
+pub const EDNS_CLIENT_SUBNET: u16 = 8;
+// The DO bit within the OPT record's TTL field
+const EDNS_DO_BIT: u32 = 0x8000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EDNSOption {
+    pub code: u16,     // Option code, e.g. 8 for Client Subnet
+    pub data: Vec<u8>, // Option data, interpreted according to the code
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DNSOPTRecord {
+    pub preamble: DNSRecordPreamble,
+    pub options: Vec<EDNSOption>, // EDNS options carried in RDATA
+}
+
+impl DNSOPTRecord {
+    // The OPT record is owned by the root and reuses CLASS for the
+    // requestor's UDP payload size; TTL holds the extended RCODE and flags
+    pub fn new(udp_payload_size: u16, options: Vec<EDNSOption>) -> Self {
+        DNSOPTRecord {
+            preamble: DNSRecordPreamble::new(String::new(), QRType::OPT, QRClass::UNKNOWN(udp_payload_size), 0, 0), // rdlength will be set later
+            options,
+        }
+    }
+    pub fn udp_payload_size(&self) -> u16 {
+        QRClass::to_u16(&self.preamble.class)
+    }
+    /// The DO bit (RFC 3225): whether the sender wants DNSSEC records
+    pub fn dnssec_ok(&self) -> bool {
+        self.preamble.ttl & EDNS_DO_BIT != 0
+    }
+    pub fn set_dnssec_ok(&mut self, dnssec_ok: bool) {
+        if dnssec_ok {
+            self.preamble.ttl |= EDNS_DO_BIT;
+        } else {
+            self.preamble.ttl &= !EDNS_DO_BIT;
+        }
+    }
+    /// The Client Subnet option, if present and well formed
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        self.options.iter()
+            .find(|option| option.code == EDNS_CLIENT_SUBNET)
+            .and_then(|option| ClientSubnet::from_option(option).ok())
+    }
+}
+
+/// An EDNS Client Subnet (RFC 7871): the network a query is asked on
+/// behalf of, and in responses the scope the answer is valid for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientSubnet {
+    pub address: IpAddr,
+    pub source_prefix: u8, // Leading bits of the address that are significant
+    pub scope_prefix: u8,  // Leading bits the answer covers; 0 in queries
+}
+
+impl ClientSubnet {
+    // Constructor for a query's subnet; bits past the prefix are cleared
+    pub fn new(address: IpAddr, source_prefix: u8) -> Result<Self, std::io::Error> {
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if source_prefix > max {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("prefix /{} is too long for {}", source_prefix, address)));
+        }
+        let address = match address {
+            IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) & u32::MAX.checked_shl(32 - source_prefix as u32).unwrap_or(0))),
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & u128::MAX.checked_shl(128 - source_prefix as u32).unwrap_or(0))),
+        };
+        Ok(ClientSubnet { address, source_prefix, scope_prefix: 0 })
+    }
+    /// Encode as option 8: family, source and scope prefix lengths, then
+    /// only as many address bytes as the source prefix covers
+    pub fn to_option(&self) -> EDNSOption {
+        let (family, octets): (u16, Vec<u8>) = match self.address {
+            IpAddr::V4(ip) => (1, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (2, ip.octets().to_vec()),
+        };
+        let mut data = Vec::new();
+        data.extend_from_slice(&family.to_be_bytes());
+        data.push(self.source_prefix);
+        data.push(self.scope_prefix);
+        data.extend_from_slice(&octets[..self.source_prefix.div_ceil(8) as usize]);
+        EDNSOption { code: EDNS_CLIENT_SUBNET, data }
+    }
+    pub fn from_option(option: &EDNSOption) -> Result<Self, std::io::Error> {
+        let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Client Subnet option {}", message));
+        if option.code != EDNS_CLIENT_SUBNET || option.data.len() < 4 {
+            return Err(invalid("is malformed"));
+        }
+        let family = u16::from_be_bytes([option.data[0], option.data[1]]);
+        let source_prefix = option.data[2];
+        let scope_prefix = option.data[3];
+        let address_bytes = &option.data[4..];
+        if address_bytes.len() != source_prefix.div_ceil(8) as usize {
+            return Err(invalid("address length does not match its prefix"));
+        }
+        let address = match family {
+            1 if address_bytes.len() <= 4 => {
+                let mut octets = [0u8; 4];
+                octets[..address_bytes.len()].copy_from_slice(address_bytes);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            2 if address_bytes.len() <= 16 => {
+                let mut octets = [0u8; 16];
+                octets[..address_bytes.len()].copy_from_slice(address_bytes);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(invalid("has an unsupported family or prefix")),
+        };
+        Ok(ClientSubnet { address, source_prefix, scope_prefix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write `record` and read it back, returning the bytes written too
+    fn round_trip(record: &DNSRecord) -> (Vec<u8>, DNSRecord) {
+        let mut buffer = BytePacketBuffer::new();
+        record.write(&mut buffer).unwrap();
+        let bytes = buffer.buf[0..buffer.pos].to_vec();
+        buffer.seek(0).unwrap();
+        let read = DNSRecord::read_with_mode(&mut buffer, ParseMode::Strict, &mut Vec::new()).unwrap();
+        (bytes, read)
+    }
+
+    // The bytes of a record named by the root with the given type and RDATA
+    fn wire(rtype: u16, rdata: &[u8]) -> BytePacketBuffer {
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&rtype.to_be_bytes());
+        bytes.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+        bytes.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(rdata);
+        let mut buffer = BytePacketBuffer::with_size(bytes.len());
+        buffer.buf.copy_from_slice(&bytes);
+        buffer
+    }
+
+    #[test]
+    fn csync_round_trips_with_its_type_bitmap() {
+        let record = DNSRecord::CSYNC(DNSCSYNCRecord::new("example.com".to_string(), QRClass::IN, 3600, 66, 3, vec![QRType::A, QRType::AAAA]));
+        let (bytes, read) = round_trip(&record);
+        // Serial, flags, then window 0 with a 4 byte bitmap: A is bit 1, AAAA bit 28
+        assert_eq!(&bytes[bytes.len() - 12..], &[0, 0, 0, 66, 0, 3, 0, 4, 0x40, 0, 0, 0x08]);
+        match read {
+            DNSRecord::CSYNC(csync) => {
+                assert_eq!(csync.soa_serial, 66);
+                assert_eq!(csync.flags, 3);
+                assert_eq!(csync.type_bit_maps, vec![QRType::A, QRType::AAAA]);
+            }
+            other => panic!("expected a CSYNC record, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn type_bitmap_overrunning_the_rdata_is_invalid() {
+        // The bitmap claims 4 bytes but the RDATA ends after 2, with more of
+        // the message following that it mustn't be read from
+        let mut buffer = wire(62, &[0, 0, 0, 66, 0, 3, 0, 4, 0x40, 0]);
+        buffer.buf.extend_from_slice(&[0xff; 8]);
+        let error = DNSRecord::read(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        // A lone window number with no length
+        let mut buffer = wire(62, &[0, 0, 0, 66, 0, 3, 0]);
+        buffer.buf.extend_from_slice(&[0xff; 8]);
+        let error = DNSRecord::read(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn edns_option_overrunning_the_rdata_is_invalid() {
+        // An OPT record whose only option claims 8 bytes of data but whose
+        // RDATA holds 2 of them
+        let mut buffer = wire(41, &[0, 8, 0, 8, 0, 1]);
+        buffer.buf.extend_from_slice(&[0xff; 8]);
+        let error = DNSRecord::read(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    fn presentation(rtype: QRType, rdata: &str) -> Result<DNSRecord,std::io::Error> {
+        DNSRecord::from_presentation("example.com.", 300, QRClass::IN, rtype, rdata)
+    }
+
+    #[test]
+    fn presentation_format_of_each_supported_type() {
+        assert_eq!(presentation(QRType::A, "192.0.2.1").unwrap(), DNSRecord::A(DNSARecord::new("example.com".to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, 1))));
+        assert_eq!(presentation(QRType::AAAA, "2001:db8::1").unwrap(), DNSRecord::AAAA(DNSAAAARecord::new("example.com".to_string(), QRClass::IN, 300, "2001:db8::1".parse().unwrap())));
+        assert_eq!(presentation(QRType::CNAME, "www.example.net.").unwrap(), DNSRecord::CNAME(DNSCNAMERecord::new("example.com".to_string(), QRClass::IN, 300, "www.example.net".to_string())));
+        assert_eq!(presentation(QRType::NS, "ns1.example.com.").unwrap(), DNSRecord::NS(DNSNSRecord::new("example.com".to_string(), QRClass::IN, 300, "ns1.example.com".to_string())));
+        assert_eq!(presentation(QRType::MX, "10 mail.example.com.").unwrap(), DNSRecord::MX(DNSMXRecord::new("example.com".to_string(), QRClass::IN, 300, 10, "mail.example.com".to_string())));
+        assert_eq!(presentation(QRType::SRV, "1 2 5060 sip.example.com.").unwrap(), DNSRecord::SRV(DNSSRVRecord::new("example.com".to_string(), QRClass::IN, 300, 1, 2, 5060, "sip.example.com".to_string())));
+        assert!(presentation(QRType::MX, "10").is_err());
+        assert!(presentation(QRType::A, "192.0.2").is_err());
+    }
+
+    #[test]
+    fn txt_presentation_is_encoded_as_bytes() {
+        // Two strings, the second holding a space, an escaped quote and a
+        // byte given in decimal
+        let record = presentation(QRType::TXT, r#"hello "a \"b\" \200""#).unwrap();
+        let (bytes, read) = round_trip(&record);
+        assert_eq!(&bytes[bytes.len() - 14..], &[5, b'h', b'e', b'l', b'l', b'o', 7, b'a', b' ', b'"', b'b', b'"', b' ', 200]);
+        assert_eq!(read, record);
+
+        // Non-ASCII text counts its UTF-8 bytes, not its characters
+        let (bytes, _) = round_trip(&presentation(QRType::TXT, "é").unwrap());
+        assert_eq!(&bytes[bytes.len() - 3..], &[2, 0xC3, 0xA9]);
+
+        // A string of 200 bytes has a length byte above 127
+        let (bytes, _) = round_trip(&presentation(QRType::TXT, &"x".repeat(200)).unwrap());
+        assert_eq!(bytes[bytes.len() - 201], 200);
+
+        let error = presentation(QRType::TXT, &"x".repeat(256)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        let error = presentation(QRType::TXT, &"\\200".repeat(256)).unwrap_err();
+        assert!(error.to_string().contains("256 bytes"));
+    }
+
+    // Known-good vectors: the type, the RDATA as it appears on the wire and
+    // the record it holds, owned by the root with class IN and TTL 3600.
+    // Each type has at least two.
+    fn vectors() -> Vec<(u16, Vec<u8>, DNSRecord)> {
+        let (name, class, ttl) = (String::new, QRClass::IN, 3600);
+        let mut opt = DNSOPTRecord::new(1, Vec::new());
+        opt.preamble.ttl = ttl;
+        let mut cookie = DNSOPTRecord::new(1, vec![EDNSOption { code: 10, data: vec![1, 2, 3, 4, 5, 6, 7, 8] }]);
+        cookie.preamble.ttl = ttl;
+        vec![
+            (1, vec![192, 0, 2, 1], DNSRecord::A(DNSARecord::new(name(), class, ttl, Ipv4Addr::new(192, 0, 2, 1)))),
+            (1, vec![255, 255, 255, 255], DNSRecord::A(DNSARecord::new(name(), class, ttl, Ipv4Addr::BROADCAST))),
+            (2, [&b"\x02ns\x07example"[..], &[0]].concat(), DNSRecord::NS(DNSNSRecord::new(name(), class, ttl, "ns.example".to_string()))),
+            (2, vec![0], DNSRecord::NS(DNSNSRecord::new(name(), class, ttl, String::new()))),
+            (5, b"\x01a\x00".to_vec(), DNSRecord::CNAME(DNSCNAMERecord::new(name(), class, ttl, "a".to_string()))),
+            (5, b"\x03www\x07example\x00".to_vec(), DNSRecord::CNAME(DNSCNAMERecord::new(name(), class, ttl, "www.example".to_string()))),
+            (6, [&b"\x02ns\x00\x05admin\x00"[..], &[0, 0, 0, 1, 0, 0, 0x0e, 0x10, 0, 0, 0x02, 0x58, 0, 0x01, 0x51, 0x80, 0, 0, 0x01, 0x2c]].concat(),
+                DNSRecord::SOA(DNSSOARecord::new(name(), class, ttl, "ns".to_string(), "admin".to_string(), 1, 3600, 600, 86400, 300))),
+            (6, [&[0, 0][..], &[0xff; 20]].concat(), DNSRecord::SOA(DNSSOARecord::new(name(), class, ttl, String::new(), String::new(), u32::MAX, u32::MAX, u32::MAX, u32::MAX, u32::MAX))),
+            (12, b"\x04host\x00".to_vec(), DNSRecord::PTR(DNSPTRRecord::new(name(), class, ttl, "host".to_string()))),
+            (12, b"\x011\x012\x07in-addr\x04arpa\x00".to_vec(), DNSRecord::PTR(DNSPTRRecord::new(name(), class, ttl, "1.2.in-addr.arpa".to_string()))),
+            (15, b"\x00\x0a\x04mail\x00".to_vec(), DNSRecord::MX(DNSMXRecord::new(name(), class, ttl, 10, "mail".to_string()))),
+            (15, vec![0xff, 0xff, 0], DNSRecord::MX(DNSMXRecord::new(name(), class, ttl, u16::MAX, String::new()))),
+            // TXT keeps the RDATA as it is, one char per byte, lengths included
+            (16, b"\x05hello".to_vec(), DNSRecord::TXT(DNSTXTRecord::new(name(), class, ttl, "\u{5}hello".to_string()))),
+            (16, vec![0, 2, 0xc3, 0xa9], DNSRecord::TXT(DNSTXTRecord::new(name(), class, ttl, "\u{0}\u{2}\u{c3}\u{a9}".to_string()))),
+            (19, b"\x05310 6".to_vec(), DNSRecord::X25(DNSX25Record::new(name(), class, ttl, "310 6".to_string()))),
+            (19, vec![0], DNSRecord::X25(DNSX25Record::new(name(), class, ttl, String::new()))),
+            (20, b"\x03150".to_vec(), DNSRecord::ISDN(DNSISDNRecord::new(name(), class, ttl, "150".to_string(), None))),
+            (20, b"\x03150\x03004".to_vec(), DNSRecord::ISDN(DNSISDNRecord::new(name(), class, ttl, "150".to_string(), Some("004".to_string())))),
+            (21, b"\x00\x0a\x05relay\x00".to_vec(), DNSRecord::RT(DNSRTRecord::new(name(), class, ttl, 10, "relay".to_string()))),
+            (21, vec![0, 0, 0], DNSRecord::RT(DNSRTRecord::new(name(), class, ttl, 0, String::new()))),
+            (28, [&[0x20, 0x01, 0x0d, 0xb8][..], &[0; 11], &[1]].concat(), DNSRecord::AAAA(DNSAAAARecord::new(name(), class, ttl, "2001:db8::1".parse().unwrap()))),
+            (28, vec![0; 16], DNSRecord::AAAA(DNSAAAARecord::new(name(), class, ttl, Ipv6Addr::UNSPECIFIED))),
+            (33, b"\x00\x01\x00\x02\x13\xc4\x03sip\x00".to_vec(), DNSRecord::SRV(DNSSRVRecord::new(name(), class, ttl, 1, 2, 5060, "sip".to_string()))),
+            (33, vec![0; 7], DNSRecord::SRV(DNSSRVRecord::new(name(), class, ttl, 0, 0, 0, String::new()))),
+            (41, Vec::new(), DNSRecord::OPT(opt)),
+            (41, vec![0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8], DNSRecord::OPT(cookie)),
+            (43, [&[0xd9, 0x60, 13, 2][..], &[0xab; 32]].concat(), DNSRecord::DS(DNSDSRecord::new(name(), class, ttl, 55648, 13, 2, vec![0xab; 32]))),
+            (43, vec![0, 1, 8, 1, 0xaa], DNSRecord::DS(DNSDSRecord::new(name(), class, ttl, 1, 8, 1, vec![0xaa]))),
+            (46, [&[0, 1, 13, 2, 0, 0, 0x0e, 0x10, 0x4c, 0x88, 0xb5, 0x57, 0x4c, 0x63, 0xc9, 0x37, 0xd9, 0x60][..], b"\x07example\x00", &[1, 2, 3]].concat(),
+                DNSRecord::RRSIG(DNSRRSIGRecord::new(name(), class, ttl, QRType::A, 13, 2, 3600, 1284027735, 1281607991, 55648, "example".to_string(), vec![1, 2, 3]))),
+            (46, [&[0, 48, 8, 0][..], &[0; 14], &[0]].concat(), DNSRecord::RRSIG(DNSRRSIGRecord::new(name(), class, ttl, QRType::DNSKEY, 8, 0, 0, 0, 0, 0, String::new(), Vec::new()))),
+            (48, [&[1, 1, 3, 13][..], &[0x42; 64]].concat(), DNSRecord::DNSKEY(DNSDNSKEYRecord::new(name(), class, ttl, 257, 3, 13, vec![0x42; 64]))),
+            (48, vec![1, 0, 3, 8], DNSRecord::DNSKEY(DNSDNSKEYRecord::new(name(), class, ttl, 256, 3, 8, Vec::new()))),
+            (53, [&[3, 1, 1][..], &[0xcd; 32]].concat(), DNSRecord::SMIMEA(DNSSMIMEARecord::new(name(), class, ttl, 3, 1, 1, vec![0xcd; 32]))),
+            (53, vec![0, 0, 0], DNSRecord::SMIMEA(DNSSMIMEARecord::new(name(), class, ttl, 0, 0, 0, Vec::new()))),
+            (62, vec![0, 0, 0, 66, 0, 3, 0, 4, 0x40, 0, 0, 0x08], DNSRecord::CSYNC(DNSCSYNCRecord::new(name(), class, ttl, 66, 3, vec![QRType::A, QRType::AAAA]))),
+            (62, vec![0xff, 0xff, 0xff, 0xff, 0, 0], DNSRecord::CSYNC(DNSCSYNCRecord::new(name(), class, ttl, u32::MAX, 0, Vec::new()))),
+            (249, [&b"\x08gss-tsig\x00"[..], &[0, 0, 0, 1, 0, 0, 0, 2, 0, 3, 0, 0, 0, 2, 0xaa, 0xbb, 0, 1, 0xcc]].concat(),
+                DNSRecord::TKEY(DNSTKEYRecord::new(name(), class, ttl, "gss-tsig".to_string(), 1, 2, 3, 0, vec![0xaa, 0xbb], vec![0xcc]))),
+            (249, vec![0; 17], DNSRecord::TKEY(DNSTKEYRecord::new(name(), class, ttl, String::new(), 0, 0, 0, 0, Vec::new(), Vec::new()))),
+            (257, b"\x00\x05issueca.example".to_vec(), DNSRecord::CAA(DNSCAARecord::new(name(), class, ttl, 0, "issue".to_string(), "ca.example".to_string()))),
+            (257, b"\x80\x03tag".to_vec(), DNSRecord::CAA(DNSCAARecord::new(name(), class, ttl, 128, "tag".to_string(), String::new()))),
+            (99, vec![1, 2, 3], DNSRecord::UNKNOWN(DNSUNKNOWNRecord::new(name(), QRType::UNKNOWN(99), class, ttl, vec![1, 2, 3]))),
+            (65280, Vec::new(), DNSRecord::UNKNOWN(DNSUNKNOWNRecord::new(name(), QRType::UNKNOWN(65280), class, ttl, Vec::new()))),
+        ]
+    }
+
+    // Deliberately malformed RDATA for each type, with the error reading it gives
+    fn malformed_vectors() -> Vec<(u16, Vec<u8>, std::io::ErrorKind)> {
+        use std::io::ErrorKind::{InvalidData, UnexpectedEof};
+        vec![
+            (1, vec![192, 0, 2], InvalidData), // Too short for an address
+            (2, b"\x03ns".to_vec(), UnexpectedEof), // Label running past the RDATA
+            (5, vec![0xc0, 11], InvalidData), // Compression pointer to itself
+            (6, vec![0; 21], InvalidData), // Too short for the five counters
+            (12, b"\x01a\x00\x00".to_vec(), InvalidData), // A byte left after the name
+            (15, vec![0, 10], InvalidData), // Preference without an exchange
+            (16, Vec::new(), InvalidData), // No string at all
+            (19, b"\x05310".to_vec(), UnexpectedEof), // String longer than the RDATA
+            (20, b"\x03150\x05".to_vec(), UnexpectedEof), // Sub-address longer than the RDATA
+            (21, vec![0, 10], InvalidData), // Preference without a host
+            (28, vec![0; 15], InvalidData), // Too short for an address
+            (33, vec![0; 6], InvalidData), // Port without a target
+            (41, vec![0, 10, 0, 8, 1], InvalidData), // Option longer than the RDATA
+            (43, vec![0, 1, 8], InvalidData), // No digest type
+            (46, [&[0, 1, 13, 2][..], &[0; 14], b"\x05ex"].concat(), UnexpectedEof), // Signer running past the RDATA
+            (48, vec![1, 1, 3], InvalidData), // No algorithm
+            (53, vec![3, 1], InvalidData), // No matching type
+            (62, vec![0, 0, 0, 1, 0, 0, 0, 0], InvalidData), // Empty type bitmap
+            (249, [&b"\x00"[..], &[0; 14], &[0, 5, 0xaa]].concat(), UnexpectedEof), // Key longer than the RDATA
+            (257, b"\x00\x05is".to_vec(), InvalidData), // Tag longer than the RDATA
+        ]
+    }
+
+    #[test]
+    fn known_good_vectors_read_and_write_byte_for_byte() {
+        let vectors = vectors();
+        // Every data type, and the OPT and TKEY meta types we also implement
+        let implemented = || QRType::all_record_types().iter().chain(&[QRType::OPT, QRType::TKEY]);
+        for rtype in implemented() {
+            assert!(vectors.iter().filter(|(code, _, _)| *code == rtype.to_u16()).count() >= 2, "fewer than two vectors for {:?}", rtype);
+        }
+
+        for (rtype, rdata, expected) in vectors {
+            let mut buffer = wire(rtype, &rdata);
+            let read = DNSRecord::read_with_mode(&mut buffer, ParseMode::Strict, &mut Vec::new()).unwrap();
+            assert_eq!(read, expected, "reading {:?}", rdata);
+            assert!(buffer.is_eof());
+
+            let mut written = BytePacketBuffer::new();
+            expected.write(&mut written).unwrap();
+            assert_eq!(&written.buf[0..written.pos], &buffer.buf[..], "writing {:?}", expected);
+        }
+    }
+
+    #[test]
+    fn all_record_types_lists_every_implemented_data_type() {
+        // One variant per record struct: these 18, OPT, TKEY and UNKNOWN
+        let types = QRType::all_record_types();
+        assert_eq!(types.len(), 18);
+        assert_eq!(types.iter().collect::<std::collections::HashSet<_>>().len(), types.len());
+
+        // Each reads into a variant of its own rather than as UNKNOWN
+        let vectors = vectors();
+        let mut variants = std::collections::HashSet::new();
+        for rtype in types {
+            assert!(!rtype.is_pseudo() && !rtype.is_query_only(), "{:?} is not a data type", rtype);
+            let (_, rdata, _) = vectors.iter().find(|(code, _, _)| *code == rtype.to_u16()).unwrap();
+            let read = DNSRecord::read_with_mode(&mut wire(rtype.to_u16(), rdata), ParseMode::Strict, &mut Vec::new()).unwrap();
+            assert!(!matches!(read, DNSRecord::UNKNOWN(_)), "{:?} is read as UNKNOWN", rtype);
+            variants.insert(std::mem::discriminant(&read));
+        }
+        assert_eq!(variants.len(), types.len());
+    }
+
+    #[test]
+    fn malformed_vectors_are_rejected() {
+        let vectors = malformed_vectors();
+        for rtype in QRType::all_record_types().iter().chain(&[QRType::OPT, QRType::TKEY]) {
+            assert!(vectors.iter().any(|(code, _, _)| *code == rtype.to_u16()), "no malformed vector for {:?}", rtype);
+        }
+
+        for (rtype, rdata, kind) in vectors {
+            let result = DNSRecord::read_with_mode(&mut wire(rtype, &rdata), ParseMode::Strict, &mut Vec::new());
+            assert_eq!(result.map_err(|e| e.kind()).unwrap_err(), kind, "reading type {} RDATA {:?}", rtype, rdata);
+        }
+
+        // Unknown types are opaque, so can only be cut short
+        let mut buffer = wire(99, &[1, 2, 3]);
+        buffer.buf.pop();
+        let error = DNSRecord::read_with_mode(&mut buffer, ParseMode::Strict, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::message::header::{CDFlag, OpCode};
+use crate::message::records::{DNSRecord, DNSUNKNOWNRecord};
+use crate::message::{DNSPacket, DNSQuestion, QRClass, QRType};
+
+/// Builds a dynamic update message (RFC 2136).
+///
+/// UPDATE reuses the sections of a query: the question section holds the
+/// zone being updated, the answer section its prerequisites and the
+/// authority section the changes. What a record means there depends on its
+/// class and TTL, which the methods below set: class ANY or NONE with empty
+/// RDATA stands for a whole RRset or name, and deletions have TTL 0.
+#[derive(Debug, Clone)]
+pub struct UpdateBuilder {
+    packet: DNSPacket,
+}
+
+impl UpdateBuilder {
+    // Constructor for an update to the IN-class zone `zone`
+    pub fn new(zone: &str) -> Self {
+        let mut packet = DNSPacket::new();
+        packet.header.opcode = OpCode::Update;
+        // The bits after the opcode are zero in an update, CD included
+        packet.header.cd = CDFlag::Enabled;
+        packet.question.add_question(DNSQuestion::new(zone.to_string(), QRType::SOA, QRClass::IN));
+        UpdateBuilder { packet }
+    }
+    /// Require an RRset of the type to exist at `name`, whatever its records
+    pub fn require_rrset_exists(mut self, name: &str, rtype: QRType) -> Self {
+        self.packet.answer.add_answer(empty_record(name, rtype, QRClass::ANY));
+        self
+    }
+    /// Require `record`'s RRset to exist with exactly this record's data
+    /// among it; the record's own class is kept
+    pub fn require_record(mut self, mut record: DNSRecord) -> Self {
+        record.preamble_mut().ttl = 0;
+        self.packet.answer.add_answer(record);
+        self
+    }
+    /// Require there to be no RRset of the type at `name`
+    pub fn require_rrset_absent(mut self, name: &str, rtype: QRType) -> Self {
+        self.packet.answer.add_answer(empty_record(name, rtype, QRClass::NONE));
+        self
+    }
+    /// Require `name` to own at least one record
+    pub fn require_name_in_use(mut self, name: &str) -> Self {
+        self.packet.answer.add_answer(empty_record(name, QRType::ANY, QRClass::ANY));
+        self
+    }
+    /// Require `name` to own no records
+    pub fn require_name_absent(mut self, name: &str) -> Self {
+        self.packet.answer.add_answer(empty_record(name, QRType::ANY, QRClass::NONE));
+        self
+    }
+    /// Add `record` to its RRset
+    pub fn add_record(mut self, record: DNSRecord) -> Self {
+        self.packet.authority.add_record(record);
+        self
+    }
+    /// Delete the record matching `record`'s name, type and data
+    pub fn delete_record(mut self, mut record: DNSRecord) -> Self {
+        let preamble = record.preamble_mut();
+        preamble.class = QRClass::NONE;
+        preamble.ttl = 0;
+        self.packet.authority.add_record(record);
+        self
+    }
+    /// Delete the whole RRset of the type at `name`
+    pub fn delete_rrset(mut self, name: &str, rtype: QRType) -> Self {
+        self.packet.authority.add_record(empty_record(name, rtype, QRClass::ANY));
+        self
+    }
+    /// Delete every RRset at `name`
+    pub fn delete_name(mut self, name: &str) -> Self {
+        self.packet.authority.add_record(empty_record(name, QRType::ANY, QRClass::ANY));
+        self
+    }
+    /// Attach a record to the additional section, e.g. glue for added NS records
+    pub fn additional(mut self, record: DNSRecord) -> Self {
+        self.packet.additional.add_record(record);
+        self
+    }
+    pub fn build(self) -> DNSPacket {
+        self.packet
+    }
+}
+
+// A record with no RDATA, standing for an RRset or name in prerequisites
+// and deletions
+fn empty_record(name: &str, rtype: QRType, class: QRClass) -> DNSRecord {
+    DNSRecord::UNKNOWN(DNSUNKNOWNRecord::new(name.to_string(), rtype, class, 0, Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use crate::message::byte_packet_buffer::BytePacketBuffer;
+    use crate::message::records::DNSARecord;
+
+    fn encode(mut packet: DNSPacket) -> Vec<u8> {
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.buf[0..buffer.pos()].to_vec()
+    }
+
+    #[test]
+    fn add_a_record_update_encodes_per_rfc_2136() {
+        let mut update = UpdateBuilder::new("example.com")
+            .add_record(DNSRecord::A(DNSARecord::new("www.example.com".to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, 1))))
+            .build();
+        update.header.id = 0x1234;
+
+        let expected = [
+            // ID, opcode UPDATE (5), one zone, no prerequisites, one update, no additional
+            &[0x12, 0x34, 0x28, 0x00, 0, 1, 0, 0, 0, 1, 0, 0][..],
+            // Zone: example.com SOA IN
+            b"\x07example\x03com\x00", &[0, 6, 0, 1],
+            // Update: www.example.com A IN, TTL 300, 192.0.2.1
+            b"\x03www\x07example\x03com\x00", &[0, 1, 0, 1, 0, 0, 0x01, 0x2c, 0, 4, 192, 0, 2, 1],
+        ].concat();
+        assert_eq!(encode(update), expected);
+    }
+
+    #[test]
+    fn deletions_and_prerequisites_use_class_and_ttl_for_their_meaning() {
+        let update = UpdateBuilder::new("example.com")
+            .require_name_absent("new.example.com")
+            .delete_rrset("old.example.com", QRType::A)
+            .build();
+        let bytes = encode(update);
+        // Prerequisite: ANY class NONE, TTL 0, no RDATA
+        let prerequisite = [&b"\x03new\x07example\x03com\x00"[..], &[0, 255, 0, 254, 0, 0, 0, 0, 0, 0]].concat();
+        // Update: A class ANY, TTL 0, no RDATA
+        let deletion = [&b"\x03old\x07example\x03com\x00"[..], &[0, 1, 0, 255, 0, 0, 0, 0, 0, 0]].concat();
+        assert_eq!(&bytes[4..12], &[0, 1, 0, 1, 0, 1, 0, 0]);
+        assert_eq!(&bytes[bytes.len() - deletion.len() - prerequisite.len()..], [prerequisite, deletion].concat());
+    }
+}
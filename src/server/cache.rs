@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use crate::message::header::ADFlag;
+use crate::message::{records::DNSRecord, DNSPacket, QRType};
+use crate::rng::Rng;
+
+struct CacheEntry {
+    records: Vec<DNSRecord>, // The cached answer records
+    inserted: Instant, // When the entry was stored
+    expires: Instant, // When the entry stops being served
+    rotation: AtomicUsize, // Number of hits served, used for round-robin
+    authenticated: bool, // Whether the response had been validated with DNSSEC
+}
+
+/// A TTL-bounded cache of answer records keyed by name and type.
+pub struct Cache {
+    entries: HashMap<(String, QRType), CacheEntry>,
+    round_robin: bool,
+    no_cache_types: HashSet<QRType>,
+    rng: Rng,
+}
+
+impl Cache {
+    // Constructor for creating a new, empty Cache
+    pub fn new() -> Self {
+        Cache {
+            entries: HashMap::new(),
+            round_robin: false,
+            no_cache_types: HashSet::new(),
+            rng: Rng::from_entropy(),
+        }
+    }
+
+    /// Rotate the order of A records on each cache hit to spread load across
+    /// addresses. Off by default so responses are deterministic.
+    pub fn set_round_robin(&mut self, enabled: bool) {
+        self.round_robin = enabled;
+    }
+
+    /// Seed the generator choosing where each entry's round-robin rotation
+    /// starts, making the order of A records reproducible
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng = Rng::with_seed(seed);
+    }
+
+    /// Record types that are never stored, e.g. TXT records used for
+    /// short-lived challenges. Empty by default.
+    pub fn set_no_cache_types(&mut self, types: HashSet<QRType>) {
+        self.no_cache_types = types;
+    }
+
+    /// Store the answer records of a response to a question. The entry lives
+    /// for the response's `min_ttl`; empty or zero-TTL answers are not stored.
+    /// Pseudo-records are dropped, and query-only types are never stored.
+    pub fn insert(&mut self, qname: &str, qtype: QRType, response: &DNSPacket) {
+        if self.no_cache_types.contains(&qtype) || qtype.is_pseudo() || qtype.is_query_only() {
+            return;
+        }
+
+        let ttl = match response.min_ttl() {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return,
+        };
+        let records: Vec<DNSRecord> = response.answer.answers
+            .iter()
+            .filter(|record| !record.preamble().rtype.is_pseudo())
+            .cloned()
+            .collect();
+        if records.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let entry = CacheEntry {
+            records,
+            inserted: now,
+            expires: now + Duration::from_secs(ttl as u64),
+            // Start each entry at a random point so that clients don't all
+            // favour the first address
+            rotation: AtomicUsize::new(self.rng.next_u64() as usize),
+            authenticated: response.header.ad == ADFlag::Authenticated,
+        };
+        self.entries.insert((qname.to_lowercase(), qtype), entry);
+    }
+
+    /// Fetch the cached answer records for a question, if still fresh.
+    ///
+    /// The returned TTLs are reduced by the time the entry has spent in the
+    /// cache. Only a shared reference is needed, so lookups can run in
+    /// parallel under a read lock; expired entries are skipped, and left for
+    /// `sweep` or the next `insert` of the name to remove.
+    pub fn lookup(&self, qname: &str, qtype: QRType) -> Option<Vec<DNSRecord>> {
+        let key = (qname.to_lowercase(), qtype);
+        let now = Instant::now();
+
+        let entry = self.entries.get(&key)?;
+        if entry.expires <= now {
+            return None;
+        }
+
+        let elapsed = now.duration_since(entry.inserted).as_secs() as u32;
+        let mut records = entry.records.clone();
+        for record in records.iter_mut() {
+            let preamble = record.preamble_mut();
+            preamble.ttl = preamble.ttl.saturating_sub(elapsed);
+        }
+
+        if self.round_robin {
+            rotate_a_records(&mut records, entry.rotation.fetch_add(1, Ordering::Relaxed));
+        }
+
+        Some(records)
+    }
+
+    /// Fetch the cached answer records for a question as a response, marked
+    /// authenticated if the one they were cached from was
+    pub fn lookup_response(&self, qname: &str, qtype: QRType) -> Option<DNSPacket> {
+        let mut packet = DNSPacket::new();
+        packet.answer.answers = self.lookup(qname, qtype)?;
+        if self.entries.get(&(qname.to_lowercase(), qtype)).is_some_and(|entry| entry.authenticated) {
+            packet.header.ad = ADFlag::Authenticated;
+        }
+        Some(packet)
+    }
+
+    /// Remove every expired entry, including those for names never queried
+    /// again, which would otherwise be kept forever.
+    pub fn sweep(&mut self) {
+        self.sweep_at(Instant::now());
+    }
+
+    /// Remove every entry expired as of `now`
+    pub fn sweep_at(&mut self, now: Instant) {
+        self.entries.retain(|_, entry| entry.expires > now);
+    }
+
+    /// Number of entries currently held, expired or not
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Rotate the A records amongst their own positions, leaving any other
+// records (such as a leading CNAME) where they are.
+fn rotate_a_records(records: &mut [DNSRecord], by: usize) {
+    let positions: Vec<usize> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, record)| matches!(record, DNSRecord::A(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if positions.len() < 2 {
+        return;
+    }
+
+    let mut a_records: Vec<DNSRecord> = positions.iter().map(|&i| records[i].clone()).collect();
+    a_records.rotate_left(by % positions.len());
+    for (&i, record) in positions.iter().zip(a_records) {
+        records[i] = record;
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Duration;
+use crate::message::{records::ClientSubnet, QRType};
+#[cfg(feature = "dnssec")]
+use crate::message::records::DNSDSRecord;
+
+/// How queries are carried to upstream servers
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Upstream {
+    #[default]
+    Plain, // UDP, retrying over TCP when a response is truncated
+    #[cfg(feature = "dot")]
+    Tls(String), // DNS over TLS, checking certificates against this server name
+    #[cfg(feature = "doh")]
+    Https(String), // DNS over HTTPS to this endpoint URL, which every query is forwarded to
+}
+
+/// Options controlling how a `DNSResolver` behaves.
+#[derive(Debug, Clone)]
+pub struct ResolverConfig {
+    pub timeout: Duration, // How long to wait for an upstream server to reply
+    pub retries: u32, // How many times a timed out upstream query is retried
+    pub retry_backoff: Duration, // Delay before the first retry
+    pub max_retry_backoff: Duration, // Upper bound for the doubling retry delay
+    pub round_robin: bool, // Rotate A records across successive cache hits
+    pub no_cache_types: HashSet<QRType>, // Record types that are never cached
+    pub recursion_available: bool, // Recurse for names outside the loaded zones
+    pub dns64_prefix: Option<Ipv6Addr>, // /96 prefix to synthesize AAAA records in, if DNS64 is on
+    pub max_records: usize, // Most answer records a single resolution may accumulate
+    pub max_response_size: usize, // Most bytes the answer of a single resolution may encode to
+    pub forwarders: Vec<(IpAddr, u16)>, // Upstreams to forward queries to instead of recursing, in order of preference
+    pub forwarder_load_balance: bool, // Start at a random forwarder rather than the first
+    pub sweep_interval: Option<Duration>, // How often `run` purges expired cache entries
+    pub query_source_addr: Option<IpAddr>, // Local address plain upstream queries are sent from, over UDP and TCP
+    pub upstream: Upstream, // How upstream queries are carried
+    pub preserve_question_bytes: bool, // Echo the request's question section byte for byte
+    pub rng_seed: Option<u64>, // Seed for random choices, for reproducible behaviour
+    pub client_subnet: Option<ClientSubnet>, // EDNS Client Subnet sent with upstream queries
+    pub ttl_override: Option<u32>, // TTL every served record is rewritten to, if set
+    pub edns_udp_size: u16, // Largest UDP message we advertise, receive and serve
+    pub allow_ipv6_upstream: bool, // Follow referrals to name servers with only IPv6 glue
+    pub partial_results: bool, // On a recursion timeout, answer SERVFAIL with the last referral
+    pub max_concurrent_lookups: usize, // Most upstream resolutions in flight at once
+    pub recurse_without_rd: bool, // Recurse even for queries with the RD flag clear
+    #[cfg(feature = "dnssec")]
+    pub dnssec_validation: bool, // Validate recursive answers and set AD on secure ones
+    #[cfg(feature = "dnssec")]
+    pub trust_anchors: Vec<DNSDSRecord>, // DS records for the root keys validation starts from
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        ResolverConfig {
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            retry_backoff: Duration::from_millis(100),
+            max_retry_backoff: Duration::from_secs(1),
+            round_robin: false,
+            no_cache_types: HashSet::new(),
+            recursion_available: true,
+            dns64_prefix: None,
+            max_records: 100,
+            max_response_size: 16384,
+            forwarders: Vec::new(),
+            forwarder_load_balance: false,
+            sweep_interval: Some(Duration::from_secs(60)),
+            query_source_addr: None,
+            upstream: Upstream::Plain,
+            preserve_question_bytes: false,
+            rng_seed: None,
+            client_subnet: None,
+            ttl_override: None,
+            edns_udp_size: 1232,
+            allow_ipv6_upstream: true,
+            partial_results: false,
+            max_concurrent_lookups: 256,
+            recurse_without_rd: false,
+            #[cfg(feature = "dnssec")]
+            dnssec_validation: false,
+            #[cfg(feature = "dnssec")]
+            trust_anchors: super::validator::root_trust_anchors(),
+        }
+    }
+}
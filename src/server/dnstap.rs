@@ -0,0 +1,275 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Frame Streams content type announced in the START frame
+const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+const CONTROL_START: u32 = 0x02;
+const CONTROL_STOP: u32 = 0x03;
+const CONTROL_FIELD_CONTENT_TYPE: u32 = 0x01;
+
+/// Which side of a client exchange a dnstap message records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    ClientQuery,
+    ClientResponse,
+}
+
+impl MessageType {
+    fn to_u64(self) -> u64 {
+        match self {
+            MessageType::ClientQuery => 5,
+            MessageType::ClientResponse => 6,
+        }
+    }
+}
+
+/// Writes client queries and responses as dnstap messages, framed as a
+/// unidirectional Frame Stream, to a file or Unix socket.
+pub struct DnstapWriter {
+    out: Box<dyn Write + Send>,
+    identity: Option<Vec<u8>>,
+}
+
+impl DnstapWriter {
+    /// Write frames to `out`, starting with the Frame Streams START frame
+    pub fn new(out: Box<dyn Write + Send>) -> Result<Self,std::io::Error> {
+        let mut writer = DnstapWriter { out, identity: None };
+        writer.write_control(CONTROL_START)?;
+        Ok(writer)
+    }
+
+    /// Write frames to a newly created file at `path`
+    pub fn create(path: &Path) -> Result<Self,std::io::Error> {
+        Self::new(Box::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Write frames to the collector listening on the Unix socket at `path`
+    #[cfg(unix)]
+    pub fn connect(path: &Path) -> Result<Self,std::io::Error> {
+        Self::new(Box::new(std::os::unix::net::UnixStream::connect(path)?))
+    }
+
+    /// Name this server in every message, e.g. its hostname
+    pub fn set_identity(&mut self, identity: &str) {
+        self.identity = Some(identity.as_bytes().to_vec());
+    }
+
+    /// Log one message exchanged with the client at `client` on our socket
+    /// `local`; `message` is the wire form of the query or response
+    pub fn log(&mut self, message_type: MessageType, client: SocketAddr, local: Option<SocketAddr>, message: &[u8]) -> Result<(),std::io::Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        // dnstap.Message
+        let mut inner = Vec::new();
+        put_varint_field(&mut inner, 1, message_type.to_u64());
+        put_varint_field(&mut inner, 2, if client.is_ipv4() { 1 } else { 2 }); // INET or INET6
+        put_varint_field(&mut inner, 3, 1); // UDP
+        put_bytes_field(&mut inner, 4, &ip_bytes(client.ip()));
+        if let Some(local) = local {
+            put_bytes_field(&mut inner, 5, &ip_bytes(local.ip()));
+        }
+        put_varint_field(&mut inner, 6, client.port() as u64);
+        if let Some(local) = local {
+            put_varint_field(&mut inner, 7, local.port() as u64);
+        }
+        let (sec_field, nsec_field, message_field) = match message_type {
+            MessageType::ClientQuery => (8, 9, 10),
+            MessageType::ClientResponse => (12, 13, 14),
+        };
+        put_varint_field(&mut inner, sec_field, now.as_secs());
+        put_fixed32_field(&mut inner, nsec_field, now.subsec_nanos());
+        put_bytes_field(&mut inner, message_field, message);
+
+        // dnstap.Dnstap wrapping it
+        let mut outer = Vec::new();
+        if let Some(identity) = &self.identity {
+            put_bytes_field(&mut outer, 1, identity);
+        }
+        put_bytes_field(&mut outer, 2, env!("CARGO_PKG_VERSION").as_bytes());
+        put_bytes_field(&mut outer, 14, &inner);
+        put_varint_field(&mut outer, 15, 1); // MESSAGE
+
+        self.out.write_all(&(outer.len() as u32).to_be_bytes())?;
+        self.out.write_all(&outer)?;
+        self.out.flush()
+    }
+
+    // A control frame is escaped by a zero length, then carries its own
+    // length, type and, for START, the content type
+    fn write_control(&mut self, control_type: u32) -> Result<(),std::io::Error> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&control_type.to_be_bytes());
+        if control_type == CONTROL_START {
+            frame.extend_from_slice(&CONTROL_FIELD_CONTENT_TYPE.to_be_bytes());
+            frame.extend_from_slice(&(CONTENT_TYPE.len() as u32).to_be_bytes());
+            frame.extend_from_slice(CONTENT_TYPE);
+        }
+
+        self.out.write_all(&0u32.to_be_bytes())?;
+        self.out.write_all(&(frame.len() as u32).to_be_bytes())?;
+        self.out.write_all(&frame)?;
+        self.out.flush()
+    }
+}
+
+impl Drop for DnstapWriter {
+    // End the stream cleanly so readers know no frames were lost
+    fn drop(&mut self) {
+        let _ = self.write_control(CONTROL_STOP);
+    }
+}
+
+fn ip_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+// Protobuf encoding of the few field kinds dnstap uses
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(out, field << 3);
+    put_varint(out, value);
+}
+
+fn put_fixed32_field(out: &mut Vec<u8>, field: u64, value: u32) {
+    put_varint(out, (field << 3) | 5);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_varint(out, (field << 3) | 2);
+    put_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::message::byte_packet_buffer::BytePacketBuffer;
+    use crate::message::{DNSPacket, DNSQuestion, ParseMode, QRClass, QRType};
+
+    // A sink the test can read back after the writer has taken it
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Value<'a> {
+        Varint(u64),
+        Fixed32(u32),
+        Bytes(&'a [u8]),
+    }
+
+    fn varint(data: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    // The fields of a protobuf message, in order
+    fn fields(data: &[u8]) -> Vec<(u64, Value<'_>)> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let key = varint(data, &mut pos);
+            let value = match key & 7 {
+                0 => Value::Varint(varint(data, &mut pos)),
+                2 => {
+                    let len = varint(data, &mut pos) as usize;
+                    pos += len;
+                    Value::Bytes(&data[pos - len..pos])
+                }
+                5 => {
+                    pos += 4;
+                    Value::Fixed32(u32::from_le_bytes(data[pos - 4..pos].try_into().unwrap()))
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    fn field<'a>(fields: &'a [(u64, Value<'a>)], number: u64) -> &'a Value<'a> {
+        &fields.iter().find(|(n, _)| *n == number).unwrap().1
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn client_query_frame_carries_the_query() {
+        let mut query = DNSPacket::new();
+        query.header.id = 4242;
+        query.question.add_question(DNSQuestion::new("example.test".to_string(), QRType::A, QRClass::IN));
+        let mut buffer = BytePacketBuffer::new();
+        query.write(&mut buffer).unwrap();
+
+        let sink = Sink::default();
+        let mut writer = DnstapWriter::new(Box::new(sink.clone())).unwrap();
+        let client: SocketAddr = "192.0.2.7:5353".parse().unwrap();
+        writer.log(MessageType::ClientQuery, client, None, &buffer.buf[0..buffer.pos]).unwrap();
+        drop(writer);
+        let data = sink.0.lock().unwrap().clone();
+
+        // START control frame announcing the content type
+        assert_eq!(u32_at(&data, 0), 0);
+        let start_len = u32_at(&data, 4) as usize;
+        assert_eq!(u32_at(&data, 8), CONTROL_START);
+        assert_eq!(u32_at(&data, 12), CONTROL_FIELD_CONTENT_TYPE);
+        assert_eq!(&data[20..8 + start_len], CONTENT_TYPE);
+
+        // One data frame holding a dnstap.Dnstap of type MESSAGE
+        let pos = 8 + start_len;
+        let frame_len = u32_at(&data, pos) as usize;
+        let outer = fields(&data[pos + 4..pos + 4 + frame_len]);
+        assert_eq!(field(&outer, 15), &Value::Varint(1));
+        let inner = match field(&outer, 14) {
+            Value::Bytes(inner) => fields(inner),
+            other => panic!("message field is {:?}", other),
+        };
+        assert_eq!(field(&inner, 1), &Value::Varint(5)); // CLIENT_QUERY
+        assert_eq!(field(&inner, 4), &Value::Bytes(&[192, 0, 2, 7]));
+        assert_eq!(field(&inner, 6), &Value::Varint(5353));
+        assert!(matches!(field(&inner, 9), Value::Fixed32(_)));
+        let logged = match field(&inner, 10) {
+            Value::Bytes(message) => DNSPacket::from_slice(message, ParseMode::Strict).unwrap(),
+            other => panic!("query_message field is {:?}", other),
+        };
+        assert_eq!(logged.header.id, 4242);
+        assert_eq!(logged.question.questions[0].qname, "example.test");
+
+        // Then the STOP frame from dropping the writer
+        assert_eq!(&data[pos + 4 + frame_len..], [0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 3]);
+    }
+}
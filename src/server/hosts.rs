@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use crate::message::{header::AAFlag, records::{DNSAAAARecord, DNSARecord, DNSRecord}, DNSPacket, QRClass, QRType};
+
+/// Static name to address mappings, in the spirit of `/etc/hosts`.
+///
+/// Names may be wildcards such as `*.dev`, which match any name below
+/// `dev` that has no exact entry.
+#[derive(Debug, Clone)]
+pub struct HostsTable {
+    entries: HashMap<String, Vec<IpAddr>>,
+    ttl: u32,
+}
+
+impl HostsTable {
+    // Constructor for creating a new, empty HostsTable
+    pub fn new() -> Self {
+        HostsTable {
+            entries: HashMap::new(),
+            ttl: 300,
+        }
+    }
+
+    /// TTL of the records synthesized from the table
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl;
+    }
+
+    // Method to map a name to an additional address
+    pub fn insert(&mut self, name: &str, addr: IpAddr) {
+        self.entries
+            .entry(name.trim_end_matches('.').to_lowercase())
+            .or_default()
+            .push(addr);
+    }
+
+    /// The addresses mapped to a name, preferring an exact entry over the
+    /// closest wildcard.
+    pub fn lookup(&self, qname: &str) -> Option<&Vec<IpAddr>> {
+        let qname = qname.trim_end_matches('.').to_lowercase();
+        if let Some(addrs) = self.entries.get(&qname) {
+            return Some(addrs);
+        }
+
+        let mut name = qname.as_str();
+        while let Some((_, parent)) = name.split_once('.') {
+            if let Some(addrs) = self.entries.get(&format!("*.{}", parent)) {
+                return Some(addrs);
+            }
+            name = parent;
+        }
+        None
+    }
+
+    /// Build an authoritative answer for an A or AAAA question, if the name
+    /// is in the table. A mapped name without addresses of the requested
+    /// family gets an empty answer.
+    pub fn answer(&self, qname: &str, qtype: QRType) -> Option<DNSPacket> {
+        if qtype != QRType::A && qtype != QRType::AAAA {
+            return None;
+        }
+        let addrs = self.lookup(qname)?;
+
+        let mut packet = DNSPacket::new();
+        packet.header.aa = AAFlag::Authoritative;
+        for addr in addrs {
+            match (addr, qtype) {
+                (IpAddr::V4(v4), QRType::A) => packet.answer.add_answer(DNSRecord::A(DNSARecord::new(qname.to_string(), QRClass::IN, self.ttl, *v4))),
+                (IpAddr::V6(v6), QRType::AAAA) => packet.answer.add_answer(DNSRecord::AAAA(DNSAAAARecord::new(qname.to_string(), QRClass::IN, self.ttl, *v6))),
+                _ => {},
+            }
+        }
+
+        Some(packet)
+    }
+}
+
+impl Default for HostsTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
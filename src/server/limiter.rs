@@ -0,0 +1,63 @@
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A counting semaphore bounding how many lookups run at once.
+pub struct Limiter {
+    state: Mutex<LimiterState>,
+    released: Condvar,
+}
+
+struct LimiterState {
+    in_flight: usize, // Permits currently held
+    max: usize, // Most permits that may be held at once
+}
+
+/// A slot held until dropped
+pub struct Permit<'a> {
+    limiter: &'a Limiter,
+}
+
+impl Limiter {
+    // Constructor for a Limiter allowing `max` lookups at once
+    pub fn new(max: usize) -> Self {
+        Limiter {
+            state: Mutex::new(LimiterState { in_flight: 0, max }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Change the limit. Lookups already running keep their slots even if
+    /// that puts them over it.
+    pub fn set_max(&self, max: usize) {
+        self.state.lock().unwrap().max = max;
+        self.released.notify_all();
+    }
+
+    /// Take a slot, waiting up to `timeout` for one to be released. `None`
+    /// if the limit was still reached when the wait ran out.
+    pub fn acquire(&self, timeout: Duration) -> Option<Permit<'_>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        while state.in_flight >= state.max {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            state = self.released.wait_timeout(state, remaining).unwrap().0;
+        }
+        state.in_flight += 1;
+        Some(Permit { limiter: self })
+    }
+
+    /// Number of slots currently held
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().in_flight -= 1;
+        self.limiter.released.notify_one();
+    }
+}
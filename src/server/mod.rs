@@ -1,147 +1,2049 @@
-use std::net::{UdpSocket,Ipv4Addr};
-use crate::message::{byte_packet_buffer::BytePacketBuffer, header::{QRFlag, RAFlag, RDFlag,RCode}, DNSPacket, DNSQuestion, QRClass, QRType};
-pub fn lookup(qname: &str, qtype: QRType, qclass: QRClass, server: (Ipv4Addr, u16)) -> Result<DNSPacket,std::io::Error> {
+pub mod cache;
+pub mod config;
+#[cfg(feature = "dnstap")]
+pub mod dnstap;
+pub mod hosts;
+pub mod limiter;
+pub mod roots;
+pub mod transfer;
+pub mod transport;
+#[cfg(feature = "dnssec")]
+pub mod validator;
+pub mod zone;
 
-    let socket = UdpSocket::bind(("0.0.0.0", 43210))?;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use cache::Cache;
+use config::{ResolverConfig, Upstream};
+use hosts::HostsTable;
+use limiter::Limiter;
+use transfer::ZoneChange;
+use transport::{Protocol, TcpTransport, Transport, UdpTransport};
+use zone::Zone;
+use crate::rng::Rng;
+#[cfg(feature = "dnssec")]
+use crate::message::records::{DNSDNSKEYRecord, DNSDSRecord};
+use crate::message::{byte_packet_buffer::BytePacketBuffer, header::{AAFlag, ADFlag, OpCode, QRFlag, RAFlag, RDFlag,RCode, TCFlag}, records::{ClientSubnet, DNSAAAARecord, DNSOPTRecord, DNSRecord}, DNSPacket, DNSQuestion, ParseMode, QRClass, QRType};
 
-    let mut packet = DNSPacket::new();
+/// What happened to one question handled by `DNSResolver::handle_query`,
+/// passed to the callback registered with `on_query`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryEvent {
+    pub qname: String, // The name asked about
+    pub qtype: QRType, // The type asked for
+    pub rcode: RCode, // The response code of the answer
+    pub cache_hit: bool, // Whether the answer came from the cache
+    pub latency: Duration, // How long answering took
+}
 
-    packet.header.id = 6666;
-    packet.header.qdcount = 1;
-    packet.header.rd = RDFlag::NonDesired;
-    packet.question.questions.push(DNSQuestion::new(qname.to_string(), qtype,qclass));
+/// The well-known DNS64 prefix, `64:ff9b::/96` (RFC 6052)
+pub const DNS64_WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
 
-    let mut req_buffer = BytePacketBuffer::new();
-    packet.write(&mut req_buffer)?;
-    socket.send_to(&req_buffer.buf[0..req_buffer.pos], server)?;
+/// A callback registered with `DNSResolver::on_query`
+pub type QueryCallback = Box<dyn Fn(&QueryEvent) + Send + Sync>;
 
-    let mut res_buffer = BytePacketBuffer::new();
-    socket.recv_from(&mut res_buffer.buf)?;
+/// Waits out a delay between retries, set with `DNSResolver::set_sleeper`
+pub type Sleeper = Box<dyn Fn(Duration) + Send + Sync>;
 
-    DNSPacket::from_buffer(&mut res_buffer)
+/// A DNS server and resolver.
+///
+/// Clones share the socket, cache, transport and other state, so several
+/// worker threads can each call `handle_query` on their own clone. Cache
+/// lookups only take a read lock and don't hold up one another.
+#[derive(Clone)]
+pub struct DNSResolver {
+    socket: Arc<UdpSocket>,
+    cache: Arc<RwLock<Cache>>,
+    config: ResolverConfig,
+    zones: Vec<Zone>,
+    hosts: HostsTable,
+    transport: Arc<dyn Transport + Send + Sync>,
+    roots: Arc<Mutex<Vec<(String, IpAddr)>>>,
+    rng: Arc<Mutex<Rng>>,
+    lookups: Arc<Limiter>,
+    on_query: Option<Arc<QueryCallback>>,
+    sleeper: Arc<Sleeper>,
+    #[cfg(feature = "dnstap")]
+    dnstap: Option<Arc<Mutex<dnstap::DnstapWriter>>>,
 }
-fn recursive_lookup(qname: &str, qtype: QRType) -> Result<DNSPacket,std::io::Error> {
-    // For now we're always starting with *a.root-servers.net*.
-    let mut ns = "1.1.1.1".parse::<Ipv4Addr>().unwrap();
-
-    // Since it might take an arbitrary number of steps, we enter an unbounded loop.
-    loop {
-        println!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
-
-        // The next step is to send the query to the active server.
-        let ns_copy = ns;
-        let qclass= QRClass::IN;
-        let server = (ns_copy, 53);
-        let response = lookup(qname, qtype, qclass, server)?;
-
-        // If there are entries in the answer section, and no errors, we are done!
-        if !response.answer.answers.is_empty() && response.header.rcode == RCode::NoError {
-            return Ok(response);
+
+impl DNSResolver {
+    // Constructor for creating a new DNSResolver listening on the given address
+    pub fn new(ip: &str, port: u16) -> Result<Self,std::io::Error> {
+        DNSResolver::with_config(ip, port, ResolverConfig::default())
+    }
+    // Constructor for creating a new DNSResolver with the given options
+    pub fn with_config(ip: &str, port: u16, config: ResolverConfig) -> Result<Self,std::io::Error> {
+        let socket = UdpSocket::bind((ip, port))?;
+
+        let mut cache = Cache::new();
+        cache.set_round_robin(config.round_robin);
+        cache.set_no_cache_types(config.no_cache_types.clone());
+        let rng = match config.rng_seed {
+            Some(seed) => {
+                cache.set_rng_seed(seed);
+                Rng::with_seed(seed)
+            }
+            None => Rng::from_entropy(),
+        };
+
+        let transport = upstream_transport(&config)?;
+
+        let lookups = Limiter::new(config.max_concurrent_lookups);
+
+        Ok(DNSResolver {
+            socket: Arc::new(socket),
+            cache: Arc::new(RwLock::new(cache)),
+            config,
+            zones: Vec::new(),
+            hosts: HostsTable::new(),
+            transport,
+            roots: Arc::new(Mutex::new(roots::root_hints())),
+            rng: Arc::new(Mutex::new(rng)),
+            lookups: Arc::new(lookups),
+            on_query: None,
+            sleeper: Arc::new(Box::new(thread::sleep)),
+            #[cfg(feature = "dnstap")]
+            dnstap: None,
+        })
+    }
+    /// Seed the random choices made by the resolver, such as which address of
+    /// a name server to use and the round-robin order of cached A records, so
+    /// that the same seed gives the same behaviour
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.config.rng_seed = Some(seed);
+        self.rng = Arc::new(Mutex::new(Rng::with_seed(seed)));
+        self.cache.write().unwrap().set_rng_seed(seed);
+        self
+    }
+    /// How long to wait for an upstream server to reply
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.config.timeout = timeout;
+    }
+    /// How many times a timed out upstream query is retried
+    pub fn set_retries(&mut self, retries: u32) {
+        self.config.retries = retries;
+    }
+    /// Delay before the first retry, doubling on each further retry up to `max`
+    pub fn set_retry_backoff(&mut self, initial: Duration, max: Duration) {
+        self.config.retry_backoff = initial;
+        self.config.max_retry_backoff = max;
+    }
+    /// Wait out retry delays with `sleeper` instead of `thread::sleep`, e.g.
+    /// to record them in tests without waiting
+    pub fn set_sleeper(&mut self, sleeper: Sleeper) {
+        self.sleeper = Arc::new(sleeper);
+    }
+    /// Rotate the order of A records across successive cache hits
+    pub fn set_round_robin(&mut self, enabled: bool) {
+        self.config.round_robin = enabled;
+        self.cache.write().unwrap().set_round_robin(enabled);
+    }
+    /// Record types that should never be cached
+    pub fn set_no_cache_types(&mut self, types: HashSet<QRType>) {
+        self.config.no_cache_types = types.clone();
+        self.cache.write().unwrap().set_no_cache_types(types);
+    }
+    /// Whether to recurse for names outside the loaded zones. When disabled
+    /// the resolver behaves like an authoritative-only server.
+    pub fn set_recursion_available(&mut self, enabled: bool) {
+        self.config.recursion_available = enabled;
+    }
+    /// Recurse for queries that didn't ask for it, with the RD flag clear.
+    /// Off by default, so such queries are only answered from the zones,
+    /// hosts table and cache, and refused otherwise.
+    pub fn set_recurse_without_rd(&mut self, enabled: bool) {
+        self.config.recurse_without_rd = enabled;
+    }
+    /// Serve a zone authoritatively
+    pub fn add_zone(&mut self, zone: Zone) {
+        self.zones.push(zone);
+    }
+    /// Exchange upstream queries through the given transport instead of UDP
+    pub fn set_transport(&mut self, transport: Box<dyn Transport + Send + Sync>) {
+        self.transport = Arc::from(transport);
+    }
+    /// Send plain upstream queries from the given local address, or from an
+    /// OS-chosen one if `None`. Unless queries go over DNS-over-TLS or
+    /// DNS-over-HTTPS, this replaces any transport set previously.
+    pub fn set_query_source_addr(&mut self, source: Option<IpAddr>) {
+        self.config.query_source_addr = source;
+        if self.config.upstream == Upstream::Plain {
+            self.transport = match source {
+                Some(source) => Arc::new(UdpTransport::with_source(source)),
+                None => Arc::new(UdpTransport::new()),
+            };
         }
+    }
+    /// Copy the question section of each request into its response byte for
+    /// byte, instead of re-encoding it. This preserves the client's casing of
+    /// the name, which some clients check as extra protection against
+    /// spoofing (the "0x20" trick). Off by default.
+    pub fn set_preserve_question_bytes(&mut self, enabled: bool) {
+        self.config.preserve_question_bytes = enabled;
+    }
+    /// The most answer records a single resolution may accumulate, bounding
+    /// the memory a malicious CNAME chain can make us hold
+    pub fn set_max_records(&mut self, max: usize) {
+        self.config.max_records = max;
+    }
+    /// The most bytes the answer of a single resolution may take up encoded,
+    /// 16384 by default, so a few huge records can't get round the record
+    /// limit
+    pub fn set_max_response_size(&mut self, max: usize) {
+        self.config.max_response_size = max;
+    }
+    /// Send an EDNS Client Subnet option for `address`/`prefix` with upstream
+    /// queries, so CDNs can answer for that network; `None` turns it off.
+    /// Errors if the prefix is longer than the address.
+    pub fn set_client_subnet(&mut self, subnet: Option<(IpAddr, u8)>) -> Result<(),std::io::Error> {
+        self.config.client_subnet = match subnet {
+            Some((address, prefix)) => Some(ClientSubnet::new(address, prefix)?),
+            None => None,
+        };
+        Ok(())
+    }
+    /// The EDNS UDP payload size, 1232 bytes by default. It is advertised to
+    /// upstreams, bounds the responses received from them and bounds those
+    /// served to clients, which are truncated beyond it.
+    pub fn set_edns_udp_size(&mut self, size: u16) {
+        self.config.edns_udp_size = size;
+    }
+    /// Whether recursion may follow referrals to name servers reachable only
+    /// over IPv6. Turn this off on hosts without IPv6 connectivity, where
+    /// such queries would only time out.
+    pub fn set_allow_ipv6_upstream(&mut self, allow: bool) {
+        self.config.allow_ipv6_upstream = allow;
+    }
+    /// Serve every record with this TTL instead of its own, e.g. 0 to stop
+    /// clients caching; `None` serves records' TTLs unchanged
+    pub fn set_ttl_override(&mut self, ttl: Option<u32>) {
+        self.config.ttl_override = ttl;
+    }
+    /// When recursion times out after following a referral, return the last
+    /// referral with a SERVFAIL response code rather than an error, so the
+    /// caller, and clients, see how far resolution got
+    pub fn set_partial_results(&mut self, enabled: bool) {
+        self.config.partial_results = enabled;
+    }
+    /// The most recursive or forwarded resolutions that may run at once,
+    /// across all clones of the resolver; 256 by default. Each holds
+    /// sockets and memory, so further queries wait up to the upstream
+    /// timeout for one to finish and are answered with SERVFAIL if none does.
+    pub fn set_max_concurrent_lookups(&mut self, max: usize) {
+        self.config.max_concurrent_lookups = max;
+        self.lookups.set_max(max);
+    }
+    /// Validate the answers of recursive resolution with DNSSEC, following
+    /// the chain of trust down from the trust anchors. Secure answers are
+    /// served with the AD flag, bogus ones as SERVFAIL. Zones that aren't
+    /// signed, or are only reachable through unsigned delegations, are
+    /// answered without AD. Denials of existence need a valid NSEC or NSEC3
+    /// proof to be secure.
+    #[cfg(feature = "dnssec")]
+    pub fn set_dnssec_validation(&mut self, enabled: bool) {
+        self.config.dnssec_validation = enabled;
+    }
+    /// The DS records of the root keys validation trusts, by default the
+    /// IANA root KSKs from `validator::root_trust_anchors`
+    #[cfg(feature = "dnssec")]
+    pub fn set_trust_anchors(&mut self, anchors: Vec<DNSDSRecord>) {
+        self.config.trust_anchors = anchors;
+    }
+    /// Call `callback` after each question `handle_query` answers, e.g. to
+    /// feed external monitoring. Replaces any previously registered callback.
+    pub fn on_query(&mut self, callback: QueryCallback) {
+        self.on_query = Some(Arc::new(callback));
+    }
+    /// Synthesize AAAA records for names that only have A records by
+    /// embedding the IPv4 address in the given /96 prefix, normally
+    /// `DNS64_WELL_KNOWN_PREFIX`, for IPv6-only clients behind NAT64 (RFC
+    /// 6147). `None` turns DNS64 off, the default.
+    pub fn set_dns64_prefix(&mut self, prefix: Option<Ipv6Addr>) {
+        self.config.dns64_prefix = prefix;
+    }
+    /// Forward queries to an upstream resolver instead of recursing
+    pub fn set_forwarder(&mut self, server: Option<(IpAddr, u16)>) {
+        self.config.forwarders = server.into_iter().collect();
+    }
+    /// Forward queries to a list of upstream resolvers, tried in order. An
+    /// upstream that times out, fails or answers `SERVFAIL` falls through to
+    /// the next. An empty list switches back to recursing.
+    pub fn set_forwarders(&mut self, servers: Vec<(IpAddr, u16)>) {
+        self.config.forwarders = servers;
+    }
+    /// Spread queries across the forwarders by starting each at a random one,
+    /// instead of always preferring the first
+    pub fn set_forwarder_load_balance(&mut self, enabled: bool) {
+        self.config.forwarder_load_balance = enabled;
+    }
+    /// Forward queries to an upstream resolver over DNS-over-TLS, checking
+    /// its certificate against `hostname`
+    #[cfg(feature = "dot")]
+    pub fn forwarder_dot(&mut self, server: (IpAddr, u16), hostname: &str) -> Result<(),std::io::Error> {
+        self.transport = Arc::new(transport::TlsTransport::new(hostname)?);
+        self.config.upstream = Upstream::Tls(hostname.to_string());
+        self.config.forwarders = vec![server];
+        Ok(())
+    }
+    /// Forward queries to a DNS-over-HTTPS endpoint. The forwarder
+    /// addresses are unused while it is set.
+    #[cfg(feature = "doh")]
+    pub fn forwarder_doh(&mut self, url: &str) {
+        self.transport = Arc::new(transport::DohTransport::new(url));
+        self.config.upstream = Upstream::Https(url.to_string());
+    }
+    /// Log every client query and response handled to `writer` in dnstap
+    /// format
+    #[cfg(feature = "dnstap")]
+    pub fn set_dnstap(&mut self, writer: dnstap::DnstapWriter) {
+        self.dnstap = Some(Arc::new(Mutex::new(writer)));
+    }
+    /// How often `run` purges expired cache entries in the background, or
+    /// `None` to only replace them when their name is resolved again
+    pub fn set_sweep_interval(&mut self, interval: Option<Duration>) {
+        self.config.sweep_interval = interval;
+    }
+    /// Answer names in the table with static addresses, ahead of any zone
+    /// or recursive lookup
+    pub fn set_hosts(&mut self, hosts: HostsTable) {
+        self.hosts = hosts;
+    }
+    pub fn lookup(&self, qname: &str, qtype: QRType, qclass: QRClass, server: (IpAddr, u16)) -> Result<DNSPacket,std::io::Error> {
+        self.lookup_proto(qname, qtype, qclass, server, Protocol::UdpThenTcp)
+    }
+    /// Like `lookup`, but choosing the protocol: `Protocol::Udp` returns a
+    /// truncated response as it is, and `Protocol::Tcp` skips UDP entirely.
+    pub fn lookup_proto(&self, qname: &str, qtype: QRType, qclass: QRClass, server: (IpAddr, u16), protocol: Protocol) -> Result<DNSPacket,std::io::Error> {
+        let mut packet = DNSPacket::new();
+
+        packet.header.id = self.query_id();
+        packet.header.qdcount = 1;
+        packet.header.rd = RDFlag::NonDesired;
+        packet.question.questions.push(DNSQuestion::new(qname.to_string(), qtype,qclass));
 
-        // We might also get a `NXDOMAIN` reply, which is the authoritative name servers
-        // way of telling us that the name doesn't exist.
-        if response.header.rcode == RCode::NXDomain {
+        self.send_with_edns(&mut packet, server, protocol)
+    }
+    // Ask an upstream resolver to do the recursion for us
+    fn forward(&self, qname: &str, qtype: QRType, qclass: QRClass, server: (IpAddr, u16)) -> Result<DNSPacket,std::io::Error> {
+        let mut packet = DNSPacket::new();
+
+        packet.header.id = self.query_id();
+        packet.header.qdcount = 1;
+        packet.header.rd = RDFlag::Desired;
+        packet.question.questions.push(DNSQuestion::new(qname.to_string(), qtype,qclass));
+
+        self.send_with_edns(&mut packet, server, Protocol::UdpThenTcp)
+    }
+    /// Send a hand-built packet to `server` as it is and return the parsed
+    /// response.
+    ///
+    /// The packet goes over the configured transport with the usual retries.
+    /// A truncated reply is retried over TCP so the full response is returned.
+    /// A response that doesn't echo the packet's questions is rejected.
+    pub fn send_packet(&self, packet: &mut DNSPacket, server: (IpAddr, u16)) -> Result<DNSPacket,std::io::Error> {
+        self.send_packet_proto(packet, server, Protocol::UdpThenTcp)
+    }
+    /// Like `send_packet`, but choosing the protocol. `Protocol::Udp` uses
+    /// the configured transport without falling back to TCP.
+    pub fn send_packet_proto(&self, packet: &mut DNSPacket, server: (IpAddr, u16), protocol: Protocol) -> Result<DNSPacket,std::io::Error> {
+        let response = if protocol == Protocol::Tcp {
+            self.stream_transport().send_query(packet, server, self.config.timeout)?
+        } else {
+            let response = self.exchange(packet, server)?;
+            if protocol == Protocol::UdpThenTcp && response.header.tc == TCFlag::Truncated {
+                self.stream_transport().send_query(packet, server, self.config.timeout)?
+            } else {
+                response
+            }
+        };
+        check_question_echo(packet, &response)?;
+        Ok(response)
+    }
+    /// Send a dynamic update, e.g. from `UpdateBuilder`, to the zone's
+    /// primary `server` over TCP and return the response code, `NoError` if
+    /// it was applied. Updates are sent unsigned, so the server has to
+    /// accept them by address.
+    pub fn send_update(&self, update: &mut DNSPacket, server: (IpAddr, u16)) -> Result<RCode,std::io::Error> {
+        update.header.id = self.query_id();
+        let response = self.send_packet_proto(update, server, Protocol::Tcp)?;
+        if response.header.id != update.header.id {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Update response has a different ID"));
+        }
+        Ok(response.header.rcode)
+    }
+    // A fresh random ID for each outgoing message, so a stale or spoofed
+    // reply can't count on matching it
+    fn query_id(&self) -> u16 {
+        self.rng.lock().unwrap().next_u64() as u16
+    }
+    // Attach an OPT record advertising the configured payload size, which
+    // the UDP transport sizes its receive buffer by, and carrying the client
+    // subnet if one is configured. When validating it asks for signatures.
+    fn add_edns(&self, packet: &mut DNSPacket) {
+        let options = self.config.client_subnet.iter().map(ClientSubnet::to_option).collect();
+        #[allow(unused_mut)]
+        let mut opt = DNSOPTRecord::new(self.config.edns_udp_size, options);
+        #[cfg(feature = "dnssec")]
+        opt.set_dnssec_ok(self.config.dnssec_validation);
+        packet.additional.records.push(DNSRecord::OPT(opt));
+    }
+    // Send a query with our OPT record attached. A server that doesn't
+    // implement EDNS may answer FORMERR, so then the query is sent again
+    // without it (RFC 6891 section 7).
+    fn send_with_edns(&self, packet: &mut DNSPacket, server: (IpAddr, u16), protocol: Protocol) -> Result<DNSPacket,std::io::Error> {
+        self.add_edns(packet);
+        let response = self.send_packet_proto(packet, server, protocol)?;
+        if response.header.rcode != RCode::FormErr {
             return Ok(response);
         }
+        packet.additional.records.retain(|record| !matches!(record, DNSRecord::OPT(_)));
+        self.send_packet_proto(packet, server, protocol)
+    }
+    // How large a UDP response the client can take: what its OPT record
+    // advertises, capped by our own size, or 512 bytes without EDNS
+    fn response_size_limit(&self, request: &DNSPacket) -> usize {
+        match request.edns_udp_size() {
+            Some(size) => size.min(self.config.edns_udp_size).max(512) as usize,
+            None => 512,
+        }
+    }
+    // Wait for a slot to resolve `qname` upstream, shedding the query if
+    // too many resolutions are already under way
+    fn acquire_lookup(&self, qname: &str) -> Result<limiter::Permit<'_>,std::io::Error> {
+        self.lookups.acquire(self.config.timeout).ok_or_else(|| {
+            std::io::Error::other(format!("Too many concurrent lookups to resolve {}", qname))
+        })
+    }
+    // Forward to each upstream in turn until one gives a usable answer,
+    // returning the last failure if none does
+    fn forward_any(&self, qname: &str, qtype: QRType, qclass: QRClass) -> Result<DNSPacket,std::io::Error> {
+        let _permit = self.acquire_lookup(qname)?;
+        // A DoH endpoint is identified by its URL, which the transport holds,
+        // so the address it is sent to goes unused
+        #[cfg(feature = "doh")]
+        if matches!(self.config.upstream, Upstream::Https(_)) {
+            return self.forward(qname, qtype, qclass, (std::net::Ipv4Addr::UNSPECIFIED.into(), 443));
+        }
+        let servers = &self.config.forwarders;
+        let start = if self.config.forwarder_load_balance {
+            self.rng.lock().unwrap().below(servers.len())
+        } else {
+            0
+        };
+
+        // Forwarders passed over are noted on the response finally returned
+        let mut skipped = Vec::new();
+        let mut last = Err(std::io::Error::other("No forwarders configured"));
+        for i in 0..servers.len() {
+            let server = servers[(start + i) % servers.len()];
+            last = self.forward(qname, qtype, qclass, server);
+            match &last {
+                Ok(response) if response.header.rcode != RCode::ServFail => break,
+                Ok(_) if i + 1 < servers.len() => skipped.push(format!("forwarder {:?} answered SERVFAIL", server)),
+                Err(e) if i + 1 < servers.len() => skipped.push(format!("forwarder {:?} failed: {}", server, e)),
+                _ => (),
+            }
+        }
+        last.map(|mut response| {
+            response.warnings.extend(skipped);
+            response
+        })
+    }
+    // The transport for queries that have to go over a stream: TCP from the
+    // configured source address, unless an encrypted transport is configured,
+    // which is a stream already
+    fn stream_transport(&self) -> Arc<dyn Transport + Send + Sync> {
+        if self.config.upstream != Upstream::Plain {
+            return self.transport.clone();
+        }
+        match self.config.query_source_addr {
+            Some(source) => Arc::new(TcpTransport::with_source(source)),
+            None => Arc::new(TcpTransport::new()),
+        }
+    }
+    // Whether queries are forwarded upstream rather than resolved by recursion
+    fn is_forwarding(&self) -> bool {
+        #[cfg(feature = "doh")]
+        if matches!(self.config.upstream, Upstream::Https(_)) {
+            return true;
+        }
+        !self.config.forwarders.is_empty()
+    }
+    // Send a query through the transport, retrying with backoff on timeout
+    fn exchange(&self, packet: &mut DNSPacket, server: (IpAddr, u16)) -> Result<DNSPacket,std::io::Error> {
+        let mut delay = self.config.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            match self.transport.send_query(packet, server, self.config.timeout) {
+                Ok(response) => return Ok(response),
+                // A read timeout surfaces as either kind depending on the platform
+                Err(e) if attempt < self.config.retries && matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                    // Back off exponentially so retries don't add to congestion
+                    (self.sleeper)(delay);
+                    delay = (delay * 2).min(self.config.max_retry_backoff);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Check that an upstream server is responsive.
+    ///
+    /// Sends a `. NS` query and returns the round-trip time, or an error if
+    /// no reply arrives within the configured timeout.
+    pub fn health_check(&self, server: (IpAddr, u16)) -> Result<Duration,std::io::Error> {
+        let start = Instant::now();
+        self.lookup("", QRType::NS, QRClass::IN, server)?;
+        Ok(start.elapsed())
+    }
+    /// Refresh the root server list by sending a `. NS` priming query to the
+    /// first reachable IPv4 root in the current list, starting with the
+    /// built-in hints, and return the new list.
+    pub fn prime_roots(&self) -> Result<Vec<(String, IpAddr)>,std::io::Error> {
+        let current = self.roots.lock().unwrap().clone();
+        let mut last_error = std::io::Error::other("No IPv4 root server to prime from");
+
+        for (_, addr) in current {
+            if !addr.is_ipv4() {
+                continue;
+            }
+            let response = match self.lookup("", QRType::NS, QRClass::IN, (addr, 53)) {
+                Ok(response) => response,
+                Err(e) => {
+                    last_error = e;
+                    continue;
+                }
+            };
+
+            let servers = roots::parse_priming_response(&response);
+            if servers.is_empty() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Priming response lists no root servers with addresses"));
+            }
+            *self.roots.lock().unwrap() = servers.clone();
+            return Ok(servers);
+        }
+
+        Err(last_error)
+    }
+    /// The root servers recursion starts from
+    pub fn roots(&self) -> Vec<(String, IpAddr)> {
+        self.roots.lock().unwrap().clone()
+    }
+    /// Recursively resolve a question of any class, starting from the root.
+    pub fn resolve(&self, qname: &str, qtype: QRType, qclass: QRClass) -> Result<DNSPacket,std::io::Error> {
+        self.recursive_lookup(qname, qtype, qclass)
+    }
+    fn recursive_lookup(&self, qname: &str, qtype: QRType, qclass: QRClass) -> Result<DNSPacket,std::io::Error> {
+        let _permit = self.acquire_lookup(qname)?;
+        let mut last_referral = None;
+        match self.recursive_lookup_with(qname, qtype, qclass, &mut HashMap::new(), &mut last_referral) {
+            Err(e) if self.config.partial_results && matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                match last_referral {
+                    Some(mut partial) => {
+                        partial.warnings.push(format!("resolving {} timed out, answering with the last referral: {}", qname, e));
+                        partial.header.rcode = RCode::ServFail;
+                        Ok(partial)
+                    }
+                    None => Err(e),
+                }
+            }
+            result => result,
+        }
+    }
+    // Recursion proper. `ns_addrs` remembers the addresses of glueless name
+    // servers resolved so far, or `None` for ones that couldn't be, so each
+    // is only looked up once however many referrals name it. The last
+    // referral followed is kept in `last_referral` if partial results are on.
+    fn recursive_lookup_with(&self, qname: &str, qtype: QRType, qclass: QRClass, ns_addrs: &mut HashMap<String, Option<IpAddr>>, last_referral: &mut Option<DNSPacket>) -> Result<DNSPacket,std::io::Error> {
+        // Start with the first root server we can reach over IPv4, normally
+        // *a.root-servers.net*.
+        let mut ns = self.roots
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, addr)| *addr)
+            .find(IpAddr::is_ipv4)
+            .unwrap_or(IpAddr::V4(roots::ROOT_HINTS[0].1));
+
+        // Since it might take an arbitrary number of steps, we enter an unbounded loop.
+        // `zone` is the zone the current server was referred to us for.
+        let mut accumulated = 0;
+        let mut zone = String::new();
+
+        // When validating, the root's keys are checked against the trust
+        // anchors before anything it says is believed
+        #[cfg(feature = "dnssec")]
+        let mut chain = match self.config.dnssec_validation {
+            true => Some(validator::ChainState {
+                keys: self.zone_keys("", ns, &self.config.trust_anchors)?,
+                zone: String::new(),
+            }),
+            false => None,
+        };
+        loop {
+            println!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
+
+            // The next step is to send the query to the active server.
+            let ns_copy = ns;
+            let server = (ns_copy, 53);
+            let mut response = self.lookup(qname, qtype, qclass, server)?;
+
+            // Signatures are checked on the response as received, since the
+            // filtering below would drop the RRSIGs with the records they cover.
+            // A server also authoritative for a child zone answers for it
+            // directly, so we first descend to the child to get its keys.
+            #[cfg(feature = "dnssec")]
+            let authenticated = match &mut chain {
+                Some(state) if !response.is_referral() => {
+                    if let Some(signer) = validator::child_signer(&response, qname, &state.zone) {
+                        self.descend(state, &signer, ns)?;
+                    }
+                    match &state.keys {
+                        Some(keys) => validator::verify_response(&response, qname, qtype, &state.zone, keys)?,
+                        None => false,
+                    }
+                }
+                _ => false,
+            };
+
+            // Some broken servers answer with records of an unrelated name,
+            // type or class, so only keep those that actually answer the
+            // question, or the same question for a name the answer's CNAME
+            // chain leads to. If nothing is left we carry on resolving as if
+            // the answer section was empty.
+            let mut questions = vec![DNSQuestion::new(qname.to_string(), qtype, qclass)];
+            while let Some(target) = response.answer.answers.iter().find_map(|record| match record {
+                DNSRecord::CNAME(cname) if questions.last().is_some_and(|q| q.matches_record(record)) => Some(cname.rdata.clone()),
+                _ => None,
+            }) {
+                if questions.iter().any(|q| q.qname.eq_ignore_ascii_case(&target)) {
+                    break;
+                }
+                questions.push(DNSQuestion::new(target, qtype, qclass));
+            }
+            response.answer.answers.retain(|record| questions.iter().any(|q| q.matches_record(record)));
+
+            // Only the answer records making up the CNAME chain count
+            // towards the limits; referrals carry none
+            accumulated += response.answer.answers.len();
+            if accumulated > self.config.max_records {
+                return Err(std::io::Error::other(format!("Resolving {} exceeded the limit of {} records", qname, self.config.max_records)));
+            }
+            let size = answer_size(&response)?;
+            if size > self.config.max_response_size {
+                return Err(std::io::Error::other(format!("Resolving {} exceeded the limit of {} bytes", qname, self.config.max_response_size)));
+            }
+
+            // Anything other than a referral is final: either we have an answer,
+            // or the server told us the name doesn't exist (`NXDOMAIN`) or failed.
+            if !response.is_referral() {
+                #[cfg(feature = "dnssec")]
+                if authenticated {
+                    response.header.ad = ADFlag::Authenticated;
+                }
+                return Ok(response);
+            }
+
+            // A referral has to move us closer to the name, to a zone below the
+            // one the server was asked about. Delegating back to the same zone
+            // or a parent is a lame delegation that would only loop. NS records
+            // only for zones not containing the name lead nowhere, so we go with
+            // what the server told us.
+            let referred = match response.referral_zone(qname) {
+                Some(referred) => referred,
+                None => return Ok(response),
+            };
+            if !is_proper_subdomain(referred, &zone) {
+                return Err(std::io::Error::other(format!("Lame delegation for {}: {} referred to zone \"{}\" from \"{}\"", qname, ns, referred, zone)));
+            }
+
+            // The child zone stays secure only if the parent vouches for its
+            // keys with signed DS records
+            #[cfg(feature = "dnssec")]
+            let child_ds = match &chain {
+                Some(validator::ChainState { keys: Some(keys), .. }) => validator::referral_ds(&response, referred, &zone, keys)?,
+                _ => None,
+            };
+            zone = referred.to_string();
+            if self.config.partial_results {
+                *last_referral = Some(response.clone());
+            }
+
+            // Otherwise, move on to one of the name servers we were referred to.
+            // If none can be found, we'll go with what the last server told us.
+            match self.find_next_server(&response, qname, ns_addrs)? {
+                Some(new_ns) => ns = new_ns,
+                None => return Ok(response),
+            }
+
+            #[cfg(feature = "dnssec")]
+            if let Some(state) = &mut chain {
+                state.keys = match &child_ds {
+                    Some(ds) => self.zone_keys(&zone, ns, ds)?,
+                    None => None,
+                };
+                state.zone = zone.clone();
+            }
+        }
+    }
+    // Fetch the DNSKEY RRset of `zone` from its server `ns` and check it
+    // against the DS records vouching for it
+    #[cfg(feature = "dnssec")]
+    fn zone_keys(&self, zone: &str, ns: IpAddr, ds: &[DNSDSRecord]) -> Result<Option<Vec<DNSDNSKEYRecord>>,std::io::Error> {
+        let response = self.lookup(zone, QRType::DNSKEY, QRClass::IN, (ns, 53))?;
+        validator::trusted_keys(&response, zone, ds)
+    }
+    // Move the chain of trust from its zone down to `child`, whose DS
+    // records are asked of the parent's server `ns`
+    #[cfg(feature = "dnssec")]
+    fn descend(&self, state: &mut validator::ChainState, child: &str, ns: IpAddr) -> Result<(),std::io::Error> {
+        let keys = match &state.keys {
+            Some(keys) => keys,
+            None => return Ok(()),
+        };
+        let response = self.lookup(child, QRType::DS, QRClass::IN, (ns, 53))?;
+        state.keys = match validator::referral_ds(&response, child, &state.zone, keys)? {
+            Some(ds) => self.zone_keys(child, ns, &ds)?,
+            None => None,
+        };
+        state.zone = child.to_string();
+        Ok(())
+    }
+    // Pick the server to follow a referral to. Name servers with glue are
+    // preferred; only if none has any do we resolve a name server's address
+    // ourselves, trying each in turn until one resolves.
+    fn find_next_server(&self, response: &DNSPacket, qname: &str, ns_addrs: &mut HashMap<String, Option<IpAddr>>) -> Result<Option<IpAddr>,std::io::Error> {
+        // First, we'll try to find a new nameserver based on NS and a corresponding A
+        // or AAAA record in the additional section. AAAA glue is only used
+        // when there is no A glue, and not at all if IPv6 is disallowed, in
+        // which case we resolve the name server's A record instead.
+        if let Some(new_ns) = response.get_resolved_ns(qname).filter(|addr| addr.is_ipv4() || self.config.allow_ipv6_upstream) {
+            return Ok(Some(new_ns));
+        }
+
+        let mut last_error = None;
+        for new_ns_name in response.get_ns_hosts(qname) {
+            let key = new_ns_name.to_ascii_lowercase();
+            if let Some(known) = ns_addrs.get(&key) {
+                match known {
+                    Some(addr) => return Ok(Some(*addr)),
+                    None => continue,
+                }
+            }
+
+            // Here we go down the rabbit hole by starting _another_ lookup sequence in the
+            // midst of our current one. Hopefully, this will give us the IP of an appropriate
+            // name server. The name is marked as unresolvable while that runs, so
+            // name servers whose names can only be resolved through each other
+            // don't send us round in circles.
+            ns_addrs.insert(key.clone(), None);
+            let addr = match self.recursive_lookup_with(new_ns_name, QRType::A, QRClass::IN, ns_addrs, &mut None) {
+                Ok(recursive_response) => recursive_response.get_random_a(&mut self.rng.lock().unwrap()).map(IpAddr::V4),
+                Err(e) => {
+                    last_error = Some(e);
+                    None
+                }
+            };
+            ns_addrs.insert(key, addr);
+            if addr.is_some() {
+                return Ok(addr);
+            }
+        }
+
+        // If no name server could be resolved, the caller returns the last
+        // result it got, unless resolving failed outright.
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+    /// Collect every record the resolver can find for a name.
+    ///
+    /// Rather than sending a raw `ANY` query, which most upstreams minimize
+    /// per RFC 8482, this issues one lookup per common type and merges the
+    /// answers, dropping duplicates. At most the configured maximum number of
+    /// records are returned.
+    pub fn query_any(&self, name: &str) -> Result<Vec<DNSRecord>,std::io::Error> {
+        let qtypes = [QRType::A, QRType::AAAA, QRType::MX, QRType::TXT, QRType::NS, QRType::CNAME];
+        let mut merged = DNSPacket::new();
+
+        for qtype in qtypes {
+            let response = self.recursive_lookup(name, qtype, QRClass::IN)?;
+            merged.merge_answers(&response);
+        }
+
+        merged.answer.answers.truncate(self.config.max_records);
+        Ok(merged.answer.answers)
+    }
+    // Answer from the cache when possible, otherwise resolve recursively and
+    // remember any positive answer for next time. Also says whether the
+    // answer was a cache hit. Only the Internet class is cached.
+    fn resolve_cached(&self, qname: &str, qtype: QRType, qclass: QRClass) -> Result<(DNSPacket, bool),std::io::Error> {
+        let cacheable = qclass == QRClass::IN;
+        if cacheable {
+            if let Some(packet) = self.cache.read().unwrap().lookup_response(qname, qtype) {
+                return Ok((packet, true));
+            }
+        }
+
+        let result = if self.is_forwarding() {
+            self.forward_any(qname, qtype, qclass)?
+        } else {
+            self.recursive_lookup(qname, qtype, qclass)?
+        };
+        if cacheable && !result.answer.answers.is_empty() && result.header.rcode == RCode::NoError {
+            self.cache.write().unwrap().insert(qname, qtype, &result);
+        }
+
+        Ok((result, false))
+    }
+    // The most specific loaded zone containing the name, if any
+    fn find_zone(&self, qname: &str) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| zone.contains(qname))
+            .max_by_key(|zone| zone.origin.len())
+    }
+    // Answer a question from the hosts table or loaded zones, or by
+    // recursing if allowed and, unless configured otherwise, the client set
+    // RD. Also says whether the answer was a cache hit.
+    fn answer(&self, question: &DNSQuestion, recursion_desired: bool) -> Result<(DNSPacket, bool),std::io::Error> {
+        // Host mappings and zones only hold Internet class data
+        let internet = matches!(question.qclass, QRClass::IN | QRClass::ANY);
+
+        if internet {
+            if let Some(packet) = self.hosts.answer(&question.qname, question.qtype) {
+                return Ok((packet, false));
+            }
+
+            if let Some(zone) = self.find_zone(&question.qname) {
+                return Ok((zone.answer(&question.qname, question.qtype), false));
+            }
+        }
+
+        // Without recursion we have nothing to offer for names outside our
+        // zones, so the query is refused rather than walking the hierarchy.
+        if !self.config.recursion_available {
+            let mut packet = DNSPacket::new();
+            packet.header.rcode = RCode::Refused;
+            return Ok((packet, false));
+        }
+
+        // A client that didn't ask for recursion gets what is already
+        // cached, and is refused otherwise rather than us walking the
+        // hierarchy on its behalf
+        if !recursion_desired && !self.config.recurse_without_rd {
+            let cached = match question.qclass {
+                QRClass::IN => self.cache.read().unwrap().lookup_response(&question.qname, question.qtype),
+                _ => None,
+            };
+            let cache_hit = cached.is_some();
+            let mut packet = cached.unwrap_or_else(|| {
+                let mut packet = DNSPacket::new();
+                packet.header.rcode = RCode::Refused;
+                packet
+            });
+            packet.header.aa = AAFlag::NonAuthoritative;
+            return Ok((packet, cache_hit));
+        }
+
+        // The upstream server may have been authoritative, but we aren't
+        let (mut result, mut cache_hit) = self.resolve_cached(&question.qname, question.qtype, question.qclass)?;
+        if let Some(prefix) = self.config.dns64_prefix {
+            let has_aaaa = result.answer.answers.iter().any(|record| matches!(record, DNSRecord::AAAA(_)));
+            if question.qtype == QRType::AAAA && result.header.rcode == RCode::NoError && !has_aaaa {
+                let (a_result, a_cache_hit) = self.resolve_cached(&question.qname, QRType::A, question.qclass)?;
+                if let Some(synthesized) = synthesize_dns64(&a_result, prefix) {
+                    result = synthesized;
+                    cache_hit = a_cache_hit;
+                }
+            }
+        }
+        result.header.aa = AAFlag::NonAuthoritative;
+        Ok((result, cache_hit))
+    }
+    /// Fetch the changes to a zone since the given SOA serial from its primary
+    /// server using IXFR, or the whole zone if the server sends a full transfer
+    pub fn incremental_transfer(&self, zone: &str, serial: u32, server: (IpAddr, u16)) -> Result<Vec<ZoneChange>,std::io::Error> {
+        transfer::incremental_transfer(zone, serial, server, self.config.timeout)
+    }
+    /// Serve queries forever, sweeping the cache on a background thread if a
+    /// sweep interval is configured
+    pub fn run(&self) -> Result<(),std::io::Error> {
+        if let Some(interval) = self.config.sweep_interval {
+            let cache = Arc::clone(&self.cache);
+            thread::Builder::new()
+                .name("cache-sweep".to_string())
+                .spawn(move || loop {
+                    thread::sleep(interval);
+                    cache.write().unwrap().sweep();
+                })?;
+        }
+
+        // For now, queries are handled sequentially, so an infinite loop for servicing
+        // requests is initiated. One receive buffer serves every query.
+        let mut req_buffer = BytePacketBuffer::with_size(self.config.edns_udp_size.max(512) as usize);
+        loop {
+            req_buffer.reset();
+            match self.handle_query_with(&mut req_buffer) {
+                Ok(_) => {},
+                Err(e) => eprintln!("An error occurred: {}", e),
+            }
+        }
+    }
+    /// Handle a single incoming packet
+    pub fn handle_query(&self) -> Result<(),std::io::Error> {
+        let mut req_buffer = BytePacketBuffer::with_size(self.config.edns_udp_size.max(512) as usize);
+        self.handle_query_with(&mut req_buffer)
+    }
+    /// Like `handle_query`, but receiving into `req_buffer` instead of a
+    /// newly allocated buffer, so a loop can reuse one. Datagrams longer
+    /// than the buffer are cut short, so it should hold at least the EDNS
+    /// UDP size.
+    pub fn handle_query_with(&self, req_buffer: &mut BytePacketBuffer) -> Result<(),std::io::Error> {
+        // With a socket ready, we can go ahead and read a packet. This will
+        // block until one is received.
+        // The `recv_from` function will write the data into the provided buffer,
+        // and return the length of the data read as well as the source address.
+        // We need to keep track of the source in order to send our reply later on.
+        let (len, src) = self.socket.recv_from(&mut req_buffer.buf)?;
+
+        let response = self.handle_datagram(&req_buffer.buf[0..len], src);
+        if !response.is_empty() {
+            self.socket.send_to(&response, src)?;
+        }
+
+        Ok(())
+    }
+    /// Process one request datagram from `src` and return the encoded
+    /// response, or nothing if the datagram should be dropped unanswered.
+    ///
+    /// This is everything `handle_query` does apart from the socket I/O, so
+    /// it can also serve other transports.
+    pub fn handle_datagram(&self, data: &[u8], src: SocketAddr) -> Vec<u8> {
+        #[cfg(feature = "dnstap")]
+        self.log_dnstap(dnstap::MessageType::ClientQuery, data, src);
+
+        let response = self.process_datagram(data, src);
+
+        #[cfg(feature = "dnstap")]
+        if !response.is_empty() {
+            self.log_dnstap(dnstap::MessageType::ClientResponse, &response, src);
+        }
 
-        // Otherwise, we'll try to find a new nameserver based on NS and a corresponding A
-        // record in the additional section. If this succeeds, we can switch name server
-        // and retry the loop.
-        if let Some(new_ns) = response.get_resolved_ns(qname) {
-            ns = new_ns;
+        response
+    }
+    // Write a client message to the dnstap log, if one is set. Logging is
+    // best effort and never holds up answering.
+    #[cfg(feature = "dnstap")]
+    fn log_dnstap(&self, message_type: dnstap::MessageType, message: &[u8], src: SocketAddr) {
+        if let Some(writer) = &self.dnstap {
+            if let Err(e) = writer.lock().unwrap().log(message_type, src, self.socket.local_addr().ok(), message) {
+                eprintln!("dnstap logging failed: {}", e);
+            }
+        }
+    }
+    fn process_datagram(&self, data: &[u8], src: SocketAddr) -> Vec<u8> {
+        // A datagram too short to hold a header, such as the empty ones some
+        // scanners send, isn't a query and has no ID to reply to, so drop it.
+        if data.len() < 12 {
+            println!("dropping {} byte datagram from {}", data.len(), src);
+            return Vec::new();
+        }
 
-            continue;
+        // A datagram with the QR bit set claims to be a response. Answering
+        // it, even with `FORMERR`, would let a spoofed source bounce traffic
+        // off us or set two servers replying to each other, so drop it too.
+        if data[2] & 0x80 != 0 {
+            println!("dropping response datagram from {}", src);
+            return Vec::new();
         }
 
-        // If not, we'll have to resolve the ip of a NS record. If no NS records exist,
-        // we'll go with what the last server told us.
-        let new_ns_name = match response.get_unresolved_ns(qname) {
-            Some(x) => x,
-            None => return Ok(response),
+        // Next, `DNSPacket::from_slice` is used to parse the bytes received into
+        // a `DnsPacket`. If that fails the request is malformed, which we
+        // report with `FORMERR` under the ID from its first two bytes.
+        let mut request = match DNSPacket::from_slice(data, ParseMode::Lenient) {
+            Ok(request) => request,
+            Err(e) => {
+                println!("malformed request from {}: {}", src, e);
+                let mut packet = DNSPacket::new();
+                packet.header.id = u16::from_be_bytes([data[0], data[1]]);
+                packet.header.qr = QRFlag::Response;
+                packet.header.rcode = RCode::FormErr;
+                return encode_response(&mut packet, 512);
+            }
         };
 
-        // Here we go down the rabbit hole by starting _another_ lookup sequence in the
-        // midst of our current one. Hopefully, this will give us the IP of an appropriate
-        // name server.
-        let recursive_response = recursive_lookup(&new_ns_name, QRType::A)?;
+        for warning in &request.warnings {
+            println!("tolerated in request from {}: {}", src, warning);
+        }
+
+        // Create and initialize the response packet
+        let mut packet = DNSPacket::response_to(&request);
+        if !self.config.recursion_available {
+            packet.header.ra = RAFlag::NonAvailable;
+        }
+
+        // Echo the client's checking-disabled flag. The data is only marked
+        // as authenticated if every answer was validated, below.
+        packet.header.cd = request.header.cd;
+        packet.header.ad = ADFlag::NonAuthenticated;
+        let mut authenticated = true;
+
+        // Only standard queries are supported. Other opcodes, such as
+        // UPDATE and NOTIFY, are well-formed requests we don't implement.
+        if request.header.opcode != OpCode::Query {
+            packet.header.rcode = RCode::NotImp;
+            return encode_response(&mut packet, 512);
+        }
+
+        // Duplicate questions would only be resolved again for the same
+        // answer, so each distinct question is kept once, in order
+        let asked = request.question.questions.len();
+        let mut questions: Vec<DNSQuestion> = Vec::new();
+        for question in request.question.questions.drain(..) {
+            if !questions.iter().any(|seen| same_question(seen, &question)) {
+                questions.push(question);
+            }
+        }
 
-        // Finally, we pick a random ip from the result, and restart the loop. If no such
-        // record is available, we again return the last result we got.
-        if let Some(new_ns) = recursive_response.get_random_a() {
-            ns = new_ns;
+        // Being mindful of how unreliable input data from arbitrary senders can be, we
+        // need make sure that a question is actually present. If not, we return `FORMERR`
+        // to indicate that the sender made something wrong.
+        packet.question.questions = questions.clone();
+        if questions.is_empty() {
+            packet.header.rcode = RCode::FormErr;
         } else {
-            return Ok(response);
+            packet.header.aa = AAFlag::Authoritative;
+        }
+
+        for question in questions {
+            println!("Received query: {:?}", question);
+
+            // Since all is set up and as expected, the query can be forwarded to the
+            // target server. There's always the possibility that the query will
+            // fail, in which case the `SERVFAIL` response code is set to indicate
+            // as much to the client. If rather everything goes as planned, the
+            // response records are copied into our response packet.
+            // The first question that fails decides the response code, and the
+            // response is only authoritative if every answer was.
+            let start = Instant::now();
+            let outcome = self.answer(&question, request.header.rd == RDFlag::Desired);
+            if let Some(callback) = &self.on_query {
+                let (rcode, cache_hit) = match &outcome {
+                    Ok((result, cache_hit)) => (result.header.rcode, *cache_hit),
+                    Err(_) => (RCode::ServFail, false),
+                };
+                callback(&QueryEvent { qname: question.qname.clone(), qtype: question.qtype, rcode, cache_hit, latency: start.elapsed() });
+            }
+
+            if let Ok((result, _)) = outcome {
+                if packet.header.rcode == RCode::NoError {
+                    packet.header.rcode = result.header.rcode;
+                }
+                if result.header.aa == AAFlag::NonAuthoritative {
+                    packet.header.aa = AAFlag::NonAuthoritative;
+                }
+                if result.header.ad == ADFlag::NonAuthenticated {
+                    authenticated = false;
+                }
+
+                for rec in result.answer.answers {
+                    println!("Answer: {:?}", rec);
+                    packet.answer.answers.push(rec);
+                }
+                for rec in result.authority.records {
+                    println!("Authority: {:?}", rec);
+                    packet.authority.records.push(rec);
+                }
+                // Upstream OPT and other pseudo-records describe that exchange, not ours
+                for rec in result.additional.records.into_iter().filter(|rec| !rec.preamble().rtype.is_pseudo()) {
+                    println!("Resource: {:?}", rec);
+                    packet.additional.records.push(rec);
+                }
+            } else {
+                if packet.header.rcode == RCode::NoError {
+                    packet.header.rcode = RCode::ServFail;
+                }
+                packet.header.aa = AAFlag::NonAuthoritative;
+                authenticated = false;
+            }
+        }
+        if authenticated && !packet.question.questions.is_empty() {
+            packet.header.ad = ADFlag::Authenticated;
         }
+
+        // The response's questions are the request's unless duplicates were
+        // dropped, in which case the original bytes no longer match
+        if self.config.preserve_question_bytes && packet.question.questions.len() == asked {
+            packet.question.raw = DNSPacket::question_bytes(data).ok();
+        }
+
+        // Sections were copied from separate answers and upstreams, so may
+        // repeat the answer records
+        packet.collapse_duplicates();
+
+        if let Some(ttl) = self.config.ttl_override {
+            let records = packet.answer.answers.iter_mut()
+                .chain(packet.authority.records.iter_mut())
+                .chain(packet.additional.records.iter_mut());
+            for record in records {
+                record.preamble_mut().ttl = ttl;
+            }
+        }
+
+        // An EDNS query gets an OPT record back advertising what we accept
+        if request.edns_udp_size().is_some() {
+            let opt = DNSOPTRecord::new(self.config.edns_udp_size, Vec::new());
+            packet.additional.records.push(DNSRecord::OPT(opt));
+        }
+
+        // The only thing remaining is to encode our response
+        encode_response(&mut packet, self.response_size_limit(&request))
+    }
+}
+
+// Encode a response in at most `limit` bytes. One that doesn't fit is sent
+// with just its question and OPT record and the TC flag set, so the client
+// retries over TCP. If encoding fails otherwise, e.g. because a record is
+// too large, fall back to a bare SERVFAIL so the client still gets an answer.
+fn encode_response(packet: &mut DNSPacket, limit: usize) -> Vec<u8> {
+    // A packet nothing was filled in would go out as a malformed query
+    if packet.is_empty_query() {
+        eprintln!("refusing to send an empty packet as a response, sending SERVFAIL");
+        packet.header.qr = QRFlag::Response;
+        packet.header.rcode = RCode::ServFail;
+    }
+
+    let mut res_buffer = BytePacketBuffer::with_size(limit);
+    let mut result = packet.write(&mut res_buffer);
+    if matches!(&result, Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof) {
+        packet.header.tc = TCFlag::Truncated;
+        packet.answer.answers.clear();
+        packet.authority.records.clear();
+        packet.additional.records.retain(|record| matches!(record, DNSRecord::OPT(_)));
+        res_buffer = BytePacketBuffer::with_size(limit);
+        result = packet.write(&mut res_buffer);
+    }
+    if let Err(e) = result {
+        eprintln!("failed to encode response: {}", e);
+        let mut failure = DNSPacket::new();
+        failure.header.id = packet.header.id;
+        failure.header.opcode = packet.header.opcode;
+        failure.header.qr = QRFlag::Response;
+        failure.header.rcode = RCode::ServFail;
+        res_buffer = BytePacketBuffer::new();
+        if failure.write(&mut res_buffer).is_err() {
+            return Vec::new();
+        }
+    }
+
+    res_buffer.buf[0..res_buffer.pos()].to_vec()
+}
+
+// The transport carrying upstream queries as `config` describes
+fn upstream_transport(config: &ResolverConfig) -> Result<Arc<dyn Transport + Send + Sync>,std::io::Error> {
+    Ok(match &config.upstream {
+        Upstream::Plain => match config.query_source_addr {
+            Some(source) => Arc::new(UdpTransport::with_source(source)),
+            None => Arc::new(UdpTransport::new()),
+        },
+        #[cfg(feature = "dot")]
+        Upstream::Tls(hostname) => Arc::new(transport::TlsTransport::new(hostname)?),
+        #[cfg(feature = "doh")]
+        Upstream::Https(url) => Arc::new(transport::DohTransport::new(url)),
+    })
+}
+
+// How many bytes the answer section of `packet` takes up encoded
+fn answer_size(packet: &DNSPacket) -> Result<usize,std::io::Error> {
+    let mut buffer = BytePacketBuffer::with_size(u16::MAX as usize);
+    for record in &packet.answer.answers {
+        record.write(&mut buffer)?;
+    }
+    Ok(buffer.pos())
+}
+
+// Whether `child` is strictly below `parent`; names compare case-insensitively
+// and the root is the empty name
+fn is_proper_subdomain(child: &str, parent: &str) -> bool {
+    let child = child.trim_end_matches('.').to_ascii_lowercase();
+    let parent = parent.trim_end_matches('.').to_ascii_lowercase();
+    if parent.is_empty() {
+        return !child.is_empty();
+    }
+    child.ends_with(&format!(".{}", parent))
+}
+
+// Whether two questions ask the same thing; names compare case-insensitively
+fn same_question(a: &DNSQuestion, b: &DNSQuestion) -> bool {
+    a.qname.eq_ignore_ascii_case(&b.qname) && a.qtype == b.qtype && a.qclass == b.qclass
+}
+
+// A response has to echo the question it answers, or it may be an answer
+// to a different query, from a confused upstream or a spoofer. Only error
+// responses may leave the question out.
+fn check_question_echo(request: &DNSPacket, response: &DNSPacket) -> Result<(),std::io::Error> {
+    let sent = &request.question.questions;
+    let echoed = &response.question.questions;
+    let matches = echoed.len() == sent.len()
+        && sent.iter().zip(echoed).all(|(a, b)| {
+            a.qname.trim_end_matches('.').eq_ignore_ascii_case(b.qname.trim_end_matches('.')) && a.qtype == b.qtype && a.qclass == b.qclass
+        });
+    if matches || (echoed.is_empty() && response.header.rcode != RCode::NoError) {
+        return Ok(());
     }
+    let echoed: Vec<String> = echoed.iter().map(|q| format!("{} {}", q.qname, q.qtype)).collect();
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Response doesn't echo the question sent, but [{}]", echoed.join(", "))))
 }
-/// Handle a single incoming packet
-pub fn handle_query(socket: &UdpSocket) -> Result<(),std::io::Error> {
-    // With a socket ready, we can go ahead and read a packet. This will
-    // block until one is received.
-    let mut req_buffer = BytePacketBuffer::new();
-
-    // The `recv_from` function will write the data into the provided buffer,
-    // and return the length of the data read as well as the source address.
-    // We're not interested in the length, but we need to keep track of the
-    // source in order to send our reply later on.
-    let (_, src) = socket.recv_from(&mut req_buffer.buf)?;
-
-    // Next, `DnsPacket::from_buffer` is used to parse the raw bytes into
-    // a `DnsPacket`.
-    let mut request = DNSPacket::from_buffer(&mut req_buffer)?;
-
-    // Create and initialize the response packet
+
+// Turn the answer to an A query into one for AAAA by mapping each address
+// into the DNS64 prefix, keeping any CNAME chain. `None` if there were no A
+// records to map.
+fn synthesize_dns64(a_result: &DNSPacket, prefix: Ipv6Addr) -> Option<DNSPacket> {
+    if !a_result.answer.answers.iter().any(|record| matches!(record, DNSRecord::A(_))) {
+        return None;
+    }
+
     let mut packet = DNSPacket::new();
-    packet.header.id = request.header.id;
-    packet.header.rd = RDFlag::Desired;
-    packet.header.ra = RAFlag::Available;
-    packet.header.qr = QRFlag::Response;
-
-    // In the normal case, exactly one question is present
-    if let Some(question) = request.question.questions.pop() {
-        println!("Received query: {:?}", question);
-
-        // Since all is set up and as expected, the query can be forwarded to the
-        // target server. There's always the possibility that the query will
-        // fail, in which case the `SERVFAIL` response code is set to indicate
-        // as much to the client. If rather everything goes as planned, the
-        // question and response records as copied into our response packet.
-        if let Ok(result) = recursive_lookup(&question.qname, question.qtype) {
-            packet.question.questions.push(question.clone());
-            packet.header.rcode = result.header.rcode;
-
-            for rec in result.answer.answers {
-                println!("Answer: {:?}", rec);
-                packet.answer.answers.push(rec);
-            }
-            for rec in result.authority.records {
-                println!("Authority: {:?}", rec);
-                packet.authority.records.push(rec);
-            }
-            for rec in result.additional.records {
-                println!("Resource: {:?}", rec);
-                packet.additional.records.push(rec);
+    packet.header.rcode = a_result.header.rcode;
+    for record in &a_result.answer.answers {
+        match record {
+            DNSRecord::A(a_record) => {
+                let mut octets = prefix.octets();
+                octets[12..].copy_from_slice(&a_record.rdata.octets());
+                let preamble = &a_record.preamble;
+                packet.answer.add_answer(DNSRecord::AAAA(DNSAAAARecord::new(preamble.name.clone(), preamble.class, preamble.ttl, Ipv6Addr::from(octets))));
             }
-        } else {
-            packet.header.rcode = RCode::ServFail;
+            DNSRecord::CNAME(_) => packet.answer.add_answer(record.clone()),
+            _ => {}
+        }
+    }
+    Some(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use crate::message::records::{DNSARecord, DNSCNAMERecord, DNSNSRecord, DNSTXTRecord};
+
+    type Queries = Arc<Mutex<Vec<(String, QRType, IpAddr)>>>;
+
+    // An upstream network answering each query from `answer`, given the
+    // query and the address of the server it was sent to
+    struct FakeNet<F> {
+        answer: F,
+        queries: Queries,
+    }
+
+    impl<F> Transport for FakeNet<F>
+    where
+        F: Fn(&DNSPacket, IpAddr) -> Result<DNSPacket,std::io::Error> + Send + Sync,
+    {
+        fn send_query(&self, packet: &mut DNSPacket, server: (IpAddr, u16), _timeout: Duration) -> Result<DNSPacket,std::io::Error> {
+            let question = &packet.question.questions[0];
+            self.queries.lock().unwrap().push((question.qname.clone(), question.qtype, server.0));
+            (self.answer)(packet, server.0)
+        }
+    }
+
+    fn fake_resolver<F>(answer: F) -> (DNSResolver, Queries)
+    where
+        F: Fn(&DNSPacket, IpAddr) -> Result<DNSPacket,std::io::Error> + Send + Sync + 'static,
+    {
+        let queries = Queries::default();
+        let mut resolver = DNSResolver::new("127.0.0.1", 0).unwrap();
+        resolver.set_transport(Box::new(FakeNet { answer, queries: queries.clone() }));
+        (resolver, queries)
+    }
+
+    const ROOT: IpAddr = IpAddr::V4(roots::ROOT_HINTS[0].1);
+
+    // A referral to `zone` served by `host`, with glue if `glue` is given
+    fn referral(request: &DNSPacket, zone: &str, host: &str, glue: Option<Ipv4Addr>) -> DNSPacket {
+        let mut response = DNSPacket::response_to(request);
+        response.authority.add_record(DNSRecord::NS(DNSNSRecord::new(zone.to_string(), QRClass::IN, 3600, host.to_string())));
+        if let Some(address) = glue {
+            response.additional.add_record(DNSRecord::A(DNSARecord::new(host.to_string(), QRClass::IN, 3600, address)));
+        }
+        response
+    }
+
+    fn a_answer(request: &DNSPacket, address: Ipv4Addr) -> DNSPacket {
+        let mut response = DNSPacket::response_to(request);
+        let name = request.question.questions[0].qname.clone();
+        response.answer.add_answer(DNSRecord::A(DNSARecord::new(name, QRClass::IN, 300, address)));
+        response
+    }
+
+    #[test]
+    fn glueless_name_servers_resolving_through_each_other_terminate() {
+        // a.test is served by ns.b.test and b.test by ns.a.test, neither
+        // with glue, so neither name server's address can ever be found
+        let (resolver, queries) = fake_resolver(|request, _| {
+            let qname = request.question.questions[0].qname.clone();
+            if qname.ends_with("a.test") {
+                Ok(referral(request, "a.test", "ns.b.test", None))
+            } else {
+                Ok(referral(request, "b.test", "ns.a.test", None))
+            }
+        });
+
+        let response = resolver.resolve("www.a.test", QRType::A, QRClass::IN).unwrap();
+        assert!(response.answer.answers.is_empty());
+        // www.a.test, then ns.b.test, then ns.a.test, which is referred
+        // back to ns.b.test, already being resolved
+        assert_eq!(queries.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn glued_name_server_is_preferred_and_glueless_ones_resolved_once() {
+        let (resolver, queries) = fake_resolver(|request, server| {
+            let qname = request.question.questions[0].qname.to_ascii_lowercase();
+            match (server, qname.as_str()) {
+                (ROOT, "ns.glueless.test") => Ok(referral(request, "glueless.test", "ns.glued.test", Some(Ipv4Addr::new(192, 0, 2, 53)))),
+                (ROOT, name) if name.ends_with("example.test") => {
+                    let mut response = referral(request, "example.test", "ns.glueless.test", None);
+                    response.authority.add_record(DNSRecord::NS(DNSNSRecord::new("example.test".to_string(), QRClass::IN, 3600, "ns.glued.test".to_string())));
+                    response.additional.add_record(DNSRecord::A(DNSARecord::new("ns.glued.test".to_string(), QRClass::IN, 3600, Ipv4Addr::new(192, 0, 2, 53))));
+                    Ok(response)
+                }
+                (ROOT, _) => Ok(referral(request, "other.test", "ns.glueless.test", None)),
+                (_, "ns.glueless.test") => Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 54))),
+                _ => Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1))),
+            }
+        });
+
+        // Glue for one of example.test's name servers means no other lookup
+        resolver.resolve("www.example.test", QRType::A, QRClass::IN).unwrap();
+        assert_eq!(queries.lock().unwrap().len(), 2);
+        assert_eq!(queries.lock().unwrap()[1].2, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53)));
+
+        // Within one resolution, a glueless name server is only looked up once
+        // however many referrals name it
+        let mut ns_addrs = HashMap::new();
+        queries.lock().unwrap().clear();
+        resolver.recursive_lookup_with("a.other.test", QRType::A, QRClass::IN, &mut ns_addrs, &mut None).unwrap();
+        resolver.recursive_lookup_with("b.other.test", QRType::A, QRClass::IN, &mut ns_addrs, &mut None).unwrap();
+        let lookups = queries.lock().unwrap().iter().filter(|(name, _, server)| name == "ns.glueless.test" && *server == ROOT).count();
+        assert_eq!(lookups, 1);
+        assert_eq!(ns_addrs.get("ns.glueless.test"), Some(&Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 54)))));
+    }
+
+    #[test]
+    fn delegation_back_to_the_same_zone_is_lame() {
+        // The root refers example.test to its server, which refers it to
+        // itself again
+        let (resolver, queries) = fake_resolver(|request, _| Ok(referral(request, "example.test", "ns.example.test", Some(Ipv4Addr::new(192, 0, 2, 53)))));
+
+        let error = resolver.resolve("www.example.test", QRType::A, QRClass::IN).unwrap_err();
+        assert!(error.to_string().contains("Lame delegation"));
+        assert_eq!(queries.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn ns_records_for_unrelated_zones_are_returned_as_is() {
+        let (resolver, queries) = fake_resolver(|request, _| Ok(referral(request, "elsewhere.test", "ns.elsewhere.test", Some(Ipv4Addr::new(192, 0, 2, 53)))));
+
+        let response = resolver.resolve("www.example.test", QRType::A, QRClass::IN).unwrap();
+        assert_eq!(response.header.rcode, RCode::NoError);
+        assert_eq!(response.authority.records.len(), 1);
+        assert_eq!(queries.lock().unwrap().len(), 1);
+    }
+
+    // An answer to `request` made of `len` CNAMEs, each pointing at the next
+    fn cname_chain(request: &DNSPacket, len: usize) -> DNSPacket {
+        let mut response = DNSPacket::response_to(request);
+        let mut name = request.question.questions[0].qname.clone();
+        for i in 0..len {
+            let target = format!("c{}.example.test", i);
+            response.answer.add_answer(DNSRecord::CNAME(DNSCNAMERecord::new(name, QRClass::IN, 300, target.clone())));
+            name = target;
+        }
+        response
+    }
+
+    #[test]
+    fn long_cname_chain_exceeds_record_limit() {
+        let (resolver, _) = fake_resolver(|request, _| Ok(cname_chain(request, 150)));
+        let error = resolver.resolve("www.example.test", QRType::A, QRClass::IN).unwrap_err();
+        assert!(error.to_string().contains("limit of 100 records"));
+
+        // Referrals along the way don't count, however many records they carry
+        let (resolver, _) = fake_resolver(|request, server| match server {
+            ROOT => {
+                let mut response = referral(request, "example.test", "ns.example.test", Some(Ipv4Addr::new(192, 0, 2, 53)));
+                for i in 0..150 {
+                    let host = format!("ns{}.example.test", i);
+                    response.authority.add_record(DNSRecord::NS(DNSNSRecord::new("example.test".to_string(), QRClass::IN, 3600, host)));
+                }
+                Ok(response)
+            }
+            _ => Ok(cname_chain(request, 99)),
+        });
+        assert_eq!(resolver.resolve("www.example.test", QRType::A, QRClass::IN).unwrap().answer.answers.len(), 99);
+    }
+
+    #[test]
+    fn large_answer_exceeds_size_limit() {
+        let (mut resolver, _) = fake_resolver(|request, _| {
+            let mut response = DNSPacket::response_to(request);
+            for i in 0..10 {
+                let text = format!("{:0>100}", i);
+                response.answer.add_answer(DNSRecord::TXT(DNSTXTRecord::new("www.example.test".to_string(), QRClass::IN, 300, text)));
+            }
+            Ok(response)
+        });
+        resolver.set_max_response_size(512);
+        let error = resolver.resolve("www.example.test", QRType::TXT, QRClass::IN).unwrap_err();
+        assert!(error.to_string().contains("limit of 512 bytes"));
+
+        resolver.set_max_response_size(2048);
+        assert_eq!(resolver.resolve("www.example.test", QRType::TXT, QRClass::IN).unwrap().answer.answers.len(), 10);
+    }
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let (mut resolver, queries) = fake_resolver(|_, _| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out")));
+        let delays = Arc::new(Mutex::new(Vec::new()));
+        let recorded = delays.clone();
+        resolver.set_sleeper(Box::new(move |delay| recorded.lock().unwrap().push(delay)));
+        resolver.set_retries(5);
+        resolver.set_retry_backoff(Duration::from_millis(100), Duration::from_millis(500));
+
+        let error = resolver.lookup("example.test", QRType::A, QRClass::IN, (ROOT, 53)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(queries.lock().unwrap().len(), 6);
+        let expected: Vec<Duration> = [100, 200, 400, 500, 500].iter().map(|ms| Duration::from_millis(*ms)).collect();
+        assert_eq!(*delays.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn truncated_reply_is_retried_over_tcp_from_the_source_address() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(server).unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, client) = udp.recv_from(&mut buf).unwrap();
+            let request = DNSPacket::from_slice(&buf[0..len], ParseMode::Strict).unwrap();
+            let mut truncated = DNSPacket::response_to(&request);
+            truncated.header.tc = TCFlag::Truncated;
+            let mut buffer = BytePacketBuffer::new();
+            truncated.write(&mut buffer).unwrap();
+            udp.send_to(&buffer.buf[0..buffer.pos], client).unwrap();
+
+            let (mut stream, peer) = tcp.accept().unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut message = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut message).unwrap();
+            let request = DNSPacket::from_slice(&message, ParseMode::Strict).unwrap();
+            let mut buffer = BytePacketBuffer::new();
+            a_answer(&request, Ipv4Addr::new(192, 0, 2, 1)).write(&mut buffer).unwrap();
+            stream.write_all(&(buffer.pos as u16).to_be_bytes()).unwrap();
+            stream.write_all(&buffer.buf[0..buffer.pos]).unwrap();
+            peer
+        });
+
+        let mut resolver = DNSResolver::new("127.0.0.1", 0).unwrap();
+        // Another loopback address than the server's, so the connection can
+        // only come from it if it was bound
+        let source = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+        resolver.set_query_source_addr(Some(source));
+        let response = resolver.lookup("example.test", QRType::A, QRClass::IN, (server.ip(), server.port())).unwrap();
+        let peer = handle.join().unwrap();
+
+        assert_eq!(response.all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+        assert_eq!(peer.ip(), source);
+    }
+
+    #[test]
+    fn formerr_to_an_edns_query_is_retried_without_edns() {
+        let (resolver, queries) = fake_resolver(|request, _| {
+            if request.edns_udp_size().is_some() {
+                let mut response = DNSPacket::response_to(request);
+                response.header.rcode = RCode::FormErr;
+                Ok(response)
+            } else {
+                Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1)))
+            }
+        });
+
+        let response = resolver.lookup("example.test", QRType::A, QRClass::IN, (ROOT, 53)).unwrap();
+        assert_eq!(response.all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+        assert_eq!(queries.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn outgoing_opt_record_carries_the_client_subnet() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let recorded = sent.clone();
+        let (mut resolver, _) = fake_resolver(move |request, _| {
+            let mut buffer = BytePacketBuffer::new();
+            request.clone().write(&mut buffer).unwrap();
+            *recorded.lock().unwrap() = buffer.buf[0..buffer.pos].to_vec();
+            Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1)))
+        });
+        resolver.set_client_subnet(Some((IpAddr::V4(Ipv4Addr::new(198, 51, 100, 77)), 24))).unwrap();
+        resolver.lookup("example.test", QRType::A, QRClass::IN, (ROOT, 53)).unwrap();
+
+        // Option 8 of 7 bytes: family 1, source prefix 24, scope 0 and the
+        // address cut to the 3 bytes the prefix covers
+        let sent = sent.lock().unwrap();
+        assert!(sent.ends_with(&[0, 11, 0, 8, 0, 7, 0, 1, 24, 0, 198, 51, 100]));
+    }
+
+    fn datagram(opcode: OpCode) -> Vec<u8> {
+        let mut request = DNSPacket::new();
+        request.header.id = 4242;
+        request.header.opcode = opcode;
+        request.question.add_question(DNSQuestion::new("example.test".to_string(), QRType::A, QRClass::IN));
+        let mut buffer = BytePacketBuffer::new();
+        request.write(&mut buffer).unwrap();
+        buffer.buf[0..buffer.pos].to_vec()
+    }
+
+    const CLIENT: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5353);
+
+    #[test]
+    fn unimplemented_opcodes_are_refused_without_upstream_traffic() {
+        let (resolver, queries) = fake_resolver(|request, _| Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1))));
+        for opcode in [OpCode::IQuery, OpCode::Update] {
+            let response = DNSPacket::from_slice(&resolver.handle_datagram(&datagram(opcode), CLIENT), ParseMode::Strict).unwrap();
+            assert_eq!(response.header.id, 4242);
+            assert_eq!(response.header.qr, QRFlag::Response);
+            assert_eq!(response.header.rcode, RCode::NotImp);
+        }
+        assert!(queries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_datagram_is_answered_with_formerr_unless_it_is_a_response() {
+        let (resolver, _) = fake_resolver(|request, _| Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1))));
+
+        // One question is claimed but none follows the header
+        let mut malformed = datagram(OpCode::Query)[0..12].to_vec();
+        let response = DNSPacket::from_slice(&resolver.handle_datagram(&malformed, CLIENT), ParseMode::Strict).unwrap();
+        assert_eq!(response.header.id, 4242);
+        assert_eq!(response.header.rcode, RCode::FormErr);
+
+        malformed[2] |= 0x80;
+        assert!(resolver.handle_datagram(&malformed, CLIENT).is_empty());
+        assert!(resolver.handle_datagram(&[0; 4], CLIENT).is_empty());
+    }
+
+    #[test]
+    fn response_to_a_different_question_is_rejected() {
+        let (resolver, _) = fake_resolver(|request, _| {
+            let mut response = a_answer(request, Ipv4Addr::new(192, 0, 2, 1));
+            response.question.questions[0].qname = "attacker.test".to_string();
+            Ok(response)
+        });
+        let error = resolver.lookup("example.test", QRType::A, QRClass::IN, (ROOT, 53)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("attacker.test"));
+
+        // Type and class have to match too, but not the name's case
+        for (qtype, qclass) in [(QRType::AAAA, QRClass::IN), (QRType::A, QRClass::CH)] {
+            let (resolver, _) = fake_resolver(move |request, _| {
+                let mut response = a_answer(request, Ipv4Addr::new(192, 0, 2, 1));
+                response.question.questions[0].qtype = qtype;
+                response.question.questions[0].qclass = qclass;
+                Ok(response)
+            });
+            assert!(resolver.lookup("example.test", QRType::A, QRClass::IN, (ROOT, 53)).is_err());
+        }
+        let (resolver, _) = fake_resolver(|request, _| {
+            let mut response = a_answer(request, Ipv4Addr::new(192, 0, 2, 1));
+            response.question.questions[0].qname = "EXAMPLE.test".to_string();
+            Ok(response)
+        });
+        assert!(resolver.lookup("example.test", QRType::A, QRClass::IN, (ROOT, 53)).is_ok());
+    }
+
+    #[test]
+    fn clones_share_one_cache_across_threads() {
+        let (resolver, _) = fake_resolver(|request, _| Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1))));
+
+        // Workers look up and insert overlapping names while another keeps
+        // taking the write lock to sweep
+        let workers: Vec<_> = (0..8)
+            .map(|worker| {
+                let resolver = resolver.clone();
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        let question = DNSQuestion::new(format!("host{}.example.test", (worker + i) % 4), QRType::A, QRClass::IN);
+                        let (response, _) = resolver.answer(&question, true).unwrap();
+                        assert_eq!(response.answer.answers.len(), 1);
+                    }
+                })
+            })
+            .collect();
+        let sweeper = {
+            let resolver = resolver.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    resolver.cache.write().unwrap().sweep();
+                }
+            })
+        };
+        for handle in workers.into_iter().chain(std::iter::once(sweeper)) {
+            handle.join().unwrap();
         }
+
+        // Every clone wrote to the original's cache
+        assert_eq!(resolver.cache.read().unwrap().len(), 4);
+        let question = DNSQuestion::new("host0.example.test".to_string(), QRType::A, QRClass::IN);
+        assert!(resolver.answer(&question, true).unwrap().1);
+    }
+
+    #[test]
+    fn saturated_lookup_limit_sheds_excess_queries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // A slow upstream that records how many queries it serves at once
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (mut resolver, _) = {
+            let (running, peak) = (running.clone(), peak.clone());
+            fake_resolver(move |request, _| {
+                peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(300));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1)))
+            })
+        };
+        resolver.set_max_concurrent_lookups(2);
+        resolver.set_timeout(Duration::from_millis(50));
+
+        let clients: Vec<_> = (0..6)
+            .map(|i| {
+                let resolver = resolver.clone();
+                std::thread::spawn(move || {
+                    let question = DNSQuestion::new(format!("host{}.example.test", i), QRType::A, QRClass::IN);
+                    resolver.answer(&question, true).is_ok()
+                })
+            })
+            .collect();
+        let answered = clients.into_iter().map(|handle| handle.join().unwrap()).filter(|ok| *ok).count();
+
+        assert_eq!(answered, 2);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(resolver.lookups.in_flight(), 0);
+    }
+
+    #[test]
+    fn authoritative_only_server_refuses_names_outside_its_zones() {
+        let (mut resolver, queries) = fake_resolver(|request, _| Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1))));
+        let mut zone = Zone::new("local.test");
+        zone.add_record(DNSRecord::A(DNSARecord::new("www.local.test".to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, 53))));
+        resolver.add_zone(zone);
+        resolver.set_recursion_available(false);
+        let query = |qname: &str| {
+            let mut request = DNSPacket::new();
+            request.header.id = 4242;
+            request.header.rd = RDFlag::Desired;
+            request.question.add_question(DNSQuestion::new(qname.to_string(), QRType::A, QRClass::IN));
+            let mut buffer = BytePacketBuffer::new();
+            request.write(&mut buffer).unwrap();
+            DNSPacket::from_slice(&resolver.handle_datagram(&buffer.buf[0..buffer.pos], CLIENT), ParseMode::Strict).unwrap()
+        };
+
+        let outside = query("example.test");
+        assert_eq!(outside.header.id, 4242);
+        assert_eq!(outside.header.ra, RAFlag::NonAvailable);
+        assert_eq!(outside.header.rcode, RCode::Refused);
+        assert!(outside.answer.answers.is_empty());
+
+        // Names in the zone are still answered
+        let inside = query("www.local.test");
+        assert_eq!(inside.header.ra, RAFlag::NonAvailable);
+        assert_eq!(inside.header.aa, AAFlag::Authoritative);
+        assert_eq!(inside.all_a(), vec![Ipv4Addr::new(192, 0, 2, 53)]);
+
+        assert!(queries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn query_without_rd_is_not_recursed() {
+        let (mut resolver, queries) = fake_resolver(|request, _| Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1))));
+        let query = |resolver: &DNSResolver, qname: &str, rd: RDFlag| {
+            let mut request = DNSPacket::new();
+            request.header.id = 4242;
+            request.header.rd = rd;
+            request.question.add_question(DNSQuestion::new(qname.to_string(), QRType::A, QRClass::IN));
+            let mut buffer = BytePacketBuffer::new();
+            request.write(&mut buffer).unwrap();
+            DNSPacket::from_slice(&resolver.handle_datagram(&buffer.buf[0..buffer.pos], CLIENT), ParseMode::Strict).unwrap()
+        };
+
+        // Nothing is cached yet, so the query is refused
+        let refused = query(&resolver, "example.test", RDFlag::NonDesired);
+        assert_eq!(refused.header.id, 4242);
+        assert_eq!(refused.header.rcode, RCode::Refused);
+        assert!(refused.answer.answers.is_empty());
+        assert!(queries.lock().unwrap().is_empty());
+
+        // Once another client's query has been resolved it is answered from
+        // the cache, still without going upstream
+        assert_eq!(query(&resolver, "example.test", RDFlag::Desired).header.rcode, RCode::NoError);
+        let sent = queries.lock().unwrap().len();
+        let cached = query(&resolver, "example.test", RDFlag::NonDesired);
+        assert_eq!(cached.header.rcode, RCode::NoError);
+        assert_eq!(cached.answer.answers.len(), 1);
+        assert_eq!(queries.lock().unwrap().len(), sent);
+
+        resolver.set_recurse_without_rd(true);
+        assert_eq!(query(&resolver, "other.test", RDFlag::NonDesired).header.rcode, RCode::NoError);
+        assert!(queries.lock().unwrap().len() > sent);
+    }
+
+    #[test]
+    fn failed_resolution_is_answered_with_servfail_and_the_request_id() {
+        let (resolver, _) = fake_resolver(|_, _| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "no reply")));
+        let mut request = DNSPacket::from_slice(&datagram(OpCode::Query), ParseMode::Strict).unwrap();
+        request.header.rd = RDFlag::Desired;
+        let mut buffer = BytePacketBuffer::new();
+        request.write(&mut buffer).unwrap();
+
+        let response = DNSPacket::from_slice(&resolver.handle_datagram(&buffer.buf[0..buffer.pos], CLIENT), ParseMode::Strict).unwrap();
+        assert!(!response.is_empty_query());
+        assert_eq!(response.header.id, 4242);
+        assert_eq!(response.header.qr, QRFlag::Response);
+        assert_eq!(response.header.rcode, RCode::ServFail);
+
+        // A response nothing was filled in never goes out as it is
+        let mut empty = DNSPacket::new();
+        assert!(empty.is_empty_query());
+        let sent = DNSPacket::from_slice(&encode_response(&mut empty, 512), ParseMode::Strict).unwrap();
+        assert_eq!(sent.header.qr, QRFlag::Response);
+        assert_eq!(sent.header.rcode, RCode::ServFail);
+    }
+
+    #[test]
+    fn question_bytes_are_echoed_only_when_asked_for() {
+        let (mut resolver, _) = fake_resolver(|request, _| Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1))));
+        let mut request = DNSPacket::new();
+        request.header.id = 4242;
+        request.header.rd = RDFlag::Desired;
+        request.question.add_question(DNSQuestion::new("ExAmPlE.test".to_string(), QRType::A, QRClass::IN));
+        let mut buffer = BytePacketBuffer::new();
+        request.write(&mut buffer).unwrap();
+        let data = buffer.buf[0..buffer.pos].to_vec();
+
+        // Without the option the name is re-encoded as parsed
+        let response = resolver.handle_datagram(&data, CLIENT);
+        assert_eq!(&response[13..20], b"example");
+
+        resolver.set_preserve_question_bytes(true);
+        let response = resolver.handle_datagram(&data, CLIENT);
+        assert_eq!(&response[12..data.len()], &data[12..]);
+    }
+
+    #[test]
+    fn forwarders_passed_over_are_noted_on_the_response() {
+        const FAILING: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53));
+        const BROKEN: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 54));
+        let (mut resolver, queries) = fake_resolver(|request, server| match server {
+            FAILING => Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused")),
+            BROKEN => {
+                let mut response = DNSPacket::response_to(request);
+                response.header.rcode = RCode::ServFail;
+                Ok(response)
+            }
+            _ => Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1))),
+        });
+        resolver.set_forwarders(vec![(FAILING, 53), (BROKEN, 53), (ROOT, 53)]);
+
+        let response = resolver.forward_any("example.test", QRType::A, QRClass::IN).unwrap();
+        assert_eq!(response.header.rcode, RCode::NoError);
+        assert_eq!(queries.lock().unwrap().len(), 3);
+        assert_eq!(response.warnings.len(), 2);
+        assert!(response.warnings[0].contains("failed: refused"));
+        assert!(response.warnings[1].contains("answered SERVFAIL"));
+    }
+
+    #[test]
+    fn timed_out_resolution_answers_with_the_last_referral_when_asked() {
+        let (mut resolver, _) = fake_resolver(|request, server| {
+            if server == ROOT {
+                Ok(referral(request, "test", "ns.test", Some(Ipv4Addr::new(192, 0, 2, 10))))
+            } else {
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "no reply"))
+            }
+        });
+        resolver.set_partial_results(true);
+
+        let partial = resolver.resolve("example.test", QRType::A, QRClass::IN).unwrap();
+        assert_eq!(partial.header.rcode, RCode::ServFail);
+        assert!(matches!(partial.authority.records.as_slice(), [DNSRecord::NS(_)]));
+        assert!(partial.warnings.iter().any(|warning| warning.contains("answering with the last referral")));
     }
-    // Being mindful of how unreliable input data from arbitrary senders can be, we
-    // need make sure that a question is actually present. If not, we return `FORMERR`
-    // to indicate that the sender made something wrong.
-    else {
-        packet.header.rcode = RCode::FormErr;
+
+    #[test]
+    fn each_upstream_query_gets_a_fresh_id() {
+        let ids = Arc::new(Mutex::new(Vec::new()));
+        let (resolver, _) = {
+            let ids = ids.clone();
+            fake_resolver(move |request, _| {
+                ids.lock().unwrap().push(request.header.id);
+                Ok(a_answer(request, Ipv4Addr::new(192, 0, 2, 1)))
+            })
+        };
+        let resolver = resolver.with_rng_seed(7);
+
+        for _ in 0..2 {
+            resolver.lookup("example.test", QRType::A, QRClass::IN, (ROOT, 53)).unwrap();
+        }
+        let ids = ids.lock().unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
     }
 
-    // The only thing remaining is to encode our response and send it off!
-    let mut res_buffer = BytePacketBuffer::new();
-    packet.write(&mut res_buffer)?;
+    #[cfg(feature = "dnssec")]
+    mod signed {
+        use super::*;
+        use std::time::{SystemTime, UNIX_EPOCH};
+        use ring::digest;
+        use ring::rand::SystemRandom;
+        use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+        use crate::message::dnssec::signed_data;
+        use crate::message::records::{DNSRRSIGRecord, DNSSOARecord, DNSUNKNOWNRecord};
+
+        const TEST: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10));
+        const INSECURE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 20));
+
+        // A zone's ECDSA P-256 key, signing its RRsets
+        struct ZoneKey {
+            zone: String,
+            pair: EcdsaKeyPair,
+            dnskey: DNSDNSKEYRecord,
+        }
+
+        impl ZoneKey {
+            fn new(zone: &str) -> Self {
+                let rng = SystemRandom::new();
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+                let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+                // DNSKEY leaves out the uncompressed point's leading 0x04
+                let dnskey = DNSDNSKEYRecord::new(zone.to_string(), QRClass::IN, 3600, 257, 3, 13, pair.public_key().as_ref()[1..].to_vec());
+                ZoneKey { zone: zone.to_string(), pair, dnskey }
+            }
+
+            // The SHA-256 DS record for the key, as held by the parent zone
+            fn ds(&self) -> DNSDSRecord {
+                let mut data = BytePacketBuffer::new();
+                data.write_qname(&self.zone).unwrap();
+                data.write_u16(257).unwrap();
+                data.write_u8(3).unwrap();
+                data.write_u8(13).unwrap();
+                data.write_bytes(&self.dnskey.public_key).unwrap();
+                let digest = digest::digest(&digest::SHA256, &data.buf[0..data.pos()]).as_ref().to_vec();
+                DNSDSRecord::new(self.zone.clone(), QRClass::IN, 3600, self.dnskey.key_tag(), 13, 2, digest)
+            }
+
+            // The RRset followed by its signature
+            fn signed(&self, rrset: Vec<DNSRecord>) -> Vec<DNSRecord> {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+                let first = rrset[0].preamble().clone();
+                let labels = first.name.split('.').filter(|label| !label.is_empty()).count() as u8;
+                let mut rrsig = DNSRRSIGRecord::new(first.name, QRClass::IN, first.ttl, first.rtype, 13, labels, first.ttl, now + 3600, now - 3600, self.dnskey.key_tag(), self.zone.clone(), Vec::new());
+                let data = signed_data(&rrset, &rrsig).unwrap().unwrap();
+                rrsig.signature = self.pair.sign(&SystemRandom::new(), &data).unwrap().as_ref().to_vec();
+                let mut records = rrset;
+                records.push(DNSRecord::RRSIG(rrsig));
+                records
+            }
+
+            fn signed_keys(&self) -> Vec<DNSRecord> {
+                self.signed(vec![DNSRecord::DNSKEY(self.dnskey.clone())])
+            }
+        }
+
+        // An NSEC record, with type codes below 256
+        fn nsec(owner: &str, next: &str, types: &[QRType]) -> DNSRecord {
+            let mut rdata = BytePacketBuffer::new();
+            rdata.write_qname(next).unwrap();
+            let mut bitmap = [0u8; 32];
+            for code in types.iter().map(QRType::to_u16).chain([46, 47]) {
+                bitmap[code as usize / 8] |= 0x80 >> (code % 8);
+            }
+            let len = bitmap.iter().rposition(|byte| *byte != 0).unwrap() + 1;
+            rdata.write_u8(0).unwrap();
+            rdata.write_u8(len as u8).unwrap();
+            rdata.write_bytes(&bitmap[0..len]).unwrap();
+            DNSRecord::UNKNOWN(DNSUNKNOWNRecord::new(owner.to_string(), QRType::UNKNOWN(47), QRClass::IN, 3600, rdata.buf[0..rdata.pos()].to_vec()))
+        }
+
+        // The root and "test", whose server also serves the signed child
+        // "sub.test" and delegates to "insecure.test", which is unsigned.
+        // Names in "test" sort as test, insecure.test, sub.test, www.test.
+        struct Hierarchy {
+            root: ZoneKey,
+            test: ZoneKey,
+            sub: ZoneKey,
+        }
+
+        impl Hierarchy {
+            fn new() -> Self {
+                Hierarchy { root: ZoneKey::new(""), test: ZoneKey::new("test"), sub: ZoneKey::new("sub.test") }
+            }
+
+            fn answer(&self, request: &DNSPacket, server: IpAddr) -> DNSPacket {
+                let question = &request.question.questions[0];
+                let qname = question.qname.to_ascii_lowercase();
+                let mut response = DNSPacket::response_to(request);
+                let a = |name: &str| vec![DNSRecord::A(DNSARecord::new(name.to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, 1)))];
+
+                if server == ROOT {
+                    if question.qtype == QRType::DNSKEY {
+                        response.answer.answers = self.root.signed_keys();
+                    } else {
+                        response = referral(request, "test", "ns.test", Some(Ipv4Addr::new(192, 0, 2, 10)));
+                        response.authority.records.extend(self.root.signed(vec![DNSRecord::DS(self.test.ds())]));
+                    }
+                    return response;
+                }
+                if server == INSECURE {
+                    response.answer.answers = a(&qname);
+                    return response;
+                }
+
+                match (qname.as_str(), question.qtype) {
+                    ("test", QRType::DNSKEY) => response.answer.answers = self.test.signed_keys(),
+                    ("sub.test", QRType::DNSKEY) => response.answer.answers = self.sub.signed_keys(),
+                    ("sub.test", QRType::DS) => response.answer.answers = self.test.signed(vec![DNSRecord::DS(self.sub.ds())]),
+                    ("www.test", QRType::A) => response.answer.answers = self.test.signed(a(&qname)),
+                    ("www.sub.test", QRType::A) => response.answer.answers = self.sub.signed(a(&qname)),
+                    ("www.insecure.test", _) => {
+                        response = referral(request, "insecure.test", "ns.insecure.test", Some(Ipv4Addr::new(192, 0, 2, 20)));
+                        response.authority.records.extend(self.test.signed(vec![nsec("insecure.test", "sub.test", &[QRType::NS])]));
+                    }
+                    _ => {
+                        response.header.rcode = RCode::NXDomain;
+                        let soa = DNSSOARecord::new("test".to_string(), QRClass::IN, 3600, "ns.test".to_string(), "admin.test".to_string(), 1, 3600, 600, 86400, 300);
+                        response.authority.records.extend(self.test.signed(vec![DNSRecord::SOA(soa)]));
+                        response.authority.records.extend(self.test.signed(vec![nsec("test", "insecure.test", &[QRType::NS, QRType::SOA, QRType::DNSKEY])]));
+                        response.authority.records.extend(self.test.signed(vec![nsec("insecure.test", "sub.test", &[QRType::NS])]));
+                    }
+                }
+                response
+            }
+        }
+
+        // A validating resolver over the hierarchy, with `tamper` applied to
+        // every response before it's returned
+        fn validating_resolver<T>(tamper: T) -> (DNSResolver, Queries)
+        where
+            T: Fn(&mut DNSPacket) + Send + Sync + 'static,
+        {
+            let hierarchy = Hierarchy::new();
+            let anchor = hierarchy.root.ds();
+            let (mut resolver, queries) = fake_resolver(move |request, server| {
+                let mut response = hierarchy.answer(request, server);
+                tamper(&mut response);
+                Ok(response)
+            });
+            resolver.set_dnssec_validation(true);
+            resolver.set_trust_anchors(vec![anchor]);
+            (resolver, queries)
+        }
+
+        fn is_bogus(result: Result<DNSPacket,std::io::Error>) -> bool {
+            result.is_err_and(|e| e.to_string().starts_with("DNSSEC validation failed"))
+        }
+
+        #[test]
+        fn signed_answer_is_authenticated_from_the_network_and_the_cache() {
+            let (resolver, queries) = validating_resolver(|_| {});
+            let question = DNSQuestion::new("www.test".to_string(), QRType::A, QRClass::IN);
+
+            let (response, cache_hit) = resolver.answer(&question, true).unwrap();
+            assert!(!cache_hit);
+            assert_eq!(response.header.ad, ADFlag::Authenticated);
+            let sent = queries.lock().unwrap().len();
 
-    let len = res_buffer.pos();
-    let data = res_buffer.get_byte_range(0, len)?;
+            let (response, cache_hit) = resolver.answer(&question, true).unwrap();
+            assert!(cache_hit);
+            assert_eq!(response.header.ad, ADFlag::Authenticated);
+            assert_eq!(queries.lock().unwrap().len(), sent);
+        }
+
+        #[test]
+        fn tampered_signature_is_bogus() {
+            let (resolver, _) = validating_resolver(|response| {
+                for record in response.answer.answers.iter_mut() {
+                    if let DNSRecord::RRSIG(rrsig) = record {
+                        if rrsig.type_covered == QRType::A {
+                            rrsig.signature[10] ^= 0x01;
+                        }
+                    }
+                }
+            });
+            assert!(is_bogus(resolver.resolve("www.test", QRType::A, QRClass::IN)));
+        }
+
+        #[test]
+        fn nonexistent_name_is_authenticated_only_with_its_nsec_proof() {
+            let (resolver, _) = validating_resolver(|_| {});
+            let response = resolver.resolve("missing.test", QRType::A, QRClass::IN).unwrap();
+            assert_eq!(response.header.rcode, RCode::NXDomain);
+            assert_eq!(response.header.ad, ADFlag::Authenticated);
+
+            // Without the NSEC covering *.test a wildcard could have answered
+            let (resolver, _) = validating_resolver(|response| {
+                if response.header.rcode == RCode::NXDomain {
+                    response.authority.records.retain(|record| match record {
+                        DNSRecord::RRSIG(rrsig) => rrsig.type_covered == QRType::SOA || rrsig.preamble.name != "test",
+                        _ => record.preamble().rtype == QRType::SOA || record.preamble().name != "test",
+                    });
+                }
+            });
+            assert!(is_bogus(resolver.resolve("missing.test", QRType::A, QRClass::IN)));
+        }
 
-    socket.send_to(data, src)?;
+        #[test]
+        fn delegation_without_ds_is_insecure_only_with_its_nsec_proof() {
+            let (resolver, _) = validating_resolver(|_| {});
+            let response = resolver.resolve("www.insecure.test", QRType::A, QRClass::IN).unwrap();
+            assert_eq!(response.all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+            assert_eq!(response.header.ad, ADFlag::NonAuthenticated);
 
-    Ok(())
-}
\ No newline at end of file
+            // Stripping the NSEC mustn't turn a signed delegation unsigned
+            let (resolver, _) = validating_resolver(|response| {
+                if response.is_referral() {
+                    response.authority.records.retain(|record| matches!(record, DNSRecord::NS(_)));
+                }
+            });
+            assert!(is_bogus(resolver.resolve("www.insecure.test", QRType::A, QRClass::IN)));
+        }
+
+        #[test]
+        fn answer_signed_by_a_child_zone_is_checked_with_the_childs_keys() {
+            let (resolver, queries) = validating_resolver(|_| {});
+            let response = resolver.resolve("www.sub.test", QRType::A, QRClass::IN).unwrap();
+            assert_eq!(response.header.ad, ADFlag::Authenticated);
+            let queries = queries.lock().unwrap();
+            assert!(queries.contains(&("sub.test".to_string(), QRType::DS, TEST)));
+            assert!(queries.contains(&("sub.test".to_string(), QRType::DNSKEY, TEST)));
+
+            // The parent not vouching for the child is no longer taken as
+            // the child being unsigned
+            let (resolver, _) = validating_resolver(|response| {
+                if response.question.questions[0].qtype == QRType::DS {
+                    response.answer.answers.clear();
+                }
+            });
+            assert!(is_bogus(resolver.resolve("www.sub.test", QRType::A, QRClass::IN)));
+        }
+    }
+}
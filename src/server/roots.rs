@@ -0,0 +1,55 @@
+use std::net::{IpAddr, Ipv4Addr};
+use crate::message::{records::DNSRecord, DNSPacket};
+
+/// The IANA root servers, used to start recursion until a priming query
+/// fetches the current set
+pub const ROOT_HINTS: [(&str, Ipv4Addr); 13] = [
+    ("a.root-servers.net", Ipv4Addr::new(198, 41, 0, 4)),
+    ("b.root-servers.net", Ipv4Addr::new(170, 247, 170, 2)),
+    ("c.root-servers.net", Ipv4Addr::new(192, 33, 4, 12)),
+    ("d.root-servers.net", Ipv4Addr::new(199, 7, 91, 13)),
+    ("e.root-servers.net", Ipv4Addr::new(192, 203, 230, 10)),
+    ("f.root-servers.net", Ipv4Addr::new(192, 5, 5, 241)),
+    ("g.root-servers.net", Ipv4Addr::new(192, 112, 36, 4)),
+    ("h.root-servers.net", Ipv4Addr::new(198, 97, 190, 53)),
+    ("i.root-servers.net", Ipv4Addr::new(192, 36, 148, 17)),
+    ("j.root-servers.net", Ipv4Addr::new(192, 58, 128, 30)),
+    ("k.root-servers.net", Ipv4Addr::new(193, 0, 14, 129)),
+    ("l.root-servers.net", Ipv4Addr::new(199, 7, 83, 42)),
+    ("m.root-servers.net", Ipv4Addr::new(202, 12, 27, 33)),
+];
+
+/// The root hints as a server list
+pub fn root_hints() -> Vec<(String, IpAddr)> {
+    ROOT_HINTS
+        .iter()
+        .map(|(name, addr)| (name.to_string(), IpAddr::V4(*addr)))
+        .collect()
+}
+
+/// Extract the root servers from a response to a `. NS` priming query: the
+/// root NS records in the answer section, paired with their A and AAAA glue
+/// from the additional section. Servers without glue are left out.
+pub fn parse_priming_response(response: &DNSPacket) -> Vec<(String, IpAddr)> {
+    let names = response.answer.answers
+        .iter()
+        .filter_map(|record| match record {
+            DNSRecord::NS(ns_record) if ns_record.preamble.name.is_empty() => Some(ns_record.rdata.to_lowercase()),
+            _ => None,
+        });
+
+    let mut servers = Vec::new();
+    for name in names {
+        for record in &response.additional.records {
+            let addr = match record {
+                DNSRecord::A(a_record) => IpAddr::V4(a_record.rdata),
+                DNSRecord::AAAA(aaaa_record) => IpAddr::V6(aaaa_record.address),
+                _ => continue,
+            };
+            if record.preamble().name.eq_ignore_ascii_case(&name) {
+                servers.push((name.clone(), addr));
+            }
+        }
+    }
+    servers
+}
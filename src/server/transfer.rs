@@ -0,0 +1,220 @@
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+use crate::rng::Rng;
+use crate::message::{byte_packet_buffer::BytePacketBuffer, header::{RCode, RDFlag}, records::{DNSRecord, DNSSOARecord}, DNSPacket, DNSQuestion, QRClass, QRType};
+
+/// A change to a zone received through a zone transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZoneChange {
+    /// One step of an incremental transfer, taking the zone from one serial
+    /// to the next
+    Diff {
+        from_serial: u32,
+        to_serial: u32,
+        deleted: Vec<DNSRecord>,
+        added: Vec<DNSRecord>,
+    },
+    /// The complete contents of the zone, sent when the server cannot or will
+    /// not send a diff
+    Full {
+        serial: u32,
+        records: Vec<DNSRecord>,
+    },
+}
+
+/// Request the changes to a zone since `serial` with an IXFR query (RFC 1995)
+/// over TCP. An empty list means the zone is already up to date; a server
+/// that falls back to a full transfer yields a single `ZoneChange::Full`.
+pub fn incremental_transfer(zone: &str, serial: u32, server: (IpAddr, u16), timeout: Duration) -> Result<Vec<ZoneChange>,std::io::Error> {
+    let mut packet = DNSPacket::new();
+    packet.header.id = Rng::from_entropy().next_u64() as u16;
+    packet.header.rd = RDFlag::NonDesired;
+    packet.question.add_question(DNSQuestion::new(zone.to_string(), QRType::IXFR, QRClass::IN));
+    // The client's current version of the zone goes in the authority section
+    packet.authority.add_record(DNSRecord::SOA(DNSSOARecord::new(zone.to_string(), QRClass::IN, 0, String::new(), String::new(), serial, 0, 0, 0, 0)));
+
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::from(server), timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut req_buffer = BytePacketBuffer::new();
+    packet.write(&mut req_buffer)?;
+    stream.write_all(&(req_buffer.pos as u16).to_be_bytes())?;
+    stream.write_all(&req_buffer.buf[0..req_buffer.pos])?;
+
+    // The transfer may span several messages, so keep reading until the
+    // records received so far form a complete transfer
+    let mut records = Vec::new();
+    loop {
+        let response = DNSPacket::from_reader(&mut stream)?;
+        if response.header.id != packet.header.id {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Transfer response has a mismatched ID"));
+        }
+        if response.header.rcode != RCode::NoError {
+            return Err(std::io::Error::other(format!("Transfer refused with {:?}", response.header.rcode)));
+        }
+        records.extend(response.answer.answers);
+
+        if let Some(changes) = parse_transfer(&records, serial)? {
+            return Ok(changes);
+        }
+    }
+}
+
+/// Interpret the answer records of a transfer, returning `None` while the
+/// closing SOA has yet to arrive.
+///
+/// An incremental transfer is the new SOA followed by pairs of sequences,
+/// each an old SOA and the records it deletes then a newer SOA and the
+/// records it adds, closed by the new SOA again. A full transfer is the new
+/// SOA, the rest of the zone, then the new SOA.
+pub fn parse_transfer(records: &[DNSRecord], serial: u32) -> Result<Option<Vec<ZoneChange>>,std::io::Error> {
+    let new_serial = match records.first() {
+        None => return Ok(None),
+        Some(DNSRecord::SOA(soa_record)) => soa_record.serial,
+        Some(_) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Transfer does not start with an SOA record")),
+    };
+
+    // A lone SOA at or before our serial means there is nothing to send
+    if records.len() == 1 {
+        return Ok(if serial_le(new_serial, serial) { Some(Vec::new()) } else { None });
+    }
+
+    let incremental = match &records[1] {
+        DNSRecord::SOA(soa_record) => soa_record.serial != new_serial,
+        _ => false,
+    };
+    if !incremental {
+        return Ok(match records.last() {
+            Some(DNSRecord::SOA(soa_record)) if soa_record.serial == new_serial => Some(vec![ZoneChange::Full {
+                serial: new_serial,
+                records: records[..records.len() - 1].to_vec(),
+            }]),
+            _ => None,
+        });
+    }
+
+    let mut changes = Vec::new();
+    let mut i = 1;
+    loop {
+        let from_serial = match records.get(i) {
+            None => return Ok(None),
+            Some(DNSRecord::SOA(soa_record)) if soa_record.serial == new_serial => return Ok(Some(changes)),
+            Some(DNSRecord::SOA(soa_record)) => soa_record.serial,
+            Some(_) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Expected an SOA record opening a deletion sequence")),
+        };
+        i += 1;
+        let deleted = take_until_soa(records, &mut i);
+
+        // The deletions run until the SOA opening the additions
+        let to_serial = match records.get(i) {
+            Some(DNSRecord::SOA(soa_record)) => soa_record.serial,
+            _ => return Ok(None),
+        };
+        i += 1;
+        let added = take_until_soa(records, &mut i);
+
+        changes.push(ZoneChange::Diff { from_serial, to_serial, deleted, added });
+    }
+}
+
+// Collect the records from `i` up to the next SOA record
+fn take_until_soa(records: &[DNSRecord], i: &mut usize) -> Vec<DNSRecord> {
+    let start = *i;
+    while *i < records.len() && !matches!(records[*i], DNSRecord::SOA(_)) {
+        *i += 1;
+    }
+    records[start..*i].to_vec()
+}
+
+// Whether serial `a` is at or before serial `b` in RFC 1982 sequence space
+fn serial_le(a: u32, b: u32) -> bool {
+    (b.wrapping_sub(a) as i32) >= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+    use crate::message::records::DNSARecord;
+
+    fn soa(serial: u32) -> DNSRecord {
+        DNSRecord::SOA(DNSSOARecord::new("example.com".to_string(), QRClass::IN, 3600, "ns.example.com".to_string(), "admin.example.com".to_string(), serial, 3600, 600, 86400, 300))
+    }
+
+    fn a(name: &str, last: u8) -> DNSRecord {
+        DNSRecord::A(DNSARecord::new(format!("{}.example.com", name), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, last)))
+    }
+
+    // Serial 1 to 3 in two steps: 1 to 2 replaces www's address, 2 to 3
+    // adds mail
+    fn diff() -> Vec<DNSRecord> {
+        vec![soa(3), soa(1), a("www", 1), soa(2), a("www", 2), soa(2), soa(3), a("mail", 3), soa(3)]
+    }
+
+    fn expected_diff() -> Vec<ZoneChange> {
+        vec![
+            ZoneChange::Diff { from_serial: 1, to_serial: 2, deleted: vec![a("www", 1)], added: vec![a("www", 2)] },
+            ZoneChange::Diff { from_serial: 2, to_serial: 3, deleted: Vec::new(), added: vec![a("mail", 3)] },
+        ]
+    }
+
+    #[test]
+    fn incremental_transfer_is_parsed_into_diffs() {
+        assert_eq!(parse_transfer(&diff(), 1).unwrap(), Some(expected_diff()));
+    }
+
+    #[test]
+    fn full_transfer_fallback_yields_the_whole_zone() {
+        let records = vec![soa(3), a("www", 2), a("mail", 3), soa(3)];
+        assert_eq!(parse_transfer(&records, 1).unwrap(), Some(vec![ZoneChange::Full {
+            serial: 3,
+            records: vec![soa(3), a("www", 2), a("mail", 3)],
+        }]));
+    }
+
+    #[test]
+    fn lone_soa_at_our_serial_means_up_to_date() {
+        assert_eq!(parse_transfer(&[soa(3)], 3).unwrap(), Some(Vec::new()));
+        // A newer serial alone is the start of a transfer still to come
+        assert_eq!(parse_transfer(&[soa(4)], 3).unwrap(), None);
+        assert!(parse_transfer(&[a("www", 1)], 3).is_err());
+    }
+
+    #[test]
+    fn diff_split_across_messages_completes_with_its_closing_soa() {
+        let records = diff();
+        for split in 1..records.len() {
+            assert_eq!(parse_transfer(&records[..split], 1).unwrap(), None, "complete after {} records", split);
+        }
+        assert_eq!(parse_transfer(&records, 1).unwrap(), Some(expected_diff()));
+    }
+
+    #[test]
+    fn incremental_transfer_reads_a_diff_from_the_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = DNSPacket::from_reader(&mut stream).unwrap();
+            assert_eq!(request.question.questions[0].qtype, QRType::IXFR);
+            assert!(matches!(&request.authority.records[..], [DNSRecord::SOA(soa_record)] if soa_record.serial == 1));
+
+            // Send the diff in two messages
+            let records = diff();
+            for part in [&records[..4], &records[4..]] {
+                let mut response = DNSPacket::response_to(&request);
+                response.answer.answers = part.to_vec();
+                let mut buffer = BytePacketBuffer::new();
+                response.write(&mut buffer).unwrap();
+                stream.write_all(&(buffer.pos as u16).to_be_bytes()).unwrap();
+                stream.write_all(&buffer.buf[0..buffer.pos]).unwrap();
+            }
+        });
+
+        let changes = incremental_transfer("example.com", 1, (IpAddr::V4(Ipv4Addr::LOCALHOST), port), Duration::from_secs(5)).unwrap();
+        server.join().unwrap();
+        assert_eq!(changes, expected_diff());
+    }
+}
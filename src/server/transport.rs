@@ -0,0 +1,546 @@
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use crate::message::{byte_packet_buffer::BytePacketBuffer, header::TCFlag, DNSPacket, ParseMode};
+
+/// A way of exchanging a query and its response with an upstream server.
+pub trait Transport {
+    /// Send a query to a server and wait up to `timeout` for its response
+    fn send_query(&self, packet: &mut DNSPacket, server: (IpAddr, u16), timeout: Duration) -> Result<DNSPacket,std::io::Error>;
+}
+
+/// Which protocol a query is sent over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    Udp, // UDP only, even if the response is truncated
+    Tcp, // TCP only
+    #[default]
+    UdpThenTcp, // UDP, retrying over TCP if the response is truncated
+}
+
+/// Plain DNS over UDP
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UdpTransport {
+    source: Option<IpAddr>, // Local address queries are sent from
+}
+
+impl UdpTransport {
+    // Constructor for a UdpTransport sending from an OS-chosen address
+    pub fn new() -> Self {
+        UdpTransport { source: None }
+    }
+
+    // Constructor for a UdpTransport sending from the given local address,
+    // pinning the interface queries leave through on multi-homed hosts
+    pub fn with_source(source: IpAddr) -> Self {
+        UdpTransport { source: Some(source) }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_query(&self, packet: &mut DNSPacket, server: (IpAddr, u16), timeout: Duration) -> Result<DNSPacket,std::io::Error> {
+        // Bind the configured source, or else an unspecified address of the
+        // same family as the server, on a fresh ephemeral port for each query.
+        // A fixed port would make concurrent lookups collide and let an
+        // off-path attacker aim forged replies at a known port.
+        let local: IpAddr = match (self.source, server.0) {
+            (Some(source), _) => source,
+            (None, IpAddr::V4(_)) => Ipv4Addr::UNSPECIFIED.into(),
+            (None, IpAddr::V6(_)) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0))?;
+
+        let mut req_buffer = BytePacketBuffer::new();
+        packet.write(&mut req_buffer)?;
+        socket.send_to(&req_buffer.buf[0..req_buffer.pos], server)?;
+
+        // Receive as much as the query's OPT record said we could, or the
+        // classic 512 bytes without EDNS. The buffer has a byte to spare, as
+        // a datagram that fills it has been cut short by the OS, which only
+        // the extra byte distinguishes from one of exactly the size allowed.
+        let size = packet.edns_udp_size().map_or(512, |size| size.max(512)) as usize;
+        let mut res_buffer = BytePacketBuffer::with_size(size + 1);
+        let mut warnings = Vec::new();
+        let len = recv_reply(&socket, SocketAddr::from(server), packet.header.id, &mut res_buffer, timeout, &mut warnings)?;
+        let mut response = if len > size {
+            warnings.push(format!("the reply exceeded the {} bytes allowed and was treated as truncated", size));
+            truncated_reply(&res_buffer.buf[0..len])?
+        } else {
+            DNSPacket::from_slice(&res_buffer.buf[0..len], ParseMode::Lenient)?
+        };
+        response.warnings.extend(warnings);
+        Ok(response)
+    }
+}
+
+// A reply that was cut short can't be trusted past its question, so keep
+// just the header and question and set TC, which makes the resolver retry
+// over TCP as for any truncated response.
+fn truncated_reply(data: &[u8]) -> Result<DNSPacket,std::io::Error> {
+    let mut buffer = BytePacketBuffer::with_size(data.len());
+    buffer.buf.copy_from_slice(data);
+    let (header, question, _) = DNSPacket::record_iter(&mut buffer)?;
+
+    let mut packet = DNSPacket::new();
+    packet.header = header;
+    packet.header.tc = TCFlag::Truncated;
+    packet.header.ancount = 0;
+    packet.header.nscount = 0;
+    packet.header.arcount = 0;
+    packet.question = question;
+    Ok(packet)
+}
+
+// Wait for a datagram from the server we queried carrying our query's ID.
+// Anything arriving from another address is an off-path spoofing attempt or
+// stray traffic, and a reply with another ID is stale, answering an earlier
+// query; both are discarded, each noted in `discarded` for the caller. The
+// timeout covers the whole wait, not each datagram.
+fn recv_reply(socket: &UdpSocket, server: SocketAddr, id: u16, buffer: &mut BytePacketBuffer, timeout: Duration, discarded: &mut Vec<String>) -> Result<usize,std::io::Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out waiting for a reply"));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (len, src) = socket.recv_from(&mut buffer.buf)?;
+        if src != server {
+            discarded.push(format!("discarded a reply from unexpected address {}", src));
+        } else if len >= 2 && u16::from_be_bytes([buffer.buf[0], buffer.buf[1]]) != id {
+            discarded.push(format!("discarded a stale reply with ID {}", u16::from_be_bytes([buffer.buf[0], buffer.buf[1]])));
+        } else {
+            return Ok(len);
+        }
+    }
+}
+
+/// DNS over TCP, with each message prefixed by its two-byte length
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport {
+    source: Option<IpAddr>, // Local address connections are made from
+}
+
+impl TcpTransport {
+    // Constructor for a TcpTransport connecting from an OS-chosen address
+    pub fn new() -> Self {
+        TcpTransport { source: None }
+    }
+
+    // Constructor for a TcpTransport connecting from the given local address
+    pub fn with_source(source: IpAddr) -> Self {
+        TcpTransport { source: Some(source) }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_query(&self, packet: &mut DNSPacket, server: (IpAddr, u16), timeout: Duration) -> Result<DNSPacket,std::io::Error> {
+        let mut stream = connect(server, self.source, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let mut req_buffer = BytePacketBuffer::with_size(u16::MAX as usize);
+        packet.write(&mut req_buffer)?;
+        stream.write_all(&(req_buffer.pos as u16).to_be_bytes())?;
+        stream.write_all(&req_buffer.buf[0..req_buffer.pos])?;
+
+        DNSPacket::from_reader(&mut stream)
+    }
+}
+
+// Open a TCP connection to `server`, from `source` on an ephemeral port if
+// given, or else from an OS-chosen address
+fn connect(server: (IpAddr, u16), source: Option<IpAddr>, timeout: Duration) -> Result<TcpStream,std::io::Error> {
+    let server = SocketAddr::from(server);
+    let source = match source {
+        Some(source) => source,
+        None => return TcpStream::connect_timeout(&server, timeout),
+    };
+    let socket = socket2::Socket::new(socket2::Domain::for_address(server), socket2::Type::STREAM, Some(socket2::Protocol::TCP))?;
+    socket.bind(&SocketAddr::new(source, 0).into())?;
+    socket.connect_timeout(&server.into(), timeout)?;
+    Ok(socket.into())
+}
+
+/// DNS over TLS (RFC 7858): length-prefixed messages, as on TCP, inside a
+/// TLS session whose certificate must be valid for the expected server name
+#[cfg(feature = "dot")]
+pub struct TlsTransport {
+    server_name: rustls::pki_types::ServerName<'static>,
+    config: std::sync::Arc<rustls::ClientConfig>,
+}
+
+#[cfg(feature = "dot")]
+impl TlsTransport {
+    // Constructor for a TlsTransport trusting the bundled web PKI roots
+    pub fn new(hostname: &str) -> Result<Self,std::io::Error> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(std::io::Error::other)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        TlsTransport::with_config(hostname, std::sync::Arc::new(config))
+    }
+
+    // Constructor for a TlsTransport using a caller-supplied TLS configuration,
+    // e.g. to trust a private CA
+    pub fn with_config(hostname: &str, config: std::sync::Arc<rustls::ClientConfig>) -> Result<Self,std::io::Error> {
+        let server_name = rustls::pki_types::ServerName::try_from(hostname.to_string())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        Ok(TlsTransport { server_name, config })
+    }
+}
+
+#[cfg(feature = "dot")]
+impl Transport for TlsTransport {
+    fn send_query(&self, packet: &mut DNSPacket, server: (IpAddr, u16), timeout: Duration) -> Result<DNSPacket,std::io::Error> {
+        let stream = TcpStream::connect_timeout(&SocketAddr::from(server), timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let connection = rustls::ClientConnection::new(self.config.clone(), self.server_name.clone())
+            .map_err(std::io::Error::other)?;
+        let mut tls = rustls::StreamOwned::new(connection, stream);
+
+        let mut req_buffer = BytePacketBuffer::with_size(u16::MAX as usize);
+        packet.write(&mut req_buffer)?;
+        let mut message = (req_buffer.pos as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(&req_buffer.buf[0..req_buffer.pos]);
+        tls.write_all(&message)?;
+
+        DNSPacket::from_reader(&mut tls)
+    }
+}
+
+/// How a DNS-over-HTTPS query is carried in the HTTP request
+#[cfg(feature = "doh")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DohMethod {
+    Post, // Message as the request body
+    Get,  // Message base64url-encoded in the `dns` query parameter
+}
+
+/// DNS over HTTPS (RFC 8484) against a fixed endpoint URL. The server
+/// address passed to `send_query` is ignored, as the URL identifies the
+/// upstream.
+#[cfg(feature = "doh")]
+pub struct DohTransport {
+    url: String,
+    method: DohMethod,
+}
+
+#[cfg(feature = "doh")]
+impl DohTransport {
+    // Constructor for a DohTransport POSTing to the given endpoint
+    pub fn new(url: &str) -> Self {
+        DohTransport { url: url.to_string(), method: DohMethod::Post }
+    }
+
+    /// Use GET requests with the `dns` parameter instead of POST
+    pub fn set_method(&mut self, method: DohMethod) {
+        self.method = method;
+    }
+}
+
+#[cfg(feature = "doh")]
+impl Transport for DohTransport {
+    fn send_query(&self, packet: &mut DNSPacket, _server: (IpAddr, u16), timeout: Duration) -> Result<DNSPacket,std::io::Error> {
+        // RFC 8484 recommends an ID of zero so responses are cache friendly.
+        // The packet keeps its own ID, which is put back on the response
+        // below so callers can match the two as for any other transport.
+        let id = packet.header.id;
+        packet.header.id = 0;
+        let mut req_buffer = BytePacketBuffer::with_size(u16::MAX as usize);
+        let written = packet.write(&mut req_buffer);
+        packet.header.id = id;
+        written?;
+        let message = &req_buffer.buf[0..req_buffer.pos];
+
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .build()
+            .into();
+        let response = match self.method {
+            DohMethod::Post => agent.post(&self.url)
+                .header("content-type", "application/dns-message")
+                .header("accept", "application/dns-message")
+                .send(message),
+            DohMethod::Get => agent.get(&self.url)
+                .query("dns", base64url(message))
+                .header("accept", "application/dns-message")
+                .call(),
+        };
+        let mut response = response.map_err(std::io::Error::other)?;
+
+        let body = response.body_mut()
+            .with_config()
+            .limit(u16::MAX as u64)
+            .read_to_vec()
+            .map_err(std::io::Error::other)?;
+
+        let mut response = DNSPacket::from_slice(&body, ParseMode::Lenient)?;
+        if response.header.id == 0 {
+            response.header.id = id;
+        }
+        Ok(response)
+    }
+}
+
+// Unpadded base64url, as required for the `dns` parameter of a GET request
+#[cfg(feature = "doh")]
+fn base64url(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |acc, (i, b)| acc | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use crate::message::records::{DNSARecord, DNSRecord};
+    use crate::message::{DNSQuestion, QRClass, QRType};
+
+    fn query(name: &str) -> DNSPacket {
+        let mut packet = DNSPacket::new();
+        packet.header.id = 4242;
+        packet.question.add_question(DNSQuestion::new(name.to_string(), QRType::A, QRClass::IN));
+        packet
+    }
+
+    // The wire form of a reply to `request` answering with `address`
+    fn reply(request: &[u8], address: Ipv4Addr) -> Vec<u8> {
+        reply_with(request, &[address])
+    }
+
+    fn reply_with(request: &[u8], addresses: &[Ipv4Addr]) -> Vec<u8> {
+        let request = DNSPacket::from_slice(request, ParseMode::Strict).unwrap();
+        let mut response = DNSPacket::response_to(&request);
+        let name = request.question.questions[0].qname.clone();
+        for address in addresses {
+            response.answer.add_answer(DNSRecord::A(DNSARecord::new(name.clone(), QRClass::IN, 60, *address)));
+        }
+        let mut buffer = BytePacketBuffer::with_size(u16::MAX as usize);
+        response.write(&mut buffer).unwrap();
+        buffer.buf[0..buffer.pos].to_vec()
+    }
+
+    #[test]
+    fn reply_from_unexpected_address_is_discarded() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            spoofer.send_to(&reply(&buf[0..len], Ipv4Addr::new(6, 6, 6, 6)), client).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            server.send_to(&reply(&buf[0..len], Ipv4Addr::new(192, 0, 2, 1)), client).unwrap();
+        });
+
+        let response = UdpTransport::new()
+            .send_query(&mut query("example.com"), (server_addr.ip(), server_addr.port()), Duration::from_secs(2))
+            .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(response.all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+        assert!(response.warnings.iter().any(|warning| warning.contains("unexpected address")));
+    }
+
+    #[test]
+    fn reply_filling_the_buffer_is_treated_as_truncated() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            // 40 A records of 16 bytes each take the reply well past 512
+            let addresses: Vec<Ipv4Addr> = (0..40).map(|i| Ipv4Addr::new(192, 0, 2, i)).collect();
+            let data = reply_with(&buf[0..len], &addresses);
+            assert!(data.len() > 512);
+            server.send_to(&data, client).unwrap();
+        });
+
+        let response = UdpTransport::new()
+            .send_query(&mut query("example.com"), (server_addr.ip(), server_addr.port()), Duration::from_secs(2))
+            .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(response.header.tc, TCFlag::Truncated);
+        assert!(response.answer.answers.is_empty());
+        assert_eq!(response.question.questions[0].qname, "example.com");
+        assert!(response.warnings.iter().any(|warning| warning.contains("512 bytes allowed")));
+    }
+
+    #[test]
+    fn stale_reply_is_ignored() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            let mut stale = reply(&buf[0..len], Ipv4Addr::new(6, 6, 6, 6));
+            stale[0..2].copy_from_slice(&4241u16.to_be_bytes());
+            server.send_to(&stale, client).unwrap();
+            server.send_to(&reply(&buf[0..len], Ipv4Addr::new(192, 0, 2, 1)), client).unwrap();
+        });
+
+        let response = UdpTransport::new()
+            .send_query(&mut query("example.com"), (server_addr.ip(), server_addr.port()), Duration::from_secs(2))
+            .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(response.header.id, 4242);
+        assert_eq!(response.all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+        assert!(response.warnings.iter().any(|warning| warning.contains("stale reply with ID 4241")));
+    }
+
+    #[test]
+    fn tcp_carries_queries_larger_than_512_bytes() {
+        use std::io::Read;
+        use std::net::TcpListener;
+        use crate::message::records::{DNSOPTRecord, EDNSOption};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut request = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut request).unwrap();
+            let response = reply(&request, Ipv4Addr::new(192, 0, 2, 1));
+            stream.write_all(&(response.len() as u16).to_be_bytes()).unwrap();
+            stream.write_all(&response).unwrap();
+            request.len()
+        });
+
+        // A padding option (RFC 7830) takes the query well past 512 bytes
+        let mut packet = query("example.com");
+        packet.additional.add_record(DNSRecord::OPT(DNSOPTRecord::new(1232, vec![EDNSOption { code: 12, data: vec![0; 1000] }])));
+        let response = TcpTransport::new()
+            .send_query(&mut packet, (server_addr.ip(), server_addr.port()), Duration::from_secs(2))
+            .unwrap();
+
+        assert!(handle.join().unwrap() > 1000);
+        assert_eq!(response.all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    }
+
+    #[cfg(feature = "dot")]
+    #[test]
+    fn tls_exchanges_length_prefixed_messages_with_a_trusted_server() {
+        use std::net::TcpListener;
+        use std::sync::Arc;
+        use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+        // A local server with a self-signed certificate for dns.test
+        let certified = rcgen::generate_simple_self_signed(vec!["dns.test".to_string()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let server_config = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        let server_config = Arc::new(server_config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            // The first connection is refused by the client over its name
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let connection = rustls::ServerConnection::new(server_config.clone()).unwrap();
+                let mut tls = rustls::StreamOwned::new(connection, stream);
+                let request = match DNSPacket::from_reader(&mut tls) {
+                    Ok(request) => request,
+                    Err(_) => continue,
+                };
+                let mut buffer = BytePacketBuffer::new();
+                request.clone().write(&mut buffer).unwrap();
+                let response = reply(&buffer.buf[0..buffer.pos], Ipv4Addr::new(192, 0, 2, 1));
+                tls.write_all(&(response.len() as u16).to_be_bytes()).unwrap();
+                tls.write_all(&response).unwrap();
+                tls.flush().unwrap();
+            }
+        });
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let client_config = Arc::new(client_config);
+        let server = (server_addr.ip(), server_addr.port());
+
+        let wrong_name = TlsTransport::with_config("other.test", client_config.clone()).unwrap();
+        assert!(wrong_name.send_query(&mut query("example.com"), server, Duration::from_secs(2)).is_err());
+
+        let response = TlsTransport::with_config("dns.test", client_config).unwrap()
+            .send_query(&mut query("example.com"), server, Duration::from_secs(2))
+            .unwrap();
+        handle.join().unwrap();
+        assert_eq!(response.header.id, 4242);
+        assert_eq!(response.all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    }
+
+    #[cfg(feature = "doh")]
+    #[test]
+    fn doh_response_carries_the_query_id() {
+        use std::io::{BufRead, BufReader, Read};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            assert_eq!(&body[0..2], &[0, 0]);
+
+            let answer = reply(&body, Ipv4Addr::new(192, 0, 2, 1));
+            let mut stream = reader.into_inner();
+            write!(stream, "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\nconnection: close\r\n\r\n", answer.len()).unwrap();
+            stream.write_all(&answer).unwrap();
+        });
+
+        let mut packet = query("example.com");
+        let response = DohTransport::new(&url)
+            .send_query(&mut packet, (Ipv4Addr::UNSPECIFIED.into(), 443), Duration::from_secs(2))
+            .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(packet.header.id, 4242);
+        assert_eq!(response.header.id, 4242);
+        assert_eq!(response.all_a(), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    }
+}
@@ -0,0 +1,561 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use ring::digest;
+use crate::message::byte_packet_buffer::BytePacketBuffer;
+use crate::message::dnssec::{ds_matches_key, rrsig_is_current, verify_rrsig};
+use crate::message::header::RCode;
+use crate::message::records::{read_type_bit_maps, DNSDNSKEYRecord, DNSDSRecord, DNSRRSIGRecord, DNSRecord};
+use crate::message::{DNSPacket, QRClass, QRType};
+
+// The IANA root KSKs: KSK-2017 (tag 20326) and KSK-2024 (tag 38696)
+const ROOT_ANCHORS: [(u16, &str); 2] = [
+    (20326, "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBB683457104237C7F8EC8D"),
+    (38696, "683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16"),
+];
+
+// The denial-of-existence types, which are read from their raw RDATA
+const NSEC: QRType = QRType::UNKNOWN(47);
+const NSEC3: QRType = QRType::UNKNOWN(50);
+
+// NSEC3 flag marking a span that may hold unsigned delegations
+const NSEC3_OPT_OUT: u8 = 0x01;
+
+// NSEC3 records taking more iterations than this to hash aren't used, so
+// proofs relying on them fail (RFC 9276 section 3.2)
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+/// The IANA trust anchors for the root zone, as SHA-256 DS records
+pub fn root_trust_anchors() -> Vec<DNSDSRecord> {
+    ROOT_ANCHORS
+        .iter()
+        .map(|(key_tag, digest)| {
+            let digest = (0..digest.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&digest[i..i + 2], 16).unwrap_or_default())
+                .collect();
+            DNSDSRecord::new(String::new(), QRClass::IN, 0, *key_tag, 8, 2, digest)
+        })
+        .collect()
+}
+
+/// How far a recursion has validated: the zone the current server was
+/// reached for and its trusted keys, or `None` once the chain of trust has
+/// been left through a delegation proven to be unsigned.
+#[derive(Debug, Clone)]
+pub struct ChainState {
+    pub zone: String,
+    pub keys: Option<Vec<DNSDNSKEYRecord>>,
+}
+
+/// The keys of `zone` that can be trusted, from the response to a DNSKEY
+/// query: the whole DNSKEY RRset, provided one of its keys is matched by a
+/// DS in `ds` and signed it. `None` if none of the DS records uses an
+/// algorithm we support, making the zone insecure rather than bogus.
+pub fn trusted_keys(response: &DNSPacket, zone: &str, ds: &[DNSDSRecord]) -> Result<Option<Vec<DNSDNSKEYRecord>>,std::io::Error> {
+    let keys: Vec<DNSDNSKEYRecord> = response.answer.answers
+        .iter()
+        .filter_map(|record| match record {
+            DNSRecord::DNSKEY(key) if same_name(&key.preamble.name, zone) => Some(key.clone()),
+            _ => None,
+        })
+        .collect();
+    let rrset: Vec<DNSRecord> = keys.iter().cloned().map(DNSRecord::DNSKEY).collect();
+
+    let mut supported = false;
+    for key in &keys {
+        for entry in ds {
+            match ds_matches_key(entry, key) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => continue,
+            }
+            supported = true;
+            if signatures(&response.answer.answers, zone, QRType::DNSKEY, zone)
+                .any(|rrsig| verify_rrsig(&rrset, rrsig, key).unwrap_or(false))
+            {
+                return Ok(Some(keys));
+            }
+        }
+    }
+
+    if !supported && ds.iter().all(|entry| !is_supported(entry.algorithm, entry.digest_type)) {
+        return Ok(None);
+    }
+    Err(bogus(format!("the DNSKEY RRset of \"{}\" is not signed by a key its DS records match", zone)))
+}
+
+/// Check the signature of every RRset in `records` that belongs to `zone`,
+/// using its trusted `keys`. An unsigned or wrongly signed RRset is an
+/// error. Returns whether there was anything to check and every RRset was
+/// in the zone, i.e. whether the records can be marked authenticated.
+pub fn verify_records(records: &[DNSRecord], zone: &str, keys: &[DNSDNSKEYRecord]) -> Result<bool,std::io::Error> {
+    let mut rrsets: Vec<(String, QRType)> = Vec::new();
+    for record in records {
+        let preamble = record.preamble();
+        if preamble.rtype == QRType::RRSIG || preamble.rtype.is_pseudo() {
+            continue;
+        }
+        let key = (preamble.name.to_ascii_lowercase(), preamble.rtype);
+        if !rrsets.contains(&key) {
+            rrsets.push(key);
+        }
+    }
+
+    let mut secure = !rrsets.is_empty();
+    for (name, rtype) in rrsets {
+        if !in_zone(&name, zone) {
+            secure = false;
+            continue;
+        }
+        let rrset: Vec<DNSRecord> = records
+            .iter()
+            .filter(|record| record.preamble().rtype == rtype && same_name(&record.preamble().name, &name))
+            .cloned()
+            .collect();
+        let verified = signatures(records, &name, rtype, zone)
+            .any(|rrsig| keys.iter().any(|key| verify_rrsig(&rrset, rrsig, key).unwrap_or(false)));
+        if !verified {
+            return Err(bogus(format!("no valid signature for {} {}", name, rtype)));
+        }
+    }
+
+    Ok(secure)
+}
+
+/// Check a final response from a server of `zone`, whose trusted keys are
+/// `keys`. The answer's RRsets must be validly signed, and if they don't
+/// answer the question, for `qname` or the name its CNAME chain leads to,
+/// signed NSEC or NSEC3 records must prove that the name or type doesn't
+/// exist. Returns whether the response can be marked authenticated.
+pub fn verify_response(response: &DNSPacket, qname: &str, qtype: QRType, zone: &str, keys: &[DNSDNSKEYRecord]) -> Result<bool,std::io::Error> {
+    let answers = &response.answer.answers;
+    let secure = verify_records(answers, zone, keys)?;
+
+    let mut name = qname.to_string();
+    let mut followed = 0;
+    while qtype != QRType::CNAME && followed < answers.len() {
+        match answers.iter().find_map(|record| match record {
+            DNSRecord::CNAME(cname) if same_name(&cname.preamble.name, &name) => Some(cname.rdata.clone()),
+            _ => None,
+        }) {
+            Some(target) => name = target,
+            None => break,
+        }
+        followed += 1;
+    }
+    let answered = answers.iter().any(|record| {
+        let preamble = record.preamble();
+        same_name(&preamble.name, &name) && (preamble.rtype == qtype || qtype == QRType::ANY)
+    });
+    // A chain leaving the zone ends somewhere we hold no keys for
+    if answered || !in_zone(&name, zone) {
+        return Ok(secure);
+    }
+
+    let denial = denial_records(&response.authority.records, zone);
+    let proven = match response.header.rcode {
+        RCode::NoError => proves_type_absent(&denial, &name, qtype, zone),
+        RCode::NXDomain => proves_name_absent(&denial, &name, zone),
+        _ => return Ok(false),
+    };
+    if !verify_records(&denial, zone, keys)? || !proven {
+        return Err(bogus(format!("no proof that {} {} doesn't exist", name, qtype)));
+    }
+    Ok(answers.is_empty() || secure)
+}
+
+/// The DS records vouching for `child`, a zone below `zone`, from a
+/// referral to it or the answer to a DS query, once their signature by the
+/// parent's `keys` is checked. `None` for an unsigned delegation, which the
+/// parent has to prove with signed NSEC or NSEC3 records; without DS
+/// records or that proof the delegation is bogus.
+pub fn referral_ds(response: &DNSPacket, child: &str, zone: &str, keys: &[DNSDNSKEYRecord]) -> Result<Option<Vec<DNSDSRecord>>,std::io::Error> {
+    let records: Vec<DNSRecord> = response.answer.answers
+        .iter()
+        .chain(&response.authority.records)
+        .filter(|record| same_name(&record.preamble().name, child))
+        .filter(|record| match record {
+            DNSRecord::DS(_) => true,
+            DNSRecord::RRSIG(rrsig) => rrsig.type_covered == QRType::DS,
+            _ => false,
+        })
+        .cloned()
+        .collect();
+    let ds: Vec<DNSDSRecord> = records
+        .iter()
+        .filter_map(|record| match record {
+            DNSRecord::DS(ds) => Some(ds.clone()),
+            _ => None,
+        })
+        .collect();
+    if ds.is_empty() {
+        let denial = denial_records(&response.authority.records, zone);
+        if verify_records(&denial, zone, keys)? && proves_unsigned_delegation(&denial, child, zone) {
+            return Ok(None);
+        }
+        return Err(bogus(format!("the delegation to \"{}\" has neither DS records nor proof there are none", child)));
+    }
+
+    verify_records(&records, zone, keys)?;
+    Ok(Some(ds))
+}
+
+/// The zone below `zone` that signed a response about `qname`, if a server
+/// authoritative for both answered for the child directly. The child's keys
+/// have to be found through its DS records before the response is checked.
+pub fn child_signer(response: &DNSPacket, qname: &str, zone: &str) -> Option<String> {
+    response.answer.answers
+        .iter()
+        .chain(&response.authority.records)
+        .find_map(|record| match record {
+            DNSRecord::RRSIG(rrsig) if !same_name(&rrsig.signer_name, zone) && in_zone(&rrsig.signer_name, zone) && in_zone(qname, &rrsig.signer_name) => {
+                Some(rrsig.signer_name.trim_end_matches('.').to_string())
+            }
+            _ => None,
+        })
+}
+
+// An NSEC record: the next name in the zone and the types at its owner
+struct Nsec {
+    owner: String,
+    next: String,
+    types: Vec<QRType>,
+}
+
+// An NSEC3 record, with its owner's first label decoded to the hash
+struct Nsec3 {
+    owner_hash: Vec<u8>,
+    flags: u8,
+    iterations: u16,
+    salt: Vec<u8>,
+    next_hash: Vec<u8>,
+    types: Vec<QRType>,
+}
+
+impl Nsec3 {
+    fn hash(&self, name: &str) -> Option<Vec<u8>> {
+        nsec3_hash(name, &self.salt, self.iterations).ok()
+    }
+    fn matches(&self, name: &str) -> bool {
+        self.hash(name).is_some_and(|hash| hash == self.owner_hash)
+    }
+    fn covers(&self, name: &str) -> bool {
+        self.hash(name).is_some_and(|hash| between(&self.owner_hash, &hash, &self.next_hash))
+    }
+}
+
+// The SOA, NSEC and NSEC3 records of `zone` among `records`, with the
+// RRSIGs covering them: what a denial of existence is made of
+fn denial_records(records: &[DNSRecord], zone: &str) -> Vec<DNSRecord> {
+    let is_denial = |rtype: QRType| rtype == QRType::SOA || rtype == NSEC || rtype == NSEC3;
+    records
+        .iter()
+        .filter(|record| in_zone(&record.preamble().name, zone))
+        .filter(|record| match record {
+            DNSRecord::RRSIG(rrsig) => is_denial(rrsig.type_covered),
+            _ => is_denial(record.preamble().rtype),
+        })
+        .cloned()
+        .collect()
+}
+
+// Whether `name` exists without any `qtype` records (or a CNAME)
+fn proves_type_absent(denial: &[DNSRecord], name: &str, qtype: QRType, zone: &str) -> bool {
+    let lacks_type = |types: &[QRType]| !types.contains(&qtype) && !types.contains(&QRType::CNAME);
+    nsecs(denial).iter().any(|nsec| same_name(&nsec.owner, name) && lacks_type(&nsec.types))
+        || nsec3s(denial, zone).iter().any(|nsec3| nsec3.matches(name) && lacks_type(&nsec3.types))
+}
+
+// Whether neither `name` nor a wildcard that could have produced it exists
+fn proves_name_absent(denial: &[DNSRecord], name: &str, zone: &str) -> bool {
+    let nsecs = nsecs(denial);
+    if let Some(covering) = nsecs.iter().find(|nsec| nsec_covers(nsec, name)) {
+        let encloser = [common_ancestor(name, &covering.owner), common_ancestor(name, &covering.next)]
+            .into_iter()
+            .max_by_key(|ancestor| ancestor.len())
+            .unwrap_or_default();
+        let wildcard = wildcard_of(&encloser);
+        return nsecs.iter().any(|nsec| nsec_covers(nsec, &wildcard));
+    }
+
+    let nsec3s = nsec3s(denial, zone);
+    match closest_encloser(&nsec3s, name, zone) {
+        Some((encloser, _)) => nsec3s.iter().any(|nsec3| nsec3.covers(&wildcard_of(&encloser))),
+        None => false,
+    }
+}
+
+// Whether the delegation to `child` has no DS records: the parent's NSEC or
+// NSEC3 at the delegation lists NS but not DS or SOA, or `child` falls in
+// an opt-out span of NSEC3 records
+fn proves_unsigned_delegation(denial: &[DNSRecord], child: &str, zone: &str) -> bool {
+    let is_delegation = |types: &[QRType]| types.contains(&QRType::NS) && !types.contains(&QRType::DS) && !types.contains(&QRType::SOA);
+    if nsecs(denial).iter().any(|nsec| same_name(&nsec.owner, child) && is_delegation(&nsec.types)) {
+        return true;
+    }
+
+    let nsec3s = nsec3s(denial, zone);
+    nsec3s.iter().any(|nsec3| nsec3.matches(child) && is_delegation(&nsec3.types))
+        || closest_encloser(&nsec3s, child, zone).is_some_and(|(_, next_closer)| next_closer.flags & NSEC3_OPT_OUT != 0)
+}
+
+// The closest encloser of `name` proven by NSEC3 records (RFC 5155 section
+// 8.3): its nearest ancestor that exists, along with the NSEC3 covering
+// the next closer name, the ancestor one label longer
+fn closest_encloser<'a>(nsec3s: &'a [Nsec3], name: &str, zone: &str) -> Option<(String, &'a Nsec3)> {
+    let labels = labels(name);
+    for i in 1..=labels.len() {
+        let encloser = labels[i..].join(".");
+        if !in_zone(&encloser, zone) {
+            break;
+        }
+        if nsec3s.iter().any(|nsec3| nsec3.matches(&encloser)) {
+            let next_closer = labels[i - 1..].join(".");
+            return nsec3s.iter().find(|nsec3| nsec3.covers(&next_closer)).map(|nsec3| (encloser, nsec3));
+        }
+    }
+    None
+}
+
+fn nsecs(denial: &[DNSRecord]) -> Vec<Nsec> {
+    denial
+        .iter()
+        .filter_map(|record| match record {
+            DNSRecord::UNKNOWN(unknown) if unknown.preamble.rtype == NSEC => {
+                let mut buffer = rdata_buffer(&unknown.rdata).ok()?;
+                let mut next = String::new();
+                buffer.read_qname(&mut next).ok()?;
+                let len = buffer.remaining();
+                let types = read_type_bit_maps(&mut buffer, len).ok()?;
+                Some(Nsec { owner: unknown.preamble.name.clone(), next, types })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// The NSEC3 records of `zone` using SHA-1, the only hash algorithm defined,
+// and a number of iterations we're willing to hash
+fn nsec3s(denial: &[DNSRecord], zone: &str) -> Vec<Nsec3> {
+    denial
+        .iter()
+        .filter_map(|record| match record {
+            DNSRecord::UNKNOWN(unknown) if unknown.preamble.rtype == NSEC3 => {
+                let (label, parent) = unknown.preamble.name.split_once('.').unwrap_or((&unknown.preamble.name, ""));
+                if !same_name(parent, zone) {
+                    return None;
+                }
+                let mut buffer = rdata_buffer(&unknown.rdata).ok()?;
+                let algorithm = buffer.read_u8().ok()?;
+                let flags = buffer.read_u8().ok()?;
+                let iterations = buffer.read_u16().ok()?;
+                let salt_len = buffer.read_u8().ok()? as usize;
+                let salt = buffer.get_byte_range(buffer.pos(), salt_len).ok()?.to_vec();
+                buffer.step(salt_len).ok()?;
+                let hash_len = buffer.read_u8().ok()? as usize;
+                let next_hash = buffer.get_byte_range(buffer.pos(), hash_len).ok()?.to_vec();
+                buffer.step(hash_len).ok()?;
+                let len = buffer.remaining();
+                let types = read_type_bit_maps(&mut buffer, len).ok()?;
+                if algorithm != 1 || iterations > MAX_NSEC3_ITERATIONS {
+                    return None;
+                }
+                Some(Nsec3 { owner_hash: base32hex_decode(label)?, flags, iterations, salt, next_hash, types })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// A buffer holding just a record's RDATA, so reads can't run past it
+fn rdata_buffer(rdata: &[u8]) -> Result<BytePacketBuffer,std::io::Error> {
+    let mut buffer = BytePacketBuffer::with_size(rdata.len());
+    buffer.write_bytes(rdata)?;
+    buffer.seek(0)?;
+    buffer.set_message_len(rdata.len());
+    Ok(buffer)
+}
+
+// The NSEC3 hash of a name (RFC 5155 section 5): SHA-1 over its canonical
+// wire form and the salt, then again over each digest and the salt
+fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Result<Vec<u8>,std::io::Error> {
+    let mut buffer = BytePacketBuffer::new();
+    buffer.write_qname(&name.to_ascii_lowercase())?;
+    let mut hash = buffer.buf[0..buffer.pos()].to_vec();
+    for _ in 0..=iterations {
+        hash.extend_from_slice(salt);
+        hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &hash).as_ref().to_vec();
+    }
+    Ok(hash)
+}
+
+// Unpadded base32 with the extended hex alphabet (RFC 4648 section 7), in
+// which NSEC3 owner names hold their hash
+fn base32hex_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut bits, mut value) = (0, 0u32);
+    for c in text.bytes() {
+        let digit = match c.to_ascii_lowercase() {
+            c @ b'0'..=b'9' => c - b'0',
+            c @ b'a'..=b'v' => c - b'a' + 10,
+            _ => return None,
+        };
+        value = (value << 5) | digit as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((value >> bits) as u8);
+            value &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// Whether `name` falls between an NSEC's owner and next name in canonical
+// order. The last NSEC of a zone wraps around to the apex.
+fn nsec_covers(nsec: &Nsec, name: &str) -> bool {
+    let (owner, name, next) = (canonical_labels(&nsec.owner), canonical_labels(name), canonical_labels(&nsec.next));
+    between(&owner, &name, &next)
+}
+
+// Whether `value` lies strictly between `start` and `end`, wrapping around
+// if `end` isn't after `start`
+fn between<T: Ord>(start: &T, value: &T, end: &T) -> bool {
+    if start < end {
+        start < value && value < end
+    } else {
+        start < value || value < end
+    }
+}
+
+// A name's labels, lowercased and from the root down, which compare in the
+// canonical order of RFC 4034 section 6.1
+fn canonical_labels(name: &str) -> Vec<Vec<u8>> {
+    labels(name).iter().rev().map(|label| label.to_ascii_lowercase().into_bytes()).collect()
+}
+
+fn labels(name: &str) -> Vec<&str> {
+    name.split('.').filter(|label| !label.is_empty()).collect()
+}
+
+// The longest name both `a` and `b` are at or below
+fn common_ancestor(a: &str, b: &str) -> String {
+    let (a, b) = (labels(a), labels(b));
+    let common = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x.eq_ignore_ascii_case(y)).count();
+    a[a.len() - common..].join(".")
+}
+
+fn wildcard_of(name: &str) -> String {
+    if name.is_empty() { "*".to_string() } else { format!("*.{}", name) }
+}
+
+// The current RRSIGs among `records` covering `name`/`rtype` made by `signer`
+fn signatures<'a>(records: &'a [DNSRecord], name: &'a str, rtype: QRType, signer: &'a str) -> impl Iterator<Item = &'a DNSRRSIGRecord> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs() as u32).unwrap_or_default();
+    records
+        .iter()
+        .filter_map(|record| match record {
+            DNSRecord::RRSIG(rrsig) => Some(rrsig),
+            _ => None,
+        })
+        .filter(move |rrsig| {
+            rrsig.type_covered == rtype
+                && same_name(&rrsig.preamble.name, name)
+                && same_name(&rrsig.signer_name, signer)
+                && rrsig_is_current(rrsig, now)
+        })
+}
+
+// Algorithms and digest types `verify_rrsig` and `ds_matches_key` handle
+fn is_supported(algorithm: u8, digest_type: u8) -> bool {
+    matches!(algorithm, 8 | 13) && matches!(digest_type, 1 | 2 | 4)
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+// Whether `name` is `zone` or below it; the root is the empty name
+fn in_zone(name: &str, zone: &str) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+fn bogus(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("DNSSEC validation failed: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::records::DNSUNKNOWNRecord;
+
+    const SALT: [u8; 4] = [0xaa, 0xbb, 0xcc, 0xdd];
+
+    // An NSEC3 record of "example" with the salt and iterations of RFC 5155
+    // appendix A, whose owner's first label is `label`
+    fn nsec3(label: &str, flags: u8, next_hash: &[u8], types: &[QRType]) -> DNSRecord {
+        let mut rdata = vec![1, flags, 0, 12, SALT.len() as u8];
+        rdata.extend_from_slice(&SALT);
+        rdata.push(next_hash.len() as u8);
+        rdata.extend_from_slice(next_hash);
+        if !types.is_empty() {
+            let mut bitmap = [0u8; 32];
+            for code in types.iter().map(QRType::to_u16) {
+                bitmap[code as usize / 8] |= 0x80 >> (code % 8);
+            }
+            let len = bitmap.iter().rposition(|byte| *byte != 0).unwrap() + 1;
+            rdata.extend_from_slice(&[0, len as u8]);
+            rdata.extend_from_slice(&bitmap[0..len]);
+        }
+        DNSRecord::UNKNOWN(DNSUNKNOWNRecord::new(format!("{}.example", label), NSEC3, QRClass::IN, 3600, rdata))
+    }
+
+    #[test]
+    fn nsec3_hash_matches_rfc_5155() {
+        let hash = nsec3_hash("example", &SALT, 12).unwrap();
+        assert_eq!(Some(hash), base32hex_decode("0p9mhaveqvm6t7vbl5lop2u3t2rp3tom"));
+        let hash = nsec3_hash("A.Example.", &SALT, 12).unwrap();
+        assert_eq!(Some(hash), base32hex_decode("35MTHGPGCU1QG68FAB165KLNSNK3DPVL"));
+    }
+
+    #[test]
+    fn opt_out_nsec3_span_proves_an_unsigned_delegation() {
+        // The apex exists, and the span covering the delegation's hash
+        // holds unsigned delegations only if the opt-out flag is set
+        let apex = nsec3("0p9mhaveqvm6t7vbl5lop2u3t2rp3tom", 0, &[0x07; 20], &[QRType::NS, QRType::SOA]);
+        let span = |flags| nsec3(&"0".repeat(32), flags, &[0xff; 20], &[]);
+
+        assert!(proves_unsigned_delegation(&[apex.clone(), span(NSEC3_OPT_OUT)], "b.example", "example"));
+        assert!(!proves_unsigned_delegation(&[apex.clone(), span(0)], "b.example", "example"));
+        // Without the closest encloser there's no knowing the span is the right one
+        assert!(!proves_unsigned_delegation(&[span(NSEC3_OPT_OUT)], "b.example", "example"));
+        // Records of another zone prove nothing about this one
+        assert!(!proves_unsigned_delegation(&[apex, span(NSEC3_OPT_OUT)], "b.example", "other"));
+    }
+
+    #[test]
+    fn nsec3_at_a_delegation_must_list_ns_but_not_ds() {
+        let label = base32hex_encode(&nsec3_hash("b.example", &SALT, 12).unwrap());
+        let matching = |types: &[QRType]| nsec3(&label, 0, &[0xff; 20], types);
+
+        assert!(proves_unsigned_delegation(&[matching(&[QRType::NS])], "b.example", "example"));
+        assert!(!proves_unsigned_delegation(&[matching(&[QRType::NS, QRType::DS])], "b.example", "example"));
+        assert!(!proves_unsigned_delegation(&[matching(&[QRType::A])], "b.example", "example"));
+    }
+
+    fn base32hex_encode(bytes: &[u8]) -> String {
+        let digits = b"0123456789abcdefghijklmnopqrstuv";
+        let mut text = String::new();
+        let (mut bits, mut value) = (0, 0u32);
+        for byte in bytes {
+            value = (value << 8) | *byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                text.push(digits[(value >> bits) as usize & 0x1f] as char);
+            }
+        }
+        text
+    }
+}
@@ -0,0 +1,287 @@
+use crate::message::{header::{AAFlag, RCode}, records::DNSRecord, DNSPacket, QRType};
+
+/// A zone the resolver is authoritative for, held in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    pub origin: String, // The name at the apex of the zone
+    pub records: Vec<DNSRecord>, // Every record in the zone
+}
+
+impl Zone {
+    // Constructor for creating a new, empty Zone
+    pub fn new(origin: &str) -> Self {
+        Zone {
+            origin: normalize(origin),
+            records: Vec::new(),
+        }
+    }
+
+    // Method to add a record to the zone
+    pub fn add_record(&mut self, record: DNSRecord) { self.records.push(record); }
+
+    /// Whether a name is at or below the apex of this zone
+    pub fn contains(&self, qname: &str) -> bool {
+        is_subdomain(&normalize(qname), &self.origin)
+    }
+
+    /// Answer a question for a name inside this zone.
+    ///
+    /// Names below a delegation point get a referral to the child zone's name
+    /// servers. Otherwise matching records are returned, or an SOA in the
+    /// authority section with `NXDOMAIN` if the name doesn't exist and
+    /// `NOERROR` if it exists without records of the requested type. A name
+    /// with no records of its own but with descendants, an empty
+    /// non-terminal, exists and so gets that `NOERROR`/NODATA answer. Those
+    /// answers are authoritative; referrals are not, as the child zone's
+    /// servers are the authority for the name.
+    pub fn answer(&self, qname: &str, qtype: QRType) -> DNSPacket {
+        let qname = normalize(qname);
+        let mut packet = DNSPacket::new();
+
+        if let Some(cut) = self.find_delegation(&qname) {
+            packet.authority.records = self.records_at(&cut)
+                .filter(|record| matches!(record, DNSRecord::NS(_)))
+                .cloned()
+                .collect();
+            self.add_glue(&mut packet);
+            return packet;
+        }
+
+        packet.header.aa = AAFlag::Authoritative;
+        let mut at_name: Vec<DNSRecord> = self.records_at(&qname).cloned().collect();
+        if at_name.is_empty() && !self.name_exists(&qname) {
+            // A name that doesn't exist may still be covered by a wildcard
+            match self.wildcard_records(&qname) {
+                Some(records) => at_name = records,
+                None => packet.header.rcode = RCode::NXDomain,
+            }
+        }
+        packet.answer.answers = at_name
+            .into_iter()
+            .filter(|record| {
+                let rtype = record.preamble().rtype;
+                rtype == qtype || rtype == QRType::CNAME
+            })
+            .collect();
+
+        if packet.answer.answers.is_empty() {
+            packet.authority.records = self.records_at(&self.origin)
+                .filter(|record| matches!(record, DNSRecord::SOA(_)))
+                .cloned()
+                .collect();
+        }
+
+        self.add_glue(&mut packet);
+        packet
+    }
+
+    // Add the in-zone A and AAAA records of every name server mentioned in
+    // the answer or authority section, so clients can reach them without a
+    // further lookup.
+    fn add_glue(&self, packet: &mut DNSPacket) {
+        let hosts: Vec<String> = packet.answer.answers
+            .iter()
+            .chain(packet.authority.records.iter())
+            .filter_map(|record| match record {
+                DNSRecord::NS(ns_record) => Some(normalize(&ns_record.rdata)),
+                _ => None,
+            })
+            .collect();
+
+        for host in hosts.iter().filter(|host| self.contains(host)) {
+            for record in self.records_at(host) {
+                if matches!(record, DNSRecord::A(_) | DNSRecord::AAAA(_)) && !packet.additional.records.contains(record) {
+                    packet.additional.add_record(record.clone());
+                }
+            }
+        }
+    }
+
+    // Find the closest zone cut between the apex and the name, i.e. a name
+    // below the apex that owns NS records.
+    fn find_delegation(&self, qname: &str) -> Option<String> {
+        let mut name = qname;
+        while name != self.origin && is_subdomain(name, &self.origin) {
+            if self.records_at(name).any(|record| matches!(record, DNSRecord::NS(_))) {
+                return Some(name.to_string());
+            }
+            name = match name.split_once('.') {
+                Some((_, parent)) => parent,
+                None => break,
+            };
+        }
+        None
+    }
+
+    // Whether a name exists in the zone: it owns records, or has descendants
+    // that do, making it an empty non-terminal
+    fn name_exists(&self, name: &str) -> bool {
+        name == self.origin || self.records.iter().any(|record| is_subdomain(&normalize(&record.preamble().name), name))
+    }
+
+    // Synthesize records for a name that doesn't exist from the wildcard at
+    // its closest encloser, the nearest existing ancestor (RFC 4592). The
+    // records take the queried name as their owner. `None` if there is no
+    // such wildcard. Names below a zone cut never get here, so wildcards
+    // can't match across a delegation.
+    fn wildcard_records(&self, qname: &str) -> Option<Vec<DNSRecord>> {
+        let mut encloser = qname;
+        loop {
+            encloser = match encloser.split_once('.') {
+                Some((_, parent)) => parent,
+                None if !encloser.is_empty() => "",
+                None => return None,
+            };
+            if !is_subdomain(encloser, &self.origin) {
+                return None;
+            }
+            if self.name_exists(encloser) {
+                break;
+            }
+        }
+
+        let wildcard = if encloser.is_empty() { "*".to_string() } else { format!("*.{}", encloser) };
+        let records: Vec<DNSRecord> = self.records_at(&wildcard)
+            .map(|record| {
+                let mut record = record.clone();
+                record.preamble_mut().name = qname.to_string();
+                record
+            })
+            .collect();
+
+        if records.is_empty() { None } else { Some(records) }
+    }
+
+    fn records_at<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a DNSRecord> {
+        self.records
+            .iter()
+            .filter(move |record| record.preamble().name.eq_ignore_ascii_case(name))
+    }
+}
+
+// Lowercase a name and drop any trailing dot
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+// Whether `name` equals `parent` or sits below it
+fn is_subdomain(name: &str, parent: &str) -> bool {
+    parent.is_empty() || name == parent || name.ends_with(&format!(".{}", parent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use crate::message::records::{DNSAAAARecord, DNSARecord, DNSNSRecord, DNSSOARecord};
+    use crate::message::QRClass;
+
+    fn a(name: &str, address: Ipv4Addr) -> DNSRecord {
+        DNSRecord::A(DNSARecord::new(name.to_string(), QRClass::IN, 300, address))
+    }
+
+    fn addresses(packet: &DNSPacket) -> Vec<(String, Ipv4Addr)> {
+        packet.answer.answers.iter().filter_map(|record| match record {
+            DNSRecord::A(a_record) => Some((a_record.preamble.name.clone(), a_record.rdata)),
+            _ => None,
+        }).collect()
+    }
+
+    fn example_zone() -> Zone {
+        let mut zone = Zone::new("example.com.");
+        zone.add_record(DNSRecord::SOA(DNSSOARecord::new("example.com".to_string(), QRClass::IN, 3600, "ns.example.com".to_string(), "admin.example.com".to_string(), 1, 3600, 600, 86400, 300)));
+        zone.add_record(DNSRecord::A(DNSARecord::new("a.b.example.com".to_string(), QRClass::IN, 300, Ipv4Addr::new(192, 0, 2, 1))));
+        zone
+    }
+
+    #[test]
+    fn empty_non_terminal_is_nodata_and_missing_name_nxdomain() {
+        let zone = example_zone();
+
+        let answer = zone.answer("a.b.example.com", QRType::A);
+        assert_eq!(answer.header.rcode, RCode::NoError);
+        assert_eq!(answer.answer.answers.len(), 1);
+
+        // b.example.com owns nothing but a.b.example.com is below it
+        let nodata = zone.answer("b.example.com", QRType::A);
+        assert_eq!(nodata.header.rcode, RCode::NoError);
+        assert_eq!(nodata.header.aa, AAFlag::Authoritative);
+        assert!(nodata.answer.answers.is_empty());
+        assert!(matches!(nodata.authority.records.as_slice(), [DNSRecord::SOA(_)]));
+
+        let nxdomain = zone.answer("c.example.com", QRType::A);
+        assert_eq!(nxdomain.header.rcode, RCode::NXDomain);
+        assert!(nxdomain.answer.answers.is_empty());
+        assert!(matches!(nxdomain.authority.records.as_slice(), [DNSRecord::SOA(_)]));
+    }
+
+    #[test]
+    fn exact_match_wins_over_the_wildcard() {
+        let mut zone = example_zone();
+        zone.add_record(a("*.example.com", Ipv4Addr::new(192, 0, 2, 9)));
+        zone.add_record(a("www.example.com", Ipv4Addr::new(192, 0, 2, 2)));
+
+        let answer = zone.answer("www.example.com", QRType::A);
+        assert_eq!(addresses(&answer), vec![("www.example.com".to_string(), Ipv4Addr::new(192, 0, 2, 2))]);
+    }
+
+    #[test]
+    fn wildcard_answer_takes_the_queried_name_as_owner() {
+        let mut zone = example_zone();
+        zone.add_record(a("*.example.com", Ipv4Addr::new(192, 0, 2, 9)));
+
+        let answer = zone.answer("Host.Example.com.", QRType::A);
+        assert_eq!(answer.header.rcode, RCode::NoError);
+        assert_eq!(answer.header.aa, AAFlag::Authoritative);
+        assert_eq!(addresses(&answer), vec![("host.example.com".to_string(), Ipv4Addr::new(192, 0, 2, 9))]);
+
+        // The wildcard owns no records of other types
+        let nodata = zone.answer("host.example.com", QRType::MX);
+        assert_eq!(nodata.header.rcode, RCode::NoError);
+        assert!(nodata.answer.answers.is_empty());
+    }
+
+    #[test]
+    fn wildcard_only_matches_below_the_closest_encloser() {
+        let mut zone = example_zone();
+        zone.add_record(a("*.b.example.com", Ipv4Addr::new(192, 0, 2, 9)));
+
+        assert_eq!(addresses(&zone.answer("x.b.example.com", QRType::A)), vec![("x.b.example.com".to_string(), Ipv4Addr::new(192, 0, 2, 9))]);
+        // a.b.example.com exists, so is the closest encloser, and has no
+        // wildcard of its own
+        assert_eq!(zone.answer("x.a.b.example.com", QRType::A).header.rcode, RCode::NXDomain);
+        // Nor does the apex
+        assert_eq!(zone.answer("x.example.com", QRType::A).header.rcode, RCode::NXDomain);
+    }
+
+    #[test]
+    fn wildcard_does_not_match_below_a_delegation() {
+        let mut zone = example_zone();
+        zone.add_record(a("*.example.com", Ipv4Addr::new(192, 0, 2, 9)));
+        zone.add_record(DNSRecord::NS(DNSNSRecord::new("sub.example.com".to_string(), QRClass::IN, 3600, "ns.other.test".to_string())));
+
+        let referral = zone.answer("host.sub.example.com", QRType::A);
+        assert!(referral.answer.answers.is_empty());
+        assert_eq!(referral.header.aa, AAFlag::NonAuthoritative);
+        assert!(matches!(referral.authority.records.as_slice(), [DNSRecord::NS(_)]));
+    }
+
+    #[test]
+    fn referral_carries_in_zone_glue_only() {
+        let mut zone = example_zone();
+        for host in ["ns1.sub.example.com", "ns.other.test"] {
+            zone.add_record(DNSRecord::NS(DNSNSRecord::new("sub.example.com".to_string(), QRClass::IN, 3600, host.to_string())));
+        }
+        zone.add_record(a("ns1.sub.example.com", Ipv4Addr::new(192, 0, 2, 53)));
+        zone.add_record(DNSRecord::AAAA(DNSAAAARecord::new("ns1.sub.example.com".to_string(), QRClass::IN, 300, Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53))));
+        // An address for a name outside the zone is not ours to vouch for
+        zone.add_record(a("ns.other.test", Ipv4Addr::new(203, 0, 113, 1)));
+
+        let referral = zone.answer("www.sub.example.com", QRType::A);
+        assert_eq!(referral.authority.records.len(), 2);
+        assert_eq!(referral.additional.records, vec![
+            a("ns1.sub.example.com", Ipv4Addr::new(192, 0, 2, 53)),
+            DNSRecord::AAAA(DNSAAAARecord::new("ns1.sub.example.com".to_string(), QRClass::IN, 300, Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53))),
+        ]);
+    }
+}